@@ -2,6 +2,7 @@ use crate::aabb::*;
 use crate::hit::*;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::rt_weekend::*;
 use crate::vec3::Vec3;
 use std::sync::Arc;
 pub struct XYRectangle {
@@ -11,9 +12,20 @@ pub struct XYRectangle {
     y1: f64,
     k: f64,
     material: Arc<dyn Material>,
+    /// the emitted power, cached for the light distribution
+    power: f64,
 }
 impl XYRectangle {
     pub fn new(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, material: Arc<dyn Material>) -> Self {
+        let area = (x1 - x0) * (y1 - y0);
+        let power = surface_power(&material, area, |s, t| {
+            (
+                s,
+                t,
+                Vec3::new(x0 + s * (x1 - x0), y0 + t * (y1 - y0), k),
+                Vec3::new(0., 0., 1.),
+            )
+        });
         Self {
             x0,
             x1,
@@ -21,6 +33,7 @@ impl XYRectangle {
             y1,
             k,
             material,
+            power,
         }
     }
 }
@@ -37,10 +50,13 @@ impl Hittable for XYRectangle {
         }
         rec.u = (x - self.x0) / (self.x1 - self.x0);
         rec.v = (y - self.y0) / (self.y1 - self.y0);
+        rec.dpdu = Vec3::new(self.x1 - self.x0, 0., 0.);
+        rec.dpdv = Vec3::new(0., self.y1 - self.y0, 0.);
         rec.t = t;
         let outward_normal = Vec3::new(0., 0., 1.);
         rec.set_face_normal(ray, outward_normal);
         rec.material = self.material.clone();
+        rec.sampled_light = self.power > 0.;
         rec.p = ray.at(t);
         true
     }
@@ -53,6 +69,27 @@ impl Hittable for XYRectangle {
         );
         true
     }
+    fn pdf_value(&self, origin: &Vec3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new(self.material.clone());
+        if !self.hit(&Ray::new(*origin, *v, 0.), 0.001, f64::INFINITY, &mut rec) {
+            return 0.;
+        }
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        let distance_squared = rec.t * rec.t * v.squared_length();
+        let cosine = (*v * rec.normal).abs() / v.length();
+        distance_squared / (cosine * area)
+    }
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        let random_point = Vec3::new(
+            random_double_in_range(self.x0, self.x1),
+            random_double_in_range(self.y0, self.y1),
+            self.k,
+        );
+        random_point - *origin
+    }
+    fn emitted_power(&self) -> f64 {
+        self.power
+    }
 }
 pub struct XZRectangle {
     x0: f64,
@@ -61,9 +98,20 @@ pub struct XZRectangle {
     z1: f64,
     k: f64,
     material: Arc<dyn Material>,
+    /// the emitted power, cached for the light distribution
+    power: f64,
 }
 impl XZRectangle {
     pub fn new(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, material: Arc<dyn Material>) -> Self {
+        let area = (x1 - x0) * (z1 - z0);
+        let power = surface_power(&material, area, |s, t| {
+            (
+                s,
+                t,
+                Vec3::new(x0 + s * (x1 - x0), k, z0 + t * (z1 - z0)),
+                Vec3::new(0., 1., 0.),
+            )
+        });
         Self {
             x0,
             x1,
@@ -71,6 +119,7 @@ impl XZRectangle {
             z1,
             k,
             material,
+            power,
         }
     }
 }
@@ -87,10 +136,13 @@ impl Hittable for XZRectangle {
         }
         rec.u = (x - self.x0) / (self.x1 - self.x0);
         rec.v = (z - self.z0) / (self.z1 - self.z0);
+        rec.dpdu = Vec3::new(self.x1 - self.x0, 0., 0.);
+        rec.dpdv = Vec3::new(0., 0., self.z1 - self.z0);
         rec.t = t;
         let outward_normal = Vec3::new(0., 1., 0.);
         rec.set_face_normal(ray, outward_normal);
         rec.material = self.material.clone();
+        rec.sampled_light = self.power > 0.;
         rec.p = ray.at(t);
         true
     }
@@ -103,6 +155,27 @@ impl Hittable for XZRectangle {
         );
         true
     }
+    fn pdf_value(&self, origin: &Vec3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new(self.material.clone());
+        if !self.hit(&Ray::new(*origin, *v, 0.), 0.001, f64::INFINITY, &mut rec) {
+            return 0.;
+        }
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+        let distance_squared = rec.t * rec.t * v.squared_length();
+        let cosine = (*v * rec.normal).abs() / v.length();
+        distance_squared / (cosine * area)
+    }
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        let random_point = Vec3::new(
+            random_double_in_range(self.x0, self.x1),
+            self.k,
+            random_double_in_range(self.z0, self.z1),
+        );
+        random_point - *origin
+    }
+    fn emitted_power(&self) -> f64 {
+        self.power
+    }
 }
 pub struct YZRectangle {
     y0: f64,
@@ -111,9 +184,20 @@ pub struct YZRectangle {
     z1: f64,
     k: f64,
     material: Arc<dyn Material>,
+    /// the emitted power, cached for the light distribution
+    power: f64,
 }
 impl YZRectangle {
     pub fn new(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, material: Arc<dyn Material>) -> Self {
+        let area = (y1 - y0) * (z1 - z0);
+        let power = surface_power(&material, area, |s, t| {
+            (
+                s,
+                t,
+                Vec3::new(k, y0 + s * (y1 - y0), z0 + t * (z1 - z0)),
+                Vec3::new(1., 0., 0.),
+            )
+        });
         Self {
             y0,
             y1,
@@ -121,6 +205,7 @@ impl YZRectangle {
             z1,
             k,
             material,
+            power,
         }
     }
 }
@@ -137,10 +222,13 @@ impl Hittable for YZRectangle {
         }
         rec.u = (y - self.y0) / (self.y1 - self.y0);
         rec.v = (z - self.z0) / (self.z1 - self.z0);
+        rec.dpdu = Vec3::new(0., self.y1 - self.y0, 0.);
+        rec.dpdv = Vec3::new(0., 0., self.z1 - self.z0);
         rec.t = t;
         let outward_normal = Vec3::new(1., 0., 0.);
         rec.set_face_normal(ray, outward_normal);
         rec.material = self.material.clone();
+        rec.sampled_light = self.power > 0.;
         rec.p = ray.at(t);
         true
     }
@@ -153,4 +241,25 @@ impl Hittable for YZRectangle {
         );
         true
     }
+    fn pdf_value(&self, origin: &Vec3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new(self.material.clone());
+        if !self.hit(&Ray::new(*origin, *v, 0.), 0.001, f64::INFINITY, &mut rec) {
+            return 0.;
+        }
+        let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+        let distance_squared = rec.t * rec.t * v.squared_length();
+        let cosine = (*v * rec.normal).abs() / v.length();
+        distance_squared / (cosine * area)
+    }
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        let random_point = Vec3::new(
+            self.k,
+            random_double_in_range(self.y0, self.y1),
+            random_double_in_range(self.z0, self.z1),
+        );
+        random_point - *origin
+    }
+    fn emitted_power(&self) -> f64 {
+        self.power
+    }
 }
@@ -0,0 +1,175 @@
+use crate::hit::*;
+use crate::rt_weekend::*;
+use crate::{aabb::AABB, texture::Texture, Ray, Vec3};
+use std::sync::Arc;
+
+/// How the opacity of an `AlphaMask` decides whether a hit counts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaTest {
+    /// Hits where the opacity is below the threshold are cut out, which gives hard edges.
+    Threshold(f64),
+    /// Hits count with the probability of the opacity,
+    /// so half transparent parts let half of the rays through and edges come out smooth.
+    Stochastic,
+}
+/// An object with holes cut out of it where its opacity texture is transparent,
+/// like leaves, fences and decals on rectangular cards.
+///
+/// The opacity is the alpha of the texture at the U,V coordinates of the hit.
+/// A ray that hits a transparent part goes on through it to whatever is behind,
+/// and shadow rays do the same.
+pub struct AlphaMask {
+    object: Arc<dyn Hittable>,
+    opacity: Arc<dyn Texture>,
+    test: AlphaTest,
+}
+impl AlphaMask {
+    pub fn new(object: Arc<dyn Hittable>, opacity: Arc<dyn Texture>) -> Self {
+        Self {
+            object,
+            opacity,
+            test: AlphaTest::Threshold(0.5),
+        }
+    }
+    pub fn with_alpha_test(mut self, test: AlphaTest) -> Self {
+        self.test = test;
+        self
+    }
+}
+impl Hittable for AlphaMask {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        /// Guard against objects that never stop being hit.
+        const MAX_LAYERS: usize = 64;
+        // The transparent layers must not end up in `rec`, which aggregates keep for their next child.
+        let mut layer = rec.clone();
+        let mut t = t_min;
+        for _ in 0..MAX_LAYERS {
+            if !self.object.hit(ray, t, t_max, &mut layer) {
+                return false;
+            }
+            let alpha = self.opacity.alpha(layer.u, layer.v, &layer.p);
+            let opaque = match self.test {
+                AlphaTest::Threshold(threshold) => alpha >= threshold,
+                AlphaTest::Stochastic => random_double() < alpha,
+            };
+            if opaque {
+                *rec = layer;
+                return true;
+            }
+            t = layer.t + 0.0001;
+        }
+        false
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.object.bounding_box(time0, time1, output_box)
+    }
+    /// Light sampling ignores the holes, whose samples then reach whatever is behind them.
+    fn pdf_value(&self, origin: &Vec3, v: &Vec3) -> f64 {
+        self.object.pdf_value(origin, v)
+    }
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        self.object.random(origin)
+    }
+    fn emitted_power(&self) -> f64 {
+        self.object.emitted_power()
+    }
+    /// The emitters inside keep the holes of the object.
+    fn collect_emitters(&self, emitters: &mut Vec<Arc<dyn Hittable>>) {
+        let mut inner = Vec::new();
+        self.object.collect_emitters(&mut inner);
+        for emitter in inner {
+            emitters.push(Arc::new(
+                AlphaMask::new(emitter, self.opacity.clone()).with_alpha_test(self.test),
+            ));
+        }
+    }
+    fn segment(&self, ray: &Ray, t_min: f64, t_max: f64) -> Segment {
+        self.object.segment(ray, t_min, t_max)
+    }
+    fn has_segments(&self) -> bool {
+        self.object.has_segments()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XYRectangle;
+    use crate::bvh::BVHNode;
+    use crate::light::LightList;
+    use crate::material::{DiffuseLight, Lambertian};
+    /// Opaque on the left half of the U,V square, transparent on the right.
+    struct LeftHalf;
+    impl Texture for LeftHalf {
+        fn value(&self, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+            Vec3::ones()
+        }
+        fn alpha(&self, u: f64, _v: f64, _p: &Vec3) -> f64 {
+            if u < 0.5 {
+                1.
+            } else {
+                0.
+            }
+        }
+    }
+    /// A ray through the cut out half of a card goes on to the card behind it.
+    #[test]
+    fn test_cutout() {
+        let material = Arc::new(Lambertian::new(Vec3::ones()));
+        let mut cards = HitList::new();
+        for k in [0., -1.] {
+            let card = XYRectangle::new(-1., 1., -1., 1., k, material.clone());
+            cards.add(Arc::new(AlphaMask::new(Arc::new(card), Arc::new(LeftHalf))));
+        }
+        let direction = Vec3::new(0., 0., -1.);
+        let mut rec = HitRecord::default();
+        let left = Ray::new(Vec3::new(-0.5, 0., 5.), direction, 0.);
+        assert!(cards.hit(&left, 0.001, f64::INFINITY, &mut rec));
+        assert!((rec.t - 5.).abs() < 1e-9);
+        let right = Ray::new(Vec3::new(0.5, 0., 5.), direction, 0.);
+        assert!(!cards.hit(&right, 0.001, f64::INFINITY, &mut rec));
+        assert_eq!(
+            cards.transmittance(&right, 0.001, f64::INFINITY),
+            Vec3::ones()
+        );
+        assert_eq!(
+            cards.transmittance(&left, 0.001, f64::INFINITY),
+            Vec3::zero()
+        );
+    }
+    /// A BVH finds the wall behind the cut out half of a card, whichever child the card is.
+    #[test]
+    fn test_cutout_in_bvh() {
+        let material = Arc::new(Lambertian::new(Vec3::ones()));
+        let card = XYRectangle::new(-1., 1., -1., 1., 0., material.clone());
+        let card: Arc<dyn Hittable> = Arc::new(AlphaMask::new(Arc::new(card), Arc::new(LeftHalf)));
+        let wall: Arc<dyn Hittable> = Arc::new(XYRectangle::new(-2., 2., -2., 2., -5., material));
+        let ray = Ray::new(Vec3::new(0.5, 0., 5.), Vec3::new(0., 0., -1.), 0.);
+        for objects in [vec![card.clone(), wall.clone()], vec![wall, card]] {
+            let bvh = BVHNode::new(objects, 0., 1.);
+            let mut rec = HitRecord::default();
+            assert!(bvh.hit(&ray, 0.001, f64::INFINITY, &mut rec));
+            assert!((rec.t - 10.).abs() < 1e-9);
+        }
+    }
+    /// Masked lights are still sampled as lights, in a list of their own or not.
+    #[test]
+    fn test_masked_emitters() {
+        let light = Arc::new(DiffuseLight::new(Vec3::ones()));
+        let card = Arc::new(XYRectangle::new(-1., 1., -1., 1., 0., light));
+        let masked = AlphaMask::new(card.clone(), Arc::new(LeftHalf));
+        assert_eq!(masked.emitted_power(), card.emitted_power());
+        let origin = Vec3::new(0., 0., 2.);
+        let v = masked.random(&origin);
+        assert_eq!(masked.pdf_value(&origin, &v), card.pdf_value(&origin, &v));
+        let mut cards = HitList::new();
+        cards.add(card);
+        let mut lights = LightList::new();
+        lights.add_emitters(&AlphaMask::new(Arc::new(cards), Arc::new(LeftHalf)));
+        assert_eq!(lights.len(), 1);
+        // Only the samples on the opaque half light anything.
+        let lit = (0..1000)
+            .filter(|_| !lights.sample(&origin, 0.).is_empty())
+            .count();
+        assert!(lit > 400 && lit < 600, "{}", lit);
+    }
+}
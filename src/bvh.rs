@@ -1,7 +1,7 @@
 use crate::aabb::*;
 use crate::hit::*;
 use crate::rt_weekend::*;
-use crate::Ray;
+use crate::{Ray, Vec3};
 use std::cmp::Ordering;
 use std::sync::Arc;
 use std::vec::Vec;
@@ -11,6 +11,9 @@ pub struct BVHNode {
     pub bbox: AABB,
     pub left: Arc<dyn Hittable>,
     pub right: Arc<dyn Hittable>,
+    /// whether any object below has media with segments,
+    /// so that the many nodes without any are never visited for them
+    has_segments: bool,
 }
 impl BVHNode {
     pub fn new(mut objects: Vec<Arc<dyn Hittable>>, time0: f64, time1: f64) -> Self {
@@ -58,7 +61,13 @@ impl BVHNode {
         }
         // Calculate the bounding box of the left and right child nodes.
         let bbox = surrounding_box(box_left, box_right);
-        Self { bbox, left, right }
+        let has_segments = left.has_segments() || right.has_segments();
+        Self {
+            bbox,
+            left,
+            right,
+            has_segments,
+        }
     }
 }
 impl Hittable for BVHNode {
@@ -79,6 +88,36 @@ impl Hittable for BVHNode {
 
         hit_left || hit_right
     }
+    fn collect_emitters(&self, emitters: &mut Vec<Arc<dyn Hittable>>) {
+        collect_emitters_of(&self.left, emitters);
+        // A node with a single object holds it as both children.
+        if !Arc::ptr_eq(&self.left, &self.right) {
+            collect_emitters_of(&self.right, emitters);
+        }
+    }
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec3 {
+        if !self.bbox.hit(ray, t_min, t_max) {
+            return Vec3::ones();
+        }
+        let left = self.left.transmittance(ray, t_min, t_max);
+        if left == Vec3::zero() || Arc::ptr_eq(&self.left, &self.right) {
+            return left;
+        }
+        Vec3::elemul(left, self.right.transmittance(ray, t_min, t_max))
+    }
+    fn segment(&self, ray: &Ray, t_min: f64, t_max: f64) -> Segment {
+        if !self.has_segments || !self.bbox.hit(ray, t_min, t_max) {
+            return Segment::default();
+        }
+        let left = self.left.segment(ray, t_min, t_max);
+        if Arc::ptr_eq(&self.left, &self.right) {
+            return left;
+        }
+        left.merge(self.right.segment(ray, t_min, t_max))
+    }
+    fn has_segments(&self) -> bool {
+        self.has_segments
+    }
 }
 pub fn box_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>, axis: i32) -> Ordering {
     let mut box_a: AABB = Default::default();
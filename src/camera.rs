@@ -1,4 +1,4 @@
-use crate::ray::Ray;
+use crate::ray::{Ray, RayDifferential};
 use crate::rt_weekend::*;
 use crate::vec3::Vec3;
 /// Camera decides the direction of the ray according to the pixel's position.
@@ -13,6 +13,8 @@ pub struct Camera {
     pub lens_radius: f64,
     pub time0: f64,
     pub time1: f64,
+    /// the spacing of the ray differentials in `s` and `t`, if the rays carry them
+    pub pixel_step: Option<(f64, f64)>,
 }
 impl Camera {
     /// The constructor of the Camera.
@@ -53,15 +55,42 @@ impl Camera {
             lens_radius: aperture / 2.,
             time0: _time0,
             time1: _time1,
+            pixel_step: None,
         }
     }
+    /// Give the rays differentials to the neighbouring pixels of an image of `width` by `height`,
+    /// so that textures can be filtered over what a pixel sees.
+    /// They are scaled down by the square root of `samples_per_pixel`,
+    /// since the samples themselves average over the pixel.
+    pub fn with_ray_differentials(
+        mut self,
+        width: usize,
+        height: usize,
+        samples_per_pixel: usize,
+    ) -> Self {
+        let scale = 1. / (samples_per_pixel.max(1) as f64).sqrt();
+        self.pixel_step = Some((scale / width as f64, scale / height as f64));
+        self
+    }
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
         let rd = Vec3::random_in_unit_disk() * self.lens_radius;
         let offset = self.u * rd.x() + self.v * rd.y();
+        let origin = self.origin + offset;
+        let direction = |s: f64, t: f64| {
+            self.lower_left_corner + self.horizontal * s + self.vertical * t - origin
+        };
+        // The offset rays go through the same point of the lens, to the neighbouring points in focus.
+        let differential = self.pixel_step.map(|(ds, dt)| RayDifferential {
+            rx_origin: origin,
+            rx_direction: direction(s + ds, t),
+            ry_origin: origin,
+            ry_direction: direction(s, t + dt),
+        });
         Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset,
+            origin,
+            direction(s, t),
             random_double_in_range(self.time0, self.time1),
         )
+        .with_differential(differential)
     }
 }
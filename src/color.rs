@@ -10,6 +10,10 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
         x
     }
 }
+/// The luminance of a linear RGB color, with the Rec. 709 weights.
+pub fn luminance(color: Vec3) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
 /// the multi-sample write_color() function
 pub fn write_color(
     pixel_color: Vec3,
@@ -1,14 +1,12 @@
 use crate::hit::*;
 use crate::rt_weekend::*;
-use crate::{aabb::AABB, material::*, texture::Texture, Ray, Vec3};
+use crate::{aabb::AABB, material::*, phase::PhaseFunction, texture::Texture, Ray, Vec3};
 use std::f64::consts::E;
-use std::f64::INFINITY;
-use std::f64::NEG_INFINITY;
 use std::sync::Arc;
 
 pub struct ConstantMedium {
     boundary: Arc<dyn Hittable>,
-    phase_function: Arc<dyn Material>,
+    phase_function: Arc<Isotropic>,
     neg_inv_density: f64,
 }
 impl ConstantMedium {
@@ -31,6 +29,16 @@ impl ConstantMedium {
             neg_inv_density: -1. / density,
         }
     }
+    /// Scatter by `phase` instead of isotropically.
+    pub fn with_phase_function(mut self, phase: PhaseFunction) -> Self {
+        self.phase_function = Arc::new(
+            self.phase_function
+                .as_ref()
+                .clone()
+                .with_phase_function(phase),
+        );
+        self
+    }
 }
 impl Hittable for ConstantMedium {
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
@@ -43,65 +51,82 @@ impl Hittable for ConstantMedium {
     /// where C is proportional to the optical density of the volume.
     /// If you go through all the differential equations,
     /// for a random number you get a distance where the scattering occurs.
-    /// This implement assumes that once a ray exits the constant medium boundary,
-    /// it will continue forever outside the boundary
-    /// So this particular implementation will work for boundaries like boxes or spheres,
-    /// but will not work with toruses or shapes that contain voids
+    /// That distance is walked along the parts of the ray inside the boundary,
+    /// so the ray may leave and re-enter boundaries like a torus or overlapping spheres.
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        // Print occasional samples when debugging. To enable, set enableDebug true.
-        const ENABLE_DEBUG: bool = false;
-        let debugging: bool = ENABLE_DEBUG && random_double() < 0.00001;
-
-        let mut rec1: HitRecord = Default::default();
-        let mut rec2: HitRecord = Default::default();
-
-        if !self.boundary.hit(ray, NEG_INFINITY, INFINITY, &mut rec1) {
-            return false;
-        }
-        if !self.boundary.hit(ray, rec1.t + 0.0001, INFINITY, &mut rec2) {
-            return false;
-        }
-        if debugging {
-            println!("\nt_min={}, t_max={}", rec1.t, rec2.t);
-        }
-        if rec1.t < t_min {
-            rec1.t = t_min
-        };
-        if rec2.t > t_max {
-            rec2.t = t_max
-        };
-
-        if rec1.t >= rec2.t {
-            return false;
-        };
-
-        if rec1.t < 0. {
-            rec1.t = 0.;
+        let ray_length: f64 = ray.direction().length();
+        let mut hit_distance: f64 = self.neg_inv_density * random_double().log(E);
+        for (t0, t1) in inside_intervals(self.boundary.as_ref(), ray, t_min, t_max) {
+            let distance_inside_boundary: f64 = (t1 - t0) * ray_length;
+            // If that distance is outside this part of the volume, go on to the next one.
+            if hit_distance > distance_inside_boundary {
+                hit_distance -= distance_inside_boundary;
+                continue;
+            }
+            rec.t = t0 + hit_distance / ray_length;
+            rec.p = ray.at(rec.t);
+            rec.normal = Vec3::new(1., 0., 0.); // arbitrary
+            rec.front_face = true; // also arbitrary
+            rec.sampled_light = false;
+            rec.material = self.phase_function.clone();
+            return true;
         }
-
+        false
+    }
+    /// The transmittance of a homogeneous medium is known exactly, by the Beer-Lambert law.
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec3 {
         let ray_length: f64 = ray.direction().length();
-        let distance_inside_boundary: f64 = (rec2.t - rec1.t) * ray_length;
-
-        let hit_distance: f64 = self.neg_inv_density * random_double().log(E);
-        // If that distance is outside the volume, then there is no "hit"
-        if hit_distance > distance_inside_boundary {
-            return false;
+        let distance: f64 = inside_intervals(self.boundary.as_ref(), ray, t_min, t_max)
+            .iter()
+            .map(|(t0, t1)| (t1 - t0) * ray_length)
+            .sum();
+        Vec3::ones() * (distance / self.neg_inv_density).exp()
+    }
+}
+/// The parts `(t0, t1)` of the ray between `t_min` and `t_max` that lie inside the closed `boundary`.
+///
+/// Every crossing of the boundary along the whole line is found,
+/// and the ray is inside wherever it has entered more often than it has left.
+/// This way the boundary may be concave, or made of several overlapping pieces.
+pub fn inside_intervals(
+    boundary: &dyn Hittable,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Vec<(f64, f64)> {
+    /// Guard against boundaries that never stop being hit.
+    const MAX_CROSSINGS: usize = 64;
+    let mut intervals = Vec::new();
+    let mut depth = 0;
+    let mut entered = f64::NEG_INFINITY;
+    let mut t = f64::NEG_INFINITY;
+    let mut rec: HitRecord = Default::default();
+    for _ in 0..MAX_CROSSINGS {
+        // Without a next crossing, the ray can only be inside by a numerical accident.
+        if !boundary.hit(ray, t, f64::INFINITY, &mut rec) {
+            break;
         }
-
-        rec.t = rec1.t + hit_distance / ray_length;
-        rec.p = ray.at(rec.t);
-
-        if debugging {
-            println!(
-                "hit_distance = {}\nrec.t = {}\nrec.p = {:?}",
-                hit_distance, rec.t, rec.p
-            );
+        if rec.t >= t_max {
+            if depth > 0 && entered < t_max {
+                intervals.push((entered.max(t_min), t_max));
+            }
+            break;
         }
-
-        rec.normal = Vec3::new(1., 0., 0.); // arbitrary
-        rec.front_face = true; // also arbitrary
-        rec.material = self.phase_function.clone();
-
-        true
+        if rec.front_face {
+            if depth == 0 {
+                entered = rec.t;
+            }
+            depth += 1;
+        } else if depth > 0 {
+            depth -= 1;
+            if depth == 0 {
+                let (t0, t1) = (entered.max(t_min), rec.t.min(t_max));
+                if t0 < t1 {
+                    intervals.push((t0, t1));
+                }
+            }
+        }
+        t = rec.t + 0.0001;
     }
+    intervals
 }
@@ -71,10 +71,31 @@ impl CornellBox {
 }
 impl Hittable for CornellBox {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        self.sides.hit(ray, t_min, t_max, rec)
+        if !self.sides.hit(ray, t_min, t_max, rec) {
+            return false;
+        }
+        // The sides are rectangles whose normals all point along their axis,
+        // so find out which side was hit to make the normal point out of the box.
+        let faces = [
+            (rec.p.x() - self.box_min.x(), Vec3::new(-1., 0., 0.)),
+            (self.box_max.x() - rec.p.x(), Vec3::new(1., 0., 0.)),
+            (rec.p.y() - self.box_min.y(), Vec3::new(0., -1., 0.)),
+            (self.box_max.y() - rec.p.y(), Vec3::new(0., 1., 0.)),
+            (rec.p.z() - self.box_min.z(), Vec3::new(0., 0., -1.)),
+            (self.box_max.z() - rec.p.z(), Vec3::new(0., 0., 1.)),
+        ];
+        let (_, outward_normal) = faces
+            .into_iter()
+            .min_by(|a, b| a.0.abs().total_cmp(&b.0.abs()))
+            .unwrap();
+        rec.set_face_normal(ray, outward_normal);
+        true
     }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
         *output_box = AABB::new(self.box_min, self.box_max);
         true
     }
+    fn collect_emitters(&self, emitters: &mut Vec<Arc<dyn Hittable>>) {
+        self.sides.collect_emitters(emitters);
+    }
 }
@@ -0,0 +1,224 @@
+use crate::constant_medium::inside_intervals;
+use crate::hit::*;
+use crate::rt_weekend::*;
+use crate::{aabb::AABB, material::*, phase::PhaseFunction, texture::*, Ray, Vec3};
+use std::sync::Arc;
+
+/// How a `HeterogeneousMedium` estimates the light that gets through it along a shadow ray.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tracking {
+    /// Either all or nothing gets through, depending on whether delta tracking finds a collision.
+    Delta,
+    /// The transmittance is the product of the null collision probabilities along the ray,
+    /// which is a fraction and so has much less noise.
+    Ratio,
+}
+/// A participating medium whose density varies in space, like clouds or uneven smoke.
+///
+/// The density at a point is `max_density` times the red channel of the `density` texture,
+/// clamped to [0,1], which is looked up at the point itself.
+/// Free flights are sampled with delta tracking (Woodcock tracking):
+/// the medium is filled up with fictitious particles to the constant `max_density`,
+/// tentative collisions are sampled against that, and each one is real
+/// with the probability of the actual density over the maximum.
+pub struct HeterogeneousMedium {
+    boundary: Arc<dyn Hittable>,
+    density: Arc<dyn Texture>,
+    max_density: f64,
+    phase_function: Arc<Isotropic>,
+    tracking: Tracking,
+}
+impl HeterogeneousMedium {
+    pub fn new(
+        boundary: Arc<dyn Hittable>,
+        density: Arc<dyn Texture>,
+        max_density: f64,
+        color: Vec3,
+    ) -> Self {
+        Self {
+            boundary,
+            density,
+            max_density,
+            phase_function: Arc::new(Isotropic::new(color)),
+            tracking: Tracking::Ratio,
+        }
+    }
+    pub fn with_tracking(mut self, tracking: Tracking) -> Self {
+        self.tracking = tracking;
+        self
+    }
+    /// Scatter by `phase` instead of isotropically.
+    pub fn with_phase_function(mut self, phase: PhaseFunction) -> Self {
+        self.phase_function = Arc::new(
+            self.phase_function
+                .as_ref()
+                .clone()
+                .with_phase_function(phase),
+        );
+        self
+    }
+    /// The density at `p` over the maximum density.
+    fn density_ratio(&self, p: &Vec3) -> f64 {
+        self.density.value(0., 0., p).x().clamp(0., 1.)
+    }
+    fn track(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        collide: impl FnMut(f64) -> bool,
+    ) -> Option<f64> {
+        delta_track(
+            self.boundary.as_ref(),
+            self.max_density,
+            ray,
+            t_min,
+            t_max,
+            collide,
+        )
+    }
+}
+/// The tentative collisions of delta tracking with the constant `majorant`
+/// along the parts of the ray inside `boundary`,
+/// in increasing order of `t`, each given to `collide` until it returns true.
+/// Returns the `t` of the collision `collide` stopped at.
+pub fn delta_track(
+    boundary: &dyn Hittable,
+    majorant: f64,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    mut collide: impl FnMut(f64) -> bool,
+) -> Option<f64> {
+    if majorant <= 0. {
+        return None;
+    }
+    let ray_length = ray.direction().length();
+    for (t0, t1) in inside_intervals(boundary, ray, t_min, t_max) {
+        let mut t = t0;
+        loop {
+            t -= (1. - random_double()).ln() / (majorant * ray_length);
+            if t >= t1 {
+                break;
+            }
+            if collide(t) {
+                return Some(t);
+            }
+        }
+    }
+    None
+}
+impl Hittable for HeterogeneousMedium {
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.boundary.bounding_box(time0, time1, output_box)
+    }
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let collision = self.track(ray, t_min, t_max, |t| {
+            random_double() < self.density_ratio(&ray.at(t))
+        });
+        match collision {
+            Some(t) => {
+                rec.t = t;
+                rec.p = ray.at(t);
+                rec.normal = Vec3::new(1., 0., 0.); // arbitrary
+                rec.front_face = true; // also arbitrary
+                rec.sampled_light = false;
+                rec.material = self.phase_function.clone();
+                true
+            }
+            None => false,
+        }
+    }
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec3 {
+        match self.tracking {
+            Tracking::Delta => {
+                let mut rec = Default::default();
+                if self.hit(ray, t_min, t_max, &mut rec) {
+                    Vec3::zero()
+                } else {
+                    Vec3::ones()
+                }
+            }
+            Tracking::Ratio => {
+                let mut transmittance = 1.;
+                self.track(ray, t_min, t_max, |t| {
+                    transmittance *= 1. - self.density_ratio(&ray.at(t));
+                    // Russian roulette, once there is little light left.
+                    if transmittance < 0.1 {
+                        if random_double() < 0.5 {
+                            transmittance = 0.;
+                            return true;
+                        }
+                        transmittance *= 2.;
+                    }
+                    false
+                });
+                Vec3::ones() * transmittance
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cornell_box::CornellBox;
+    use crate::sphere::Sphere;
+    fn sphere(center: Vec3) -> Arc<dyn Hittable> {
+        let unused: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::zero()));
+        Arc::new(Sphere::new(center, 1., unused))
+    }
+    /// Two overlapping spheres make a single stretch of medium, and two apart make two.
+    #[test]
+    fn test_inside_intervals() {
+        let ray = Ray::new(Vec3::new(-5., 0., 0.), Vec3::new(1., 0., 0.), 0.);
+        let mut overlapping = HitList::new();
+        overlapping.add(sphere(Vec3::new(-0.5, 0., 0.)));
+        overlapping.add(sphere(Vec3::new(0.5, 0., 0.)));
+        let intervals = inside_intervals(&overlapping, &ray, 0.001, f64::INFINITY);
+        assert_eq!(intervals.len(), 1);
+        assert!((intervals[0].0 - 3.5).abs() < 1e-6 && (intervals[0].1 - 6.5).abs() < 1e-6);
+
+        let mut apart = HitList::new();
+        apart.add(sphere(Vec3::new(-2., 0., 0.)));
+        apart.add(sphere(Vec3::new(2., 0., 0.)));
+        let intervals = inside_intervals(&apart, &ray, 0.001, 7.);
+        assert_eq!(intervals.len(), 2);
+        assert!((intervals[1].0 - 6.).abs() < 1e-6 && (intervals[1].1 - 7.).abs() < 1e-6);
+
+        // Boxes tell entering from leaving too, even when moved and rotated.
+        let unused: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::zero()));
+        let cube = Translate::new(
+            Arc::new(RotateY::new(
+                Arc::new(CornellBox::new(-Vec3::ones(), Vec3::ones(), unused)),
+                90.,
+            )),
+            Vec3::new(0., 0., 0.5),
+        );
+        let intervals = inside_intervals(&cube, &ray, 0.001, f64::INFINITY);
+        assert_eq!(intervals.len(), 1);
+        assert!((intervals[0].0 - 4.).abs() < 1e-6 && (intervals[0].1 - 6.).abs() < 1e-6);
+    }
+    /// Both trackings agree on average with Beer-Lambert in a medium of constant density.
+    #[test]
+    fn test_transmittance() {
+        let density = Arc::new(SolidColor::new(Vec3::new(0.5, 0.5, 0.5)));
+        let ray = Ray::new(Vec3::new(0., 0., -5.), Vec3::new(0., 0., 1.), 0.);
+        let expected = (-0.5_f64 * 2.).exp();
+        for tracking in [Tracking::Delta, Tracking::Ratio] {
+            let medium =
+                HeterogeneousMedium::new(sphere(Vec3::zero()), density.clone(), 1., Vec3::ones())
+                    .with_tracking(tracking);
+            const N: usize = 20000;
+            let sum: f64 = (0..N)
+                .map(|_| medium.transmittance(&ray, 0.001, f64::INFINITY).x())
+                .sum();
+            let average = sum / N as f64;
+            assert!(
+                (average - expected).abs() < 0.02,
+                "{:?} {}",
+                tracking,
+                average
+            );
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use crate::aabb::*;
 use crate::material::*;
-use crate::ray::Ray;
+use crate::ray::{Ray, RayDifferential};
 use crate::rt_weekend::*;
+use crate::texture::TexturePoint;
 use crate::vec3::Vec3;
-use std::f64::INFINITY;
 use std::sync::Arc;
 #[derive(Clone)]
 pub struct HitRecord {
@@ -24,8 +24,23 @@ pub struct HitRecord {
     /// the U,V surface coordinates of the ray-object hit point
     pub u: f64,
     pub v: f64,
+    /// the width in U,V of the area around the hit point that the ray stands for,
+    /// which textures filter over; 0 for a single point
+    pub uv_footprint: f64,
+    /// how the hitting point moves with U and V, which spans the tangent plane of the surface;
+    /// zero where the surface has no U,V parameterization
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    /// how far the hitting points of the offset rays of the ray differential are from `p`
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
     /// whether the hit happens on the front face of the hitting surface
     pub front_face: bool,
+    /// the time of the ray that hits
+    pub time: f64,
+    /// whether the primitive that is hit is sampled as a light when it emits,
+    /// in which case direct lighting has already counted its emission
+    pub sampled_light: bool,
 }
 impl Default for HitRecord {
     fn default() -> Self {
@@ -36,7 +51,14 @@ impl Default for HitRecord {
             t: 0.,
             u: 0.,
             v: 0.,
+            uv_footprint: 0.,
+            dpdu: Vec3::zero(),
+            dpdv: Vec3::zero(),
+            dpdx: Vec3::zero(),
+            dpdy: Vec3::zero(),
             front_face: true,
+            time: 0.,
+            sampled_light: false,
         }
     }
 }
@@ -48,9 +70,31 @@ impl HitRecord {
             normal: Vec3::zero(),
             t: 0.,
             front_face: true,
+            time: 0.,
+            sampled_light: false,
             material,
             u: 0.,
             v: 0.,
+            uv_footprint: 0.,
+            dpdu: Vec3::zero(),
+            dpdv: Vec3::zero(),
+            dpdx: Vec3::zero(),
+            dpdy: Vec3::zero(),
+        }
+    }
+    /// Where textures are looked up for this hit.
+    pub fn texture_point(&self) -> TexturePoint {
+        TexturePoint {
+            u: self.u,
+            v: self.v,
+            p: self.p,
+            normal: if self.front_face {
+                self.normal
+            } else {
+                -self.normal
+            },
+            footprint: self.uv_footprint,
+            time: self.time,
         }
     }
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vec3) {
@@ -61,10 +105,174 @@ impl HitRecord {
             -outward_normal
         };
     }
+    /// Find where the offset rays of the differential of `ray` meet the tangent plane at the hit,
+    /// and from that the `uv_footprint` of the ray.
+    pub fn set_differentials(&mut self, ray: &Ray) {
+        let differential = match ray.differential() {
+            Some(differential) => differential,
+            None => return,
+        };
+        let offset = |origin: Vec3, direction: Vec3| {
+            let denominator = self.normal * direction;
+            if denominator.abs() < 1e-12 {
+                return Vec3::zero();
+            }
+            let t = self.normal * (self.p - origin) / denominator;
+            origin + direction * t - self.p
+        };
+        self.dpdx = offset(differential.rx_origin, differential.rx_direction);
+        self.dpdy = offset(differential.ry_origin, differential.ry_direction);
+        // Write the offsets in terms of the tangents by least squares,
+        // which is exact as long as they are in the tangent plane.
+        let (a, b) = (self.dpdu, self.dpdv);
+        let (aa, ab, bb) = (a * a, a * b, b * b);
+        let determinant = aa * bb - ab * ab;
+        if determinant.abs() < 1e-12 {
+            self.uv_footprint = 0.;
+            return;
+        }
+        let uv_length = |dp: Vec3| {
+            let (ad, bd) = (a * dp, b * dp);
+            let du = (bb * ad - ab * bd) / determinant;
+            let dv = (aa * bd - ab * ad) / determinant;
+            (du * du + dv * dv).sqrt()
+        };
+        self.uv_footprint = uv_length(self.dpdx).max(uv_length(self.dpdy));
+    }
+    /// The differential of a ray that `scattered` specularly off the hit, either reflected or refracted,
+    /// from the differential of the incoming ray `r_in`.
+    ///
+    /// The offset rays leave from their own hitting points, in the directions they would be sent in
+    /// by the same interface. The surface is taken as flat around the hit,
+    /// so the spreading of the rays by a curved mirror or lens is left out.
+    pub fn specular_differential(&self, r_in: &Ray, scattered: &Ray) -> Option<RayDifferential> {
+        let differential = r_in.differential()?;
+        let n = self.normal;
+        let incoming = r_in.direction().unit();
+        let outgoing = scattered.direction().unit();
+        let bend = |direction: Vec3| {
+            if outgoing * n > 0. {
+                return Vec3::reflect(&direction, &n);
+            }
+            // The ratio of the indices of refraction, from Snell's law.
+            let sin_in = (incoming - n * (incoming * n)).length();
+            let sin_out = (outgoing - n * (outgoing * n)).length();
+            let eta = if sin_in > 1e-6 { sin_out / sin_in } else { 1. };
+            Vec3::refract(&direction.unit(), &n, eta)
+        };
+        Some(RayDifferential {
+            rx_origin: self.p + self.dpdx,
+            rx_direction: bend(differential.rx_direction),
+            ry_origin: self.p + self.dpdy,
+            ry_direction: bend(differential.ry_direction),
+        })
+    }
 }
 pub trait Hittable: Sync + Send {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool;
+    /// The solid angle PDF of `random` producing the direction `v` from `origin`.
+    #[allow(unused_variables)]
+    fn pdf_value(&self, origin: &Vec3, v: &Vec3) -> f64 {
+        0.
+    }
+    /// A random direction from `origin` towards the object.
+    #[allow(unused_variables)]
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        Vec3::new(1., 0., 0.)
+    }
+    /// The total power emitted by the object.
+    /// Only primitives that implement `pdf_value` and `random` report it,
+    /// since anything with a non-zero power is sampled as a light.
+    /// Those primitives set `sampled_light` in their hits when it is positive, and all others clear it.
+    fn emitted_power(&self) -> f64 {
+        0.
+    }
+    /// Push the emissive primitives inside an aggregate into `emitters`.
+    #[allow(unused_variables)]
+    fn collect_emitters(&self, emitters: &mut Vec<Arc<dyn Hittable>>) {}
+    /// The fraction of light of each channel that gets through the object
+    /// along the ray between `t_min` and `t_max`, which is what a shadow ray sees.
+    /// Surfaces block all of it; participating media may let some through.
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec3 {
+        let mut rec = Default::default();
+        if self.hit(ray, t_min, t_max, &mut rec) {
+            Vec3::zero()
+        } else {
+            Vec3::ones()
+        }
+    }
+    /// What the media inside the object do to the light along the ray between `t_min` and `t_max`,
+    /// on top of the collisions that `hit` finds. Only media that absorb each channel differently
+    /// or emit light have anything to add.
+    #[allow(unused_variables)]
+    fn segment(&self, ray: &Ray, t_min: f64, t_max: f64) -> Segment {
+        Segment::default()
+    }
+    /// Whether `segment` can give anything but the default,
+    /// so that the objects where it can't are skipped.
+    fn has_segments(&self) -> bool {
+        false
+    }
+}
+/// The light that a stretch of ray goes through: what is left of each channel of the light
+/// coming from its end, and the light emitted along it toward its origin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Segment {
+    pub transmittance: Vec3,
+    pub emission: Vec3,
+}
+impl Default for Segment {
+    fn default() -> Self {
+        Self {
+            transmittance: Vec3::ones(),
+            emission: Vec3::zero(),
+        }
+    }
+}
+impl Segment {
+    /// Add up what two objects along the same stretch do.
+    /// The light one of them emits isn't dimmed by the other,
+    /// which only matters for media that overlap or lie one behind the other.
+    pub fn merge(self, other: Segment) -> Self {
+        Self {
+            transmittance: Vec3::elemul(self.transmittance, other.transmittance),
+            emission: self.emission + other.emission,
+        }
+    }
+    /// The light at the origin of the stretch, given the light `radiance` that reaches its end.
+    pub fn apply(&self, radiance: Vec3) -> Vec3 {
+        self.emission + Vec3::elemul(self.transmittance, radiance)
+    }
+}
+/// Push `object` itself if it emits light, or else look for emitters inside it.
+pub fn collect_emitters_of(object: &Arc<dyn Hittable>, emitters: &mut Vec<Arc<dyn Hittable>>) {
+    if object.emitted_power() > 0. {
+        emitters.push(object.clone());
+    } else {
+        object.collect_emitters(emitters);
+    }
+}
+/// The power emitted by a surface of `area` with `material`, from its power per unit area
+/// averaged over a grid of points. `point` takes (s, t) in [0,1]², spread evenly over the surface,
+/// to the U,V, position and outward normal there.
+pub fn surface_power(
+    material: &Arc<dyn Material>,
+    area: f64,
+    point: impl Fn(f64, f64) -> (f64, f64, Vec3, Vec3),
+) -> f64 {
+    const N: usize = 8;
+    let mut rec = HitRecord::new(material.clone());
+    let mut sum = 0.;
+    for i in 0..N {
+        for j in 0..N {
+            let s = (i as f64 + 0.5) / N as f64;
+            let t = (j as f64 + 0.5) / N as f64;
+            (rec.u, rec.v, rec.p, rec.normal) = point(s, t);
+            sum += material.emitted_power(&rec);
+        }
+    }
+    sum / (N * N) as f64 * area
 }
 #[derive(Clone)]
 pub struct HitList {
@@ -121,6 +329,31 @@ impl Hittable for HitList {
         }
         true
     }
+    fn collect_emitters(&self, emitters: &mut Vec<Arc<dyn Hittable>>) {
+        for object in self.list.iter() {
+            collect_emitters_of(object, emitters);
+        }
+    }
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec3 {
+        let mut transmittance = Vec3::ones();
+        for object in self.list.iter() {
+            transmittance = Vec3::elemul(transmittance, object.transmittance(ray, t_min, t_max));
+            if transmittance == Vec3::zero() {
+                break;
+            }
+        }
+        transmittance
+    }
+    fn segment(&self, ray: &Ray, t_min: f64, t_max: f64) -> Segment {
+        self.list
+            .iter()
+            .fold(Segment::default(), |segment, object| {
+                segment.merge(object.segment(ray, t_min, t_max))
+            })
+    }
+    fn has_segments(&self) -> bool {
+        self.list.iter().any(|object| object.has_segments())
+    }
 }
 pub struct Translate {
     ptr: Arc<dyn Hittable>,
@@ -130,15 +363,20 @@ impl Translate {
     pub fn new(ptr: Arc<dyn Hittable>, offset: Vec3) -> Self {
         Self { ptr, offset }
     }
+    /// Move a world space ray into the space of the wrapped object.
+    fn to_object(&self, ray: &Ray) -> Ray {
+        Ray::new(ray.origin() - self.offset, ray.direction(), ray.time())
+            .with_wavelengths(ray.wavelengths())
+    }
 }
 impl Hittable for Translate {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let moved_ray = Ray::new(ray.origin() - self.offset, ray.direction(), ray.time());
+        let moved_ray = self.to_object(ray);
         if !self.ptr.hit(&moved_ray, t_min, t_max, rec) {
             return false;
         }
+        // Moving the ray keeps its direction, so the normal and front_face stay as they are.
         rec.p += self.offset;
-        rec.set_face_normal(&moved_ray, rec.normal);
         true
     }
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
@@ -149,9 +387,37 @@ impl Hittable for Translate {
         *output_box = AABB::new(temp_box.min() + self.offset, temp_box.max() + self.offset);
         true
     }
+    fn pdf_value(&self, origin: &Vec3, v: &Vec3) -> f64 {
+        self.ptr.pdf_value(&(*origin - self.offset), v)
+    }
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        self.ptr.random(&(*origin - self.offset))
+    }
+    fn emitted_power(&self) -> f64 {
+        self.ptr.emitted_power()
+    }
+    /// The emitters inside are moved along with the object.
+    fn collect_emitters(&self, emitters: &mut Vec<Arc<dyn Hittable>>) {
+        let mut inner = Vec::new();
+        self.ptr.collect_emitters(&mut inner);
+        for emitter in inner {
+            emitters.push(Arc::new(Translate::new(emitter, self.offset)));
+        }
+    }
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec3 {
+        self.ptr.transmittance(&self.to_object(ray), t_min, t_max)
+    }
+    fn segment(&self, ray: &Ray, t_min: f64, t_max: f64) -> Segment {
+        self.ptr.segment(&self.to_object(ray), t_min, t_max)
+    }
+    fn has_segments(&self) -> bool {
+        self.ptr.has_segments()
+    }
 }
 pub struct RotateY {
     ptr: Arc<dyn Hittable>,
+    /// the rotation angle in degrees
+    angle: f64,
     sin_theta: f64,
     cos_theta: f64,
     has_box: bool,
@@ -165,8 +431,8 @@ impl RotateY {
         let mut bbox: AABB = Default::default();
         let has_box = ptr.bounding_box(0., 1., &mut bbox);
 
-        let mut min = Vec3::new(INFINITY, INFINITY, INFINITY);
-        let mut max = Vec3::new(-INFINITY, -INFINITY, -INFINITY);
+        let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
 
         for i in 0..2 {
             for j in 0..2 {
@@ -190,12 +456,38 @@ impl RotateY {
         bbox = AABB::new(min, max);
         Self {
             ptr,
+            angle,
             sin_theta,
             cos_theta,
             has_box,
             bbox,
         }
     }
+    /// Rotate a world space vector into the space of the wrapped object.
+    fn to_object(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x() - self.sin_theta * v.z(),
+            v.y(),
+            self.sin_theta * v.x() + self.cos_theta * v.z(),
+        )
+    }
+    /// Rotate a world space ray into the space of the wrapped object.
+    fn rotate_ray(&self, ray: &Ray) -> Ray {
+        Ray::new(
+            self.to_object(&ray.origin()),
+            self.to_object(&ray.direction()),
+            ray.time(),
+        )
+        .with_wavelengths(ray.wavelengths())
+    }
+    /// Rotate a vector in the space of the wrapped object back into world space.
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x() + self.sin_theta * v.z(),
+            v.y(),
+            -self.sin_theta * v.x() + self.cos_theta * v.z(),
+        )
+    }
 }
 impl Hittable for RotateY {
     fn bounding_box(&self, _time0: f64, _time11: f64, output_box: &mut AABB) -> bool {
@@ -203,20 +495,7 @@ impl Hittable for RotateY {
         self.has_box
     }
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let mut origin = ray.origin();
-        let mut direction = ray.direction();
-
-        *origin.get(0) =
-            *ray.origin().get(0) * self.cos_theta - *ray.origin().get(2) * self.sin_theta;
-        *origin.get(2) =
-            *ray.origin().get(0) * self.sin_theta + *ray.origin().get(2) * self.cos_theta;
-
-        *direction.get(0) =
-            *ray.direction().get(0) * self.cos_theta - *ray.direction().get(2) * self.sin_theta;
-        *direction.get(2) =
-            *ray.direction().get(0) * self.sin_theta + *ray.direction().get(2) * self.cos_theta;
-
-        let rotated_r = Ray::new(origin, direction, ray.time());
+        let rotated_r = self.rotate_ray(ray);
 
         if !self.ptr.hit(&rotated_r, t_min, t_max, rec) {
             return false;
@@ -230,9 +509,123 @@ impl Hittable for RotateY {
         *normal.get(0) = *rec.normal.get(0) * self.cos_theta + *rec.normal.get(2) * self.sin_theta;
         *normal.get(2) = -*rec.normal.get(0) * self.sin_theta + *rec.normal.get(2) * self.cos_theta;
 
+        // The normal already faces against the ray, and keeps doing so when both are rotated,
+        // so front_face stays as the object found it.
         rec.p = p;
-        rec.set_face_normal(&rotated_r, normal);
+        rec.normal = normal;
+        rec.dpdu = self.to_world(&rec.dpdu);
+        rec.dpdv = self.to_world(&rec.dpdv);
 
         true
     }
+    fn pdf_value(&self, origin: &Vec3, v: &Vec3) -> f64 {
+        self.ptr
+            .pdf_value(&self.to_object(origin), &self.to_object(v))
+    }
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        self.to_world(&self.ptr.random(&self.to_object(origin)))
+    }
+    fn emitted_power(&self) -> f64 {
+        self.ptr.emitted_power()
+    }
+    /// The emitters inside are rotated along with the object.
+    fn collect_emitters(&self, emitters: &mut Vec<Arc<dyn Hittable>>) {
+        let mut inner = Vec::new();
+        self.ptr.collect_emitters(&mut inner);
+        for emitter in inner {
+            emitters.push(Arc::new(RotateY::new(emitter, self.angle)));
+        }
+    }
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec3 {
+        self.ptr.transmittance(&self.rotate_ray(ray), t_min, t_max)
+    }
+    fn segment(&self, ray: &Ray, t_min: f64, t_max: f64) -> Segment {
+        self.ptr.segment(&self.rotate_ray(ray), t_min, t_max)
+    }
+    fn has_segments(&self) -> bool {
+        self.ptr.has_segments()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XZRectangle;
+    use crate::sphere::Sphere;
+    /// A ray straight down onto a floor, with offset rays that spread by 0.01 per unit of length.
+    fn ray_down(direction: Vec3) -> Ray {
+        Ray::new(Vec3::new(0., 1., 0.), direction, 0.).with_differential(Some(RayDifferential {
+            rx_origin: Vec3::new(0., 1., 0.),
+            rx_direction: direction + Vec3::new(0.01, 0., 0.),
+            ry_origin: Vec3::new(0., 1., 0.),
+            ry_direction: direction + Vec3::new(0., 0., 0.01),
+        }))
+    }
+    fn hit_floor(ray: &Ray) -> HitRecord {
+        let floor = XZRectangle::new(
+            -1.,
+            1.,
+            -1.,
+            1.,
+            0.,
+            Arc::new(Lambertian::new(Vec3::ones())),
+        );
+        let mut rec = HitRecord::default();
+        assert!(floor.hit(ray, 0.001, f64::INFINITY, &mut rec));
+        rec.set_differentials(ray);
+        rec
+    }
+    #[test]
+    fn test_footprint() {
+        let rec = hit_floor(&ray_down(Vec3::new(0., -1., 0.)));
+        assert!((rec.dpdx - Vec3::new(0.01, 0., 0.)).length() < 1e-12);
+        // The floor is 2 wide, so 0.01 is 0.005 in U,V.
+        assert!((rec.uv_footprint - 0.005).abs() < 1e-12);
+        // Seen at a grazing angle, the footprint stretches.
+        let grazing = hit_floor(&ray_down(Vec3::new(0., -1., 0.9)));
+        assert!(grazing.uv_footprint > 0.005);
+    }
+    #[test]
+    fn test_specular_differential() {
+        let ray = ray_down(Vec3::new(0.3, -1., 0.));
+        let rec = hit_floor(&ray);
+        let reflected = Ray::new(rec.p, Vec3::reflect(&ray.direction(), &rec.normal), 0.);
+        let differential = rec.specular_differential(&ray, &reflected).unwrap();
+        assert!((differential.rx_origin - rec.p - rec.dpdx).length() < 1e-12);
+        let expected = Vec3::reflect(&(ray.direction() + Vec3::new(0.01, 0., 0.)), &rec.normal);
+        assert!((differential.rx_direction - expected).length() < 1e-12);
+
+        let eta = 1. / 1.5;
+        let refracted = Ray::new(
+            rec.p,
+            Vec3::refract(&ray.direction().unit(), &rec.normal, eta),
+            0.,
+        );
+        let differential = rec.specular_differential(&ray, &refracted).unwrap();
+        let offset = (ray.direction() + Vec3::new(0., 0., 0.01)).unit();
+        let expected = Vec3::refract(&offset, &rec.normal, eta);
+        assert!((differential.ry_direction - expected).length() < 1e-9);
+    }
+    /// Moving or turning an object keeps telling the inside from the outside.
+    #[test]
+    fn test_transformed_front_face() {
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(
+            Vec3::zero(),
+            1.,
+            Arc::new(Lambertian::new(Vec3::ones())),
+        ));
+        let offset = Vec3::new(2., 0., 0.);
+        let translated = Translate::new(sphere.clone(), offset);
+        let rotated = Translate::new(Arc::new(RotateY::new(sphere, 30.)), offset);
+        for object in [&translated as &dyn Hittable, &rotated] {
+            let mut rec = HitRecord::default();
+            let outside = Ray::new(Vec3::new(2., 0., 5.), Vec3::new(0., 0., -1.), 0.);
+            assert!(object.hit(&outside, 0.001, f64::INFINITY, &mut rec));
+            assert!(rec.front_face);
+            assert!((rec.normal - Vec3::new(0., 0., 1.)).length() < 1e-9);
+            let inside = Ray::new(offset, Vec3::new(0., 0., -1.), 0.);
+            assert!(object.hit(&inside, 0.001, f64::INFINITY, &mut rec));
+            assert!(!rec.front_face);
+            assert!((rec.normal - Vec3::new(0., 0., 1.)).length() < 1e-9);
+        }
+    }
 }
@@ -0,0 +1,315 @@
+use crate::onb::ONB;
+use crate::vec3::Vec3;
+use std::fs;
+/// A photometric profile read from an IES LM-63 file,
+/// which gives the luminous intensity of a real fixture in every direction.
+///
+/// Angles follow the type C convention of the format:
+/// the vertical angle is measured from the nadir (the axis the fixture points along),
+/// and the horizontal angle goes around that axis.
+pub struct IesProfile {
+    /// vertical angles in degrees, increasing
+    vertical_angles: Vec<f64>,
+    /// horizontal angles in degrees, increasing
+    horizontal_angles: Vec<f64>,
+    /// the candela values, one row over the vertical angles for each horizontal angle,
+    /// normalized so that the brightest direction is 1
+    candela: Vec<Vec<f64>>,
+}
+impl IesProfile {
+    pub fn new(filename: &str) -> Self {
+        let text = fs::read_to_string(filename)
+            .unwrap_or_else(|error| panic!("Cannot read {}: {}", filename, error));
+        Self::parse(&text).unwrap_or_else(|error| panic!("Cannot parse {}: {}", filename, error))
+    }
+    /// Parse the text of an IES file.
+    ///
+    /// The keyword header is skipped until the `TILT=` line.
+    /// Tilt data is skipped as well, since the lamp is assumed to be mounted as measured.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines();
+        let tilt = loop {
+            match lines.next() {
+                Some(line) if line.trim_start().starts_with("TILT=") => {
+                    break line.trim_start()["TILT=".len()..].trim().to_string();
+                }
+                Some(_) => continue,
+                None => return Err("missing TILT line".to_string()),
+            }
+        };
+        let rest: Vec<&str> = lines.collect();
+        let mut numbers = rest
+            .join(" ")
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                token
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid number \"{}\"", token))
+            })
+            .collect::<Result<Vec<f64>, String>>()?
+            .into_iter();
+        let mut next = || {
+            numbers
+                .next()
+                .ok_or_else(|| "unexpected end of file".to_string())
+        };
+
+        if tilt == "INCLUDE" {
+            // lamp-to-luminaire geometry, then the number of pairs, the angles and the factors
+            next()?;
+            let pairs = next()? as usize;
+            for _ in 0..2 * pairs {
+                next()?;
+            }
+        }
+        let _lamp_count = next()?;
+        let _lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        let _units_type = next()?;
+        let _width = next()?;
+        let _length = next()?;
+        let _height = next()?;
+        let _ballast_factor = next()?;
+        let _future_use = next()?;
+        let _input_watts = next()?;
+        if photometric_type != 1. {
+            // Types A and B measure the angles around other axes.
+            return Err(format!(
+                "unsupported photometric type {}, only type C (1) is",
+                photometric_type
+            ));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err("no angles".to_string());
+        }
+
+        let mut vertical_angles = Vec::with_capacity(vertical_count);
+        for _ in 0..vertical_count {
+            vertical_angles.push(next()?);
+        }
+        let mut horizontal_angles = Vec::with_capacity(horizontal_count);
+        for _ in 0..horizontal_count {
+            horizontal_angles.push(next()?);
+        }
+        let mut candela = Vec::with_capacity(horizontal_count);
+        let mut max_candela: f64 = 0.;
+        for _ in 0..horizontal_count {
+            let mut row = Vec::with_capacity(vertical_count);
+            for _ in 0..vertical_count {
+                let value = next()? * multiplier;
+                max_candela = max_candela.max(value);
+                row.push(value);
+            }
+            candela.push(row);
+        }
+        if max_candela <= 0. {
+            return Err("the fixture emits no light".to_string());
+        }
+        for row in candela.iter_mut() {
+            for value in row.iter_mut() {
+                *value /= max_candela;
+            }
+        }
+        Ok(Self {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+        })
+    }
+    /// The normalized intensity towards the vertical angle `theta` and horizontal angle `phi`,
+    /// both in degrees.
+    pub fn value(&self, theta: f64, phi: f64) -> f64 {
+        let first = self.vertical_angles[0];
+        let last = *self.vertical_angles.last().unwrap();
+        if theta < first || theta > last {
+            return 0.;
+        }
+        let phi = self.fold_horizontal(phi);
+        let (h0, h1, ht) = Self::locate(&self.horizontal_angles, phi);
+        let (v0, v1, vt) = Self::locate(&self.vertical_angles, theta);
+        let lerp = |row: &Vec<f64>| row[v0] * (1. - vt) + row[v1] * vt;
+        lerp(&self.candela[h0]) * (1. - ht) + lerp(&self.candela[h1]) * ht
+    }
+    /// The normalized intensity towards the direction `w`, for a fixture pointing along
+    /// `uvw.w()` whose 0 degree horizontal plane contains `uvw.u()`.
+    pub fn value_towards(&self, uvw: &ONB, w: &Vec3) -> f64 {
+        let w = Vec3::unit(*w);
+        let theta = (w * uvw.w()).clamp(-1., 1.).acos().to_degrees();
+        let phi = (w * uvw.v()).atan2(w * uvw.u()).to_degrees();
+        self.value(theta, phi)
+    }
+    /// Map `phi` into the range of horizontal angles the file covers,
+    /// using the symmetry that its last angle implies.
+    fn fold_horizontal(&self, phi: f64) -> f64 {
+        let mut phi = phi.rem_euclid(360.);
+        let first = self.horizontal_angles[0] as i32;
+        match *self.horizontal_angles.last().unwrap() as i32 {
+            // rotationally symmetric
+            0 => 0.,
+            // symmetric about the 90-270 degree plane
+            270 if first == 90 => {
+                if !(90. ..=270.).contains(&phi) {
+                    phi = (180. - phi).rem_euclid(360.);
+                }
+                phi
+            }
+            // symmetric in each quadrant
+            90 => {
+                if phi > 180. {
+                    phi = 360. - phi;
+                }
+                if phi > 90. {
+                    phi = 180. - phi;
+                }
+                phi
+            }
+            // symmetric about the 0-180 degree plane
+            180 => {
+                if phi > 180. {
+                    phi = 360. - phi;
+                }
+                phi
+            }
+            _ => phi,
+        }
+    }
+    /// Find the two entries of the increasing `angles` around `x` and how far `x` is between them.
+    fn locate(angles: &[f64], x: f64) -> (usize, usize, f64) {
+        if angles.len() == 1 || x <= angles[0] {
+            return (0, 0, 0.);
+        }
+        let upper = angles.partition_point(|&angle| angle < x);
+        if upper >= angles.len() {
+            return (angles.len() - 1, angles.len() - 1, 0.);
+        }
+        let lower = upper - 1;
+        let t = (x - angles[lower]) / (angles[upper] - angles[lower]);
+        (lower, upper, t)
+    }
+    /// The fraction of the power of a uniform diffuse emitter that is left
+    /// when the emitted radiance is scaled by this profile.
+    pub fn power_fraction(&self) -> f64 {
+        const THETA_STEPS: usize = 90;
+        const PHI_STEPS: usize = 36;
+        let mut fraction = 0.;
+        for i in 0..THETA_STEPS {
+            let theta = (i as f64 + 0.5) / THETA_STEPS as f64 * 90.;
+            let mut ring = 0.;
+            for j in 0..PHI_STEPS {
+                ring += self.value(theta, (j as f64 + 0.5) / PHI_STEPS as f64 * 360.);
+            }
+            fraction += ring / PHI_STEPS as f64 * (2. * theta.to_radians()).sin();
+        }
+        fraction * std::f64::consts::PI / 2. / THETA_STEPS as f64
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::{Light, PointLight};
+    use std::sync::Arc;
+
+    /// A downlight whose intensity halves at 30 degrees and is cut off at 60 degrees,
+    /// brighter towards the 0 degree plane than across it.
+    const DOWNLIGHT: &str = "IESNA:LM-63-2002
+[TEST] test
+[MANUFAC] rt_simple
+TILT=NONE
+1 1000 2 4 3 1 2 0 0 0
+1 1 50
+0 30 60 90
+0 90 180
+100 50 0 0
+50 25 0 0
+100 50 0 0
+";
+
+    #[test]
+    fn test_parse() {
+        let profile = IesProfile::parse(DOWNLIGHT).unwrap();
+        assert_eq!(profile.vertical_angles, vec![0., 30., 60., 90.]);
+        assert_eq!(profile.horizontal_angles, vec![0., 90., 180.]);
+        assert_eq!(profile.value(0., 0.), 1.);
+        assert_eq!(profile.value(30., 0.), 0.5);
+        assert_eq!(profile.value(15., 0.), 0.75);
+        assert_eq!(profile.value(60., 0.), 0.);
+        assert_eq!(profile.value(120., 0.), 0.);
+    }
+    #[test]
+    fn test_horizontal_symmetry() {
+        let profile = IesProfile::parse(DOWNLIGHT).unwrap();
+        assert_eq!(profile.value(0., 90.), 0.5);
+        assert_eq!(profile.value(0., 45.), 0.75);
+        // The file stops at 180 degrees, so the other half is mirrored.
+        assert_eq!(profile.value(0., 270.), profile.value(0., 90.));
+        assert_eq!(profile.value(0., 315.), profile.value(0., 45.));
+    }
+    #[test]
+    fn test_symmetric_about_90_270() {
+        // Only the half from 90 to 270 degrees is given, brightest at 180 degrees.
+        let profile = IesProfile::parse(
+            "TILT=NONE
+1 1000 1 2 3 1 2 0 0 0
+1 1 50
+0 90
+90 180 270
+50 0
+100 0
+50 0
+",
+        )
+        .unwrap();
+        assert_eq!(profile.value(0., 180.), 1.);
+        assert_eq!(profile.value(0., 0.), 1.);
+        assert_eq!(profile.value(0., 90.), 0.5);
+        assert_eq!(profile.value(0., 45.), profile.value(0., 135.));
+        assert_eq!(profile.value(0., 315.), profile.value(0., 225.));
+    }
+    #[test]
+    fn test_parse_errors() {
+        assert!(IesProfile::parse("IESNA:LM-63-2002\n1 2 3").is_err());
+        assert!(IesProfile::parse("TILT=NONE\n1 1000 1 4 3").is_err());
+        assert!(IesProfile::parse("TILT=NONE\n1 1000 1 x").is_err());
+        // Type B, used for floodlights.
+        let type_b = DOWNLIGHT.replace("1 1000 2 4 3 1 2", "1 1000 2 4 3 2 2");
+        assert!(IesProfile::parse(&type_b).is_err());
+    }
+    /// Render a flat wall lit by the downlight from 1 unit away,
+    /// and check that it shows the pattern of the profile.
+    #[test]
+    fn test_render_wall() {
+        let profile = Arc::new(IesProfile::parse(DOWNLIGHT).unwrap());
+        let light = PointLight::new_ies(
+            Vec3::new(0., 1., 0.),
+            Vec3::new(0., -1., 0.),
+            Vec3::ones(),
+            profile,
+        );
+        // The wall is the plane y = 0, facing up towards the light.
+        const SIZE: usize = 41;
+        let mut wall = [[0.; SIZE]; SIZE];
+        for (i, row) in wall.iter_mut().enumerate() {
+            for (j, pixel) in row.iter_mut().enumerate() {
+                let x = (i as f64 - 20.) / 10.;
+                let z = (j as f64 - 20.) / 10.;
+                if let Some(sample) = light.sample(&Vec3::new(x, 0., z), 0.) {
+                    *pixel = sample.radiance.x() * sample.wi.y();
+                }
+            }
+        }
+        let center = wall[20][20];
+        assert!((center - 1.).abs() < 1e-9);
+        // The light is cut off at 60 degrees, which is tan(60°) ≈ 1.73 away from the center.
+        assert_eq!(wall[20][40], 0.);
+        assert_eq!(wall[0][20], 0.);
+        assert!(wall[20][25] > 0.);
+        // Brighter along the 0 degree plane (x) than across it (z).
+        assert!(wall[25][20] > wall[20][25]);
+        // Symmetric about the 0-180 degree plane.
+        assert!((wall[20][25] - wall[20][15]).abs() < 1e-9);
+    }
+}
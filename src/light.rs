@@ -0,0 +1,605 @@
+use crate::color::luminance;
+use crate::hit::*;
+use crate::ies::IesProfile;
+use crate::onb::ONB;
+use crate::ray::Ray;
+use crate::rt_weekend::*;
+use crate::sphere::random_to_sphere;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+/// What a light returns when it is sampled from a shading point.
+pub struct LightSample {
+    /// unit direction from the shading point towards the sampled position on the light
+    pub wi: Vec3,
+    /// distance from the shading point to the sampled position, infinite for directional lights
+    pub distance: f64,
+    /// the radiance arriving at the shading point along `wi`, ignoring occlusion
+    pub radiance: Vec3,
+    /// the solid angle PDF of choosing `wi`, which is 1 for delta lights
+    pub pdf: f64,
+}
+/// A light source that is sampled explicitly by the integrator,
+/// instead of being found by chance when a scattered ray hits an emissive surface.
+///
+/// Apart from `EmitterLight`, these lights are not part of the world,
+/// so scattered rays never hit them and they only contribute through direct lighting.
+pub trait Light: Sync + Send {
+    /// Sample a position on the light as seen from the shading point `p` at `time`.
+    /// Return `None` if the light cannot illuminate `p` at all.
+    fn sample(&self, p: &Vec3, time: f64) -> Option<LightSample>;
+    /// Whether the light is described by a delta distribution (a point or a single direction).
+    fn is_delta(&self) -> bool {
+        false
+    }
+    /// The total power of the light, which decides how often it is sampled.
+    /// Delta lights are always sampled, so they don't need it.
+    fn power(&self) -> f64 {
+        0.
+    }
+}
+/// All the lights of a scene.
+///
+/// Delta lights are cheap and few, so every one of them is sampled at each hit.
+/// For the rest, which may be hundreds of emissive primitives,
+/// a single light is picked at random in proportion to its power.
+#[derive(Clone, Default)]
+pub struct LightList {
+    delta: Vec<Arc<dyn Light>>,
+    others: Vec<Arc<dyn Light>>,
+    /// the running sum of the power of `others`
+    cumulative_power: Vec<f64>,
+}
+impl LightList {
+    pub fn new() -> Self {
+        Self {
+            delta: Vec::new(),
+            others: Vec::new(),
+            cumulative_power: Vec::new(),
+        }
+    }
+    pub fn add(&mut self, light: Arc<dyn Light>) {
+        if light.is_delta() {
+            self.delta.push(light);
+        } else {
+            let total = self.cumulative_power.last().copied().unwrap_or(0.);
+            self.cumulative_power.push(total + light.power());
+            self.others.push(light);
+        }
+    }
+    /// Add a light for every emissive primitive in `world`.
+    pub fn add_emitters(&mut self, world: &dyn Hittable) {
+        let mut emitters = Vec::new();
+        world.collect_emitters(&mut emitters);
+        for emitter in emitters {
+            self.add(Arc::new(EmitterLight::new(emitter)));
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.delta.len() + self.others.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Pick one of the non-delta lights in proportion to its power,
+    /// returning it together with the probability of picking it.
+    fn choose(&self) -> Option<(&Arc<dyn Light>, f64)> {
+        let total = *self.cumulative_power.last()?;
+        if total <= 0. {
+            // Nothing to go by, so pick uniformly.
+            let index = random_int_in_range(0, self.others.len() as i32 - 1) as usize;
+            return Some((&self.others[index], 1. / self.others.len() as f64));
+        }
+        let target = random_double() * total;
+        let index = self
+            .cumulative_power
+            .partition_point(|&power| power <= target)
+            .min(self.others.len() - 1);
+        let previous = if index == 0 {
+            0.
+        } else {
+            self.cumulative_power[index - 1]
+        };
+        Some((
+            &self.others[index],
+            (self.cumulative_power[index] - previous) / total,
+        ))
+    }
+    /// Sample the lights from the shading point `p` at `time`:
+    /// every delta light, plus one of the others chosen by power.
+    /// The PDFs of the samples include the probability of choosing the light.
+    pub fn sample(&self, p: &Vec3, time: f64) -> Vec<LightSample> {
+        let mut samples: Vec<LightSample> = self
+            .delta
+            .iter()
+            .filter_map(|light| light.sample(p, time))
+            .collect();
+        if let Some((light, probability)) = self.choose() {
+            if let Some(mut sample) = light.sample(p, time) {
+                sample.pdf *= probability;
+                samples.push(sample);
+            }
+        }
+        samples
+    }
+}
+/// An ideal point light which emits the same intensity in all directions,
+/// unless it follows the photometric profile of a real fixture.
+pub struct PointLight {
+    position: Vec3,
+    intensity: Vec3,
+    /// the IES profile and the frame of the fixture, whose nadir is `w`
+    profile: Option<(Arc<IesProfile>, ONB)>,
+}
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        Self {
+            position,
+            intensity,
+            profile: None,
+        }
+    }
+    /// A point light pointing along `direction`,
+    /// whose `intensity` is scaled by the normalized candela values of `profile`.
+    pub fn new_ies(
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        profile: Arc<IesProfile>,
+    ) -> Self {
+        Self {
+            position,
+            intensity,
+            profile: Some((profile, ONB::build_from_w(&direction))),
+        }
+    }
+}
+impl Light for PointLight {
+    fn sample(&self, p: &Vec3, _time: f64) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance = to_light.length();
+        if distance == 0. {
+            return None;
+        }
+        let scale = match &self.profile {
+            Some((profile, uvw)) => profile.value_towards(uvw, &-to_light),
+            None => 1.,
+        };
+        Some(LightSample {
+            wi: to_light / distance,
+            distance,
+            // The intensity falls off with the inverse square of the distance.
+            radiance: self.intensity * scale / (distance * distance),
+            pdf: 1.,
+        })
+    }
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+/// A point light that only emits into a cone around `direction`.
+///
+/// The intensity is constant inside `falloff_start` degrees from the axis,
+/// and fades out smoothly until `total_width` degrees.
+pub struct SpotLight {
+    position: Vec3,
+    direction: Vec3,
+    intensity: Vec3,
+    cos_total_width: f64,
+    cos_falloff_start: f64,
+}
+impl SpotLight {
+    pub fn new(
+        position: Vec3,
+        look_at: Vec3,
+        intensity: Vec3,
+        total_width: f64,
+        falloff_start: f64,
+    ) -> Self {
+        Self {
+            position,
+            direction: Vec3::unit(look_at - position),
+            intensity,
+            cos_total_width: degrees_to_radians(total_width).cos(),
+            cos_falloff_start: degrees_to_radians(falloff_start.min(total_width)).cos(),
+        }
+    }
+}
+/// The smooth falloff of a cone of light, from 1 on the axis to 0 outside the cone.
+///
+/// # Arguments
+/// * `cos_theta` - the cosine of the angle to the axis of the cone
+pub fn spot_falloff(cos_theta: f64, cos_total_width: f64, cos_falloff_start: f64) -> f64 {
+    if cos_theta < cos_total_width {
+        return 0.;
+    }
+    if cos_theta >= cos_falloff_start {
+        return 1.;
+    }
+    let delta = (cos_theta - cos_total_width) / (cos_falloff_start - cos_total_width);
+    delta * delta * delta * delta
+}
+impl Light for SpotLight {
+    fn sample(&self, p: &Vec3, _time: f64) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance = to_light.length();
+        if distance == 0. {
+            return None;
+        }
+        let wi = to_light / distance;
+        let falloff = spot_falloff(
+            -wi * self.direction,
+            self.cos_total_width,
+            self.cos_falloff_start,
+        );
+        if falloff == 0. {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: self.intensity * falloff / (distance * distance),
+            pdf: 1.,
+        })
+    }
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+/// A light infinitely far away, so that every point receives it from the same direction,
+/// like sunlight.
+pub struct DirectionalLight {
+    /// the direction the light travels in
+    direction: Vec3,
+    radiance: Vec3,
+}
+impl DirectionalLight {
+    pub fn new(direction: Vec3, radiance: Vec3) -> Self {
+        Self {
+            direction: Vec3::unit(direction),
+            radiance,
+        }
+    }
+}
+impl Light for DirectionalLight {
+    fn sample(&self, _p: &Vec3, _time: f64) -> Option<LightSample> {
+        Some(LightSample {
+            wi: -self.direction,
+            distance: f64::INFINITY,
+            radiance: self.radiance,
+            pdf: 1.,
+        })
+    }
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+/// Convert a PDF over the area of a light into a PDF over solid angle.
+fn area_to_solid_angle(pdf_area: f64, distance: f64, cos_light: f64) -> f64 {
+    pdf_area * distance * distance / cos_light
+}
+/// A one-sided parallelogram light spanned by the edges `u` and `v` from `corner`.
+/// It emits along `u × v`.
+pub struct RectangleLight {
+    corner: Vec3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    area: f64,
+    radiance: Vec3,
+}
+impl RectangleLight {
+    pub fn new(corner: Vec3, u: Vec3, v: Vec3, radiance: Vec3) -> Self {
+        let n = Vec3::cross(u, v);
+        Self {
+            corner,
+            u,
+            v,
+            normal: Vec3::unit(n),
+            area: n.length(),
+            radiance,
+        }
+    }
+}
+impl Light for RectangleLight {
+    fn sample(&self, p: &Vec3, _time: f64) -> Option<LightSample> {
+        let position = self.corner + self.u * random_double() + self.v * random_double();
+        let to_light = position - *p;
+        let distance = to_light.length();
+        let wi = to_light / distance;
+        let cos_light = -(wi * self.normal);
+        if cos_light <= 0. {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: self.radiance,
+            pdf: area_to_solid_angle(1. / self.area, distance, cos_light),
+        })
+    }
+    fn power(&self) -> f64 {
+        luminance(self.radiance) * self.area * PI
+    }
+}
+/// A one-sided disk light facing `normal`.
+pub struct DiskLight {
+    center: Vec3,
+    radius: f64,
+    uvw: ONB,
+    radiance: Vec3,
+}
+impl DiskLight {
+    pub fn new(center: Vec3, normal: Vec3, radius: f64, radiance: Vec3) -> Self {
+        Self {
+            center,
+            radius,
+            uvw: ONB::build_from_w(&normal),
+            radiance,
+        }
+    }
+    fn area(&self) -> f64 {
+        PI * self.radius * self.radius
+    }
+}
+impl Light for DiskLight {
+    fn sample(&self, p: &Vec3, _time: f64) -> Option<LightSample> {
+        let d = Vec3::random_in_unit_disk() * self.radius;
+        let position = self.center + self.uvw.local(d.x(), d.y(), 0.);
+        let to_light = position - *p;
+        let distance = to_light.length();
+        let wi = to_light / distance;
+        let cos_light = -(wi * self.uvw.w());
+        if cos_light <= 0. {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: self.radiance,
+            pdf: area_to_solid_angle(1. / self.area(), distance, cos_light),
+        })
+    }
+    fn power(&self) -> f64 {
+        luminance(self.radiance) * self.area() * PI
+    }
+}
+/// A spherical light emitting outwards from its whole surface.
+pub struct SphereLight {
+    center: Vec3,
+    radius: f64,
+    radiance: Vec3,
+}
+impl SphereLight {
+    pub fn new(center: Vec3, radius: f64, radiance: Vec3) -> Self {
+        Self {
+            center,
+            radius,
+            radiance,
+        }
+    }
+}
+impl Light for SphereLight {
+    /// Only the part of the sphere visible from `p` can light it,
+    /// so we sample the cone of directions that the sphere subtends.
+    fn sample(&self, p: &Vec3, _time: f64) -> Option<LightSample> {
+        let direction = self.center - *p;
+        let distance_squared = direction.squared_length();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            // Points inside the sphere never see its outside.
+            return None;
+        }
+        let cos_theta_max = (1. - radius_squared / distance_squared).sqrt();
+        let uvw = ONB::build_from_w(&direction);
+        let wi = uvw.local_vec(&random_to_sphere(self.radius, distance_squared));
+        // Find where `wi` enters the sphere.
+        let oc = *p - self.center;
+        let half_b = oc * wi;
+        let c = oc.squared_length() - radius_squared;
+        let distance = -half_b - (half_b * half_b - c).max(0.).sqrt();
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: self.radiance,
+            pdf: 1. / (2. * PI * (1. - cos_theta_max)),
+        })
+    }
+    fn power(&self) -> f64 {
+        luminance(self.radiance) * 4. * PI * self.radius * self.radius * PI
+    }
+}
+/// An emissive primitive of the world, sampled through its `Hittable::random`.
+///
+/// Unlike the other lights it can also be hit by scattered rays,
+/// so the integrator has to avoid counting it twice.
+pub struct EmitterLight {
+    object: Arc<dyn Hittable>,
+    power: f64,
+}
+impl EmitterLight {
+    pub fn new(object: Arc<dyn Hittable>) -> Self {
+        let power = object.emitted_power();
+        Self { object, power }
+    }
+}
+impl Light for EmitterLight {
+    fn sample(&self, p: &Vec3, time: f64) -> Option<LightSample> {
+        let direction = self.object.random(p);
+        let pdf = self.object.pdf_value(p, &direction);
+        if pdf <= 0. {
+            return None;
+        }
+        // Find the sampled point to know how far it is and what it emits.
+        let wi = Vec3::unit(direction);
+        let ray = Ray::new(*p, wi, time);
+        let mut rec: HitRecord = Default::default();
+        if !self.object.hit(&ray, 0.001, f64::INFINITY, &mut rec) {
+            return None;
+        }
+        rec.time = time;
+        Some(LightSample {
+            wi,
+            distance: rec.t,
+            radiance: rec.material.emitted(&ray, &rec),
+            pdf,
+        })
+    }
+    fn power(&self) -> f64 {
+        self.power
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::DiffuseLight;
+    use crate::quad::Quad;
+    use crate::texture::{Texture, TexturePoint};
+    /// Averaging `1 / pdf` over the samples gives the solid angle the PDF is spread over,
+    /// which is the solid angle of the light exactly when the PDF integrates to 1.
+    fn sampled_solid_angle(light: &dyn Light, p: &Vec3) -> f64 {
+        const N: usize = 100000;
+        let sum: f64 = (0..N)
+            .map(|_| {
+                let sample = light.sample(p, 0.).unwrap();
+                assert!((sample.wi.length() - 1.).abs() < 1e-9);
+                1. / sample.pdf
+            })
+            .sum();
+        sum / N as f64
+    }
+    #[test]
+    fn test_pdf_normalized() {
+        let p = Vec3::new(0., 0., 0.);
+        let radiance = Vec3::ones();
+        // A 2 by 1 rectangle and a disk of radius 1, 2 above `p` and facing it.
+        let rectangle = RectangleLight::new(
+            Vec3::new(-1., 2., -0.5),
+            Vec3::new(2., 0., 0.),
+            Vec3::new(0., 0., 1.),
+            radiance,
+        );
+        let expected = 4. * (2f64 / ((4. + 16.) * (1f64 + 16.)).sqrt()).asin();
+        let solid_angle = sampled_solid_angle(&rectangle, &p);
+        assert!(
+            (solid_angle / expected - 1.).abs() < 0.01,
+            "{}",
+            solid_angle
+        );
+        let disk = DiskLight::new(Vec3::new(0., 2., 0.), Vec3::new(0., -1., 0.), 1., radiance);
+        let expected = 2. * PI * (1. - 2. / 5f64.sqrt());
+        let solid_angle = sampled_solid_angle(&disk, &p);
+        assert!(
+            (solid_angle / expected - 1.).abs() < 0.01,
+            "{}",
+            solid_angle
+        );
+        let sphere = SphereLight::new(Vec3::new(0., 2., 0.), 1., radiance);
+        let expected = 2. * PI * (1. - 0.75f64.sqrt());
+        let solid_angle = sampled_solid_angle(&sphere, &p);
+        assert!(
+            (solid_angle / expected - 1.).abs() < 0.01,
+            "{}",
+            solid_angle
+        );
+        // Facing away, the one-sided lights give nothing.
+        let behind = Vec3::new(0., 4., 0.);
+        assert!(rectangle.sample(&behind, 0.).is_none());
+        assert!(disk.sample(&behind, 0.).is_none());
+    }
+    #[test]
+    fn test_point_and_spot() {
+        let point = PointLight::new(Vec3::new(0., 2., 0.), Vec3::ones() * 8.);
+        let sample = point.sample(&Vec3::zero(), 0.).unwrap();
+        assert!(point.is_delta());
+        assert!((sample.wi - Vec3::new(0., 1., 0.)).length() < 1e-9);
+        assert!((sample.radiance.x() - 2.).abs() < 1e-9 && sample.distance == 2.);
+        assert_eq!(spot_falloff(1., 0.5, 0.8), 1.);
+        assert_eq!(spot_falloff(0.8, 0.5, 0.8), 1.);
+        assert_eq!(spot_falloff(0.4, 0.5, 0.8), 0.);
+        let halfway = spot_falloff(0.65, 0.5, 0.8);
+        assert!((halfway - 0.0625).abs() < 1e-9);
+        assert!(spot_falloff(0.7, 0.5, 0.8) > halfway);
+        let spot = SpotLight::new(Vec3::zero(), Vec3::new(0., -1., 0.), Vec3::ones(), 30., 20.);
+        let below = spot.sample(&Vec3::new(0., -2., 0.), 0.).unwrap();
+        assert!((below.radiance.x() - 0.25).abs() < 1e-9);
+        // 40° off the axis is outside the cone.
+        let aside = Vec3::new(degrees_to_radians(40.).tan(), -1., 0.);
+        assert!(spot.sample(&aside, 0.).is_none());
+    }
+    #[test]
+    fn test_directional() {
+        let sun = DirectionalLight::new(Vec3::new(1., -1., 0.), Vec3::ones() * 3.);
+        assert!(sun.is_delta());
+        for p in [Vec3::zero(), Vec3::new(100., -5., 20.)] {
+            let sample = sun.sample(&p, 0.).unwrap();
+            assert!((sample.wi - Vec3::unit(Vec3::new(-1., 1., 0.))).length() < 1e-9);
+            assert!(sample.distance.is_infinite());
+            assert_eq!(sample.radiance.x(), 3.);
+            assert_eq!(sample.pdf, 1.);
+        }
+    }
+    #[test]
+    fn test_choose() {
+        let p = Vec3::zero();
+        let rectangle = |side: f64| -> Arc<dyn Light> {
+            Arc::new(RectangleLight::new(
+                Vec3::new(0., 2., 0.),
+                Vec3::new(side, 0., 0.),
+                Vec3::new(0., 0., side),
+                Vec3::ones(),
+            ))
+        };
+        // Areas of 1, 4 and 9, so powers in the same ratio.
+        let others = [rectangle(1.), rectangle(2.), rectangle(3.)];
+        let mut lights = LightList::new();
+        lights.add(Arc::new(PointLight::new(
+            Vec3::new(0., 1., 0.),
+            Vec3::ones(),
+        )));
+        for light in &others {
+            lights.add(light.clone());
+        }
+        assert_eq!(lights.len(), 4);
+        const N: usize = 100000;
+        let mut counts = [0; 3];
+        for _ in 0..N {
+            let (chosen, probability) = lights.choose().unwrap();
+            let index = others.iter().position(|l| Arc::ptr_eq(l, chosen)).unwrap();
+            assert!((probability - [1., 4., 9.][index] / 14.).abs() < 1e-9);
+            counts[index] += 1;
+        }
+        for (count, power) in counts.iter().zip([1., 4., 9.]) {
+            assert!((*count as f64 / N as f64 - power / 14.).abs() < 0.01);
+        }
+        // The point light is sampled every time, together with one of the others.
+        for _ in 0..100 {
+            let samples = lights.sample(&p, 0.);
+            assert_eq!(samples.len(), 2);
+            assert_eq!(samples[0].distance, 1.);
+            assert!(samples[1].distance > 1.);
+        }
+    }
+    /// A texture as bright as the time it is looked up at.
+    struct Clock;
+    impl Texture for Clock {
+        fn value(&self, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+            Vec3::zero()
+        }
+        fn sample(&self, point: &TexturePoint) -> Vec3 {
+            Vec3::ones() * point.time
+        }
+    }
+    #[test]
+    fn test_emitter_time() {
+        let material = Arc::new(DiffuseLight::new_texture(Arc::new(Clock)));
+        let light = EmitterLight::new(Arc::new(Quad::new(
+            Vec3::new(-1., 2., -1.),
+            Vec3::new(2., 0., 0.),
+            Vec3::new(0., 0., 2.),
+            material,
+        )));
+        // The emission is looked up at the time of the shading ray.
+        let sample = light.sample(&Vec3::zero(), 0.5).unwrap();
+        assert_eq!(sample.radiance, Vec3::ones() * 0.5);
+    }
+}
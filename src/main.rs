@@ -6,16 +6,32 @@
 extern crate rand;
 pub mod aabb;
 mod aarect;
+mod alpha_mask;
 mod bvh;
 mod camera;
 mod color;
 mod constant_medium;
 mod cornell_box;
+mod heterogeneous_medium;
 mod hit;
+mod ies;
+mod light;
 mod material;
+mod microfacet;
+mod normal_map;
+mod onb;
+mod participating_medium;
 mod perlin;
+mod phase;
+mod procedural;
+mod quad;
 mod scene;
+mod shapes;
+mod spectrum;
+mod subsurface;
 mod texture;
+mod texture_graph;
+mod voxel_grid;
 pub use camera::Camera;
 pub use hit::*;
 mod rt_weekend;
@@ -25,46 +41,126 @@ use color::write_color;
 mod ray;
 mod vec3;
 use crate::bvh::BVHNode;
+use crate::light::LightList;
 use crate::scene::*;
+use crate::spectrum::Wavelengths;
 pub use ray::Ray;
 use std::collections::VecDeque;
 use std::fmt::Display;
 use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time::Instant;
-use std::{f64::INFINITY, fs::File, process::exit};
+use std::{fs::File, process::exit};
 pub use vec3::Vec3;
 
 use console::style;
 use image::{ImageBuffer, RgbImage};
 #[allow(unused_imports)]
 use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+/// Estimate the light arriving at the hit point directly from the sampled light sources,
+/// each of them tested for occlusion with a shadow ray.
+fn direct_light(r: &Ray, rec: &HitRecord, world: &Arc<BVHNode>, lights: &LightList) -> Vec3 {
+    let mut direct = Vec3::zero();
+    for sample in lights.sample(&rec.p, r.time()) {
+        if sample.pdf <= 0. {
+            continue;
+        }
+        let shadow_ray = Ray::new(rec.p, sample.wi, r.time());
+        // Stop a little before the light, so that we don't hit the surface we sampled.
+        // Surfaces block the light, and media in between dim it.
+        let transmittance = world.transmittance(&shadow_ray, 0.001, sample.distance * (1. - 1e-4));
+        if transmittance == Vec3::zero() {
+            continue;
+        }
+        let f = Vec3::elemul(
+            r.spectrum(rec.material.eval(r, rec, &shadow_ray)),
+            r.spectrum(transmittance),
+        );
+        direct += Vec3::elemul(f, r.spectrum(sample.radiance)) / sample.pdf;
+    }
+    direct
+}
+/// A `Segment` as the ray sees it, like `Ray::spectrum`.
+fn spectral_segment(r: &Ray, segment: Segment) -> Segment {
+    Segment {
+        transmittance: r.spectrum(segment.transmittance),
+        emission: r.spectrum(segment.emission),
+    }
+}
 /// ray_color() function decides the color of a ray.
-fn ray_color(r: Ray, background: &Vec3, world: &Arc<BVHNode>, depth: i32) -> Vec3 {
+///
+/// In spectral mode, the color is the radiance at the wavelengths of the ray,
+/// and the RGB colors of the scene are upsampled to them as the path goes.
+///
+/// # Arguments
+/// * `skip_emitters` - whether the previous hit has already sampled the lights directly,
+///   in which case finding an emissive primitive again would count its light twice
+fn ray_color(
+    r: Ray,
+    background: &Vec3,
+    world: &Arc<BVHNode>,
+    lights: &LightList,
+    depth: i32,
+    skip_emitters: bool,
+) -> Vec3 {
     if depth <= 0 {
         return Vec3::zero();
     }
     let mut hit_record = Default::default();
     // If the ray hits nothing, return the background color.
-    if !world.hit(&r, 0.001, INFINITY, &mut hit_record) {
-        return *background;
+    if !world.hit(&r, 0.001, f64::INFINITY, &mut hit_record) {
+        let segment = spectral_segment(&r, world.segment(&r, 0.001, f64::INFINITY));
+        return segment.apply(r.spectrum(*background));
     }
+    hit_record.set_differentials(&r);
+    hit_record.time = r.time();
+    // Some media dim or light up the way to the hit point.
+    let segment = spectral_segment(&r, world.segment(&r, 0.001, hit_record.t));
     let mut scattered = Ray::zero();
     let mut attenuation = Vec3::zero();
-    let emitted = hit_record
-        .material
-        .emitted(hit_record.u, hit_record.v, &hit_record.p);
+    let emitted = if skip_emitters && hit_record.sampled_light {
+        Vec3::zero()
+    } else {
+        r.spectrum(hit_record.material.emitted(&r, &hit_record))
+    };
     if !hit_record
         .material
         .scatter(&r, &hit_record, &mut attenuation, &mut scattered)
     {
-        return emitted;
+        return segment.apply(emitted);
     }
-    emitted
-        + Vec3::elemul(
-            attenuation,
-            ray_color(scattered, background, world, depth - 1),
-        )
+    scattered.inherit_wavelengths(&r);
+    // Mirrors and glass keep the footprint of the ray sharp, anything else blurs it completely.
+    if hit_record.material.is_specular() {
+        scattered = scattered.with_differential(hit_record.specular_differential(&r, &scattered));
+    }
+    let mut attenuation = r.spectrum(attenuation);
+    if let (Some(before), Some(after)) = (r.wavelengths(), scattered.wavelengths()) {
+        if after.secondary_terminated() && !before.secondary_terminated() {
+            attenuation = Vec3::elemul(attenuation, Wavelengths::termination_weight());
+        }
+    }
+    let sample_lights = !lights.is_empty() && !hit_record.material.is_specular();
+    let direct = if sample_lights {
+        direct_light(&r, &hit_record, world, lights)
+    } else {
+        Vec3::zero()
+    };
+    segment.apply(
+        emitted
+            + direct
+            + Vec3::elemul(
+                attenuation,
+                ray_color(
+                    scattered,
+                    background,
+                    world,
+                    lights,
+                    depth - 1,
+                    sample_lights,
+                ),
+            ),
+    )
     // return Vec3::zero();
 
     // let unit_direction = Vec3::unit(r.direction());
@@ -97,6 +193,8 @@ fn main() {
     let mut aspect_ratio: f64 = 16.0 / 9.0;
     let mut image_width: usize = 400;
     let mut samples_per_pixel: usize = 100;
+    // Whether to trace wavelengths instead of RGB, which dispersive glass needs.
+    let mut spectral = false;
     /// Reflection max depth
     const MAX_DEPTH: i32 = 50;
     /// JPG_QUALITY
@@ -114,6 +212,7 @@ fn main() {
     // * `aperture` - aperture's radius of the camera
     let mut aperture = 0.;
     let background;
+    let mut lights = LightList::new();
     match 9 {
         1 => {
             hit_list = Arc::new(random_scene());
@@ -192,6 +291,170 @@ fn main() {
             look_at = Vec3::new(0., 0., 0.);
             vfov = 40.0;
         }
+        10 => {
+            let (objects, scene_lights) = analytic_lights();
+            hit_list = Arc::new(objects);
+            lights = scene_lights;
+            background = Vec3::zero();
+            look_from = Vec3::new(26., 3., 6.);
+            look_at = Vec3::new(0., 2., 0.);
+            vfov = 20.0;
+        }
+        11 => {
+            hit_list = Arc::new(recessed_lights());
+            background = Vec3::zero();
+            look_from = Vec3::new(0., 150., -800.);
+            look_at = Vec3::new(0., 150., 0.);
+            vfov = 40.0;
+        }
+        12 => {
+            let (objects, scene_lights) = ies_wall();
+            hit_list = Arc::new(objects);
+            lights = scene_lights;
+            background = Vec3::zero();
+            look_from = Vec3::new(0., 150., -800.);
+            look_at = Vec3::new(0., 150., 0.);
+            vfov = 40.0;
+        }
+        13 => {
+            hit_list = Arc::new(microfacet_metals());
+            background = Vec3::new(0.35, 0.4, 0.5);
+            look_from = Vec3::new(13., 3., 3.);
+            look_at = Vec3::new(0., 0.8, 0.);
+            vfov = 35.0;
+        }
+        14 => {
+            hit_list = Arc::new(glass_materials());
+            background = Vec3::new(0.35, 0.4, 0.5);
+            look_from = Vec3::new(13., 3., 3.);
+            look_at = Vec3::new(0., 0.8, 0.);
+            vfov = 35.0;
+        }
+        15 => {
+            hit_list = Arc::new(dispersion());
+            background = Vec3::new(0.02, 0.02, 0.03);
+            look_from = Vec3::new(0., 6., 9.);
+            look_at = Vec3::new(0., 0.5, 0.);
+            vfov = 30.0;
+            spectral = true;
+        }
+        16 => {
+            hit_list = Arc::new(principled_materials());
+            background = Vec3::new(0.35, 0.4, 0.5);
+            look_from = Vec3::new(13., 3., 3.);
+            look_at = Vec3::new(0., 0.6, 0.);
+            vfov = 40.0;
+        }
+        17 => {
+            hit_list = Arc::new(mixed_materials());
+            background = Vec3::new(0.35, 0.4, 0.5);
+            look_from = Vec3::new(13., 3., 3.);
+            look_at = Vec3::new(0., 0.8, 0.);
+            vfov = 35.0;
+        }
+        18 => {
+            hit_list = Arc::new(rough_diffuse());
+            background = Vec3::new(0.05, 0.05, 0.08);
+            look_from = Vec3::new(13., 3., 3.);
+            look_at = Vec3::new(0., 1., 0.);
+            vfov = 35.0;
+        }
+        19 => {
+            hit_list = Arc::new(subsurface_marble());
+            background = Vec3::new(0.7, 0.8, 1.);
+            look_from = Vec3::new(13., 2., 3.);
+            look_at = Vec3::new(0., 1., 0.);
+            vfov = 30.0;
+        }
+        20 => {
+            hit_list = Arc::new(cornell_clouds());
+            aspect_ratio = 1.0;
+            image_width = 600;
+            samples_per_pixel = 200;
+            background = Vec3::zero();
+            look_from = Vec3::new(278., 278., -800.);
+            look_at = Vec3::new(278., 278., 0.);
+            vfov = 40.0;
+        }
+        21 => {
+            let (objects, scene_lights) = foggy_lights();
+            hit_list = Arc::new(objects);
+            lights = scene_lights;
+            background = Vec3::new(0.01, 0.01, 0.02);
+            look_from = Vec3::new(2., 2., 10.);
+            look_at = Vec3::new(0., 2., -2.);
+            vfov = 40.0;
+        }
+        22 => {
+            hit_list = Arc::new(glowing_media());
+            background = Vec3::new(0.3, 0.35, 0.45);
+            look_from = Vec3::new(0., 3., 12.);
+            look_at = Vec3::new(0., 1., 0.);
+            vfov = 35.0;
+        }
+        23 => {
+            hit_list = Arc::new(voxel_smoke());
+            background = Vec3::new(0.02, 0.02, 0.03);
+            look_from = Vec3::new(0., 2.5, 9.);
+            look_at = Vec3::new(0., 2., 0.);
+            vfov = 35.0;
+        }
+        24 => {
+            hit_list = Arc::new(tiled_floor());
+            background = Vec3::new(0.7, 0.8, 1.);
+            look_from = Vec3::new(0., 1.5, 4.);
+            look_at = Vec3::new(0., 1., 0.);
+            vfov = 50.0;
+        }
+        25 => {
+            hit_list = Arc::new(falling_leaves());
+            background = Vec3::new(0.5, 0.6, 0.75);
+            look_from = Vec3::new(0., 2., 7.);
+            look_at = Vec3::new(0., 1.2, -1.);
+            vfov = 45.0;
+        }
+        26 => {
+            hit_list = Arc::new(bumpy_surfaces());
+            background = Vec3::new(0.1, 0.12, 0.15);
+            look_from = Vec3::new(0., 3., 6.);
+            look_at = Vec3::new(0., 0.8, 0.);
+            vfov = 45.0;
+        }
+        27 => {
+            hit_list = Arc::new(procedural_textures());
+            background = Vec3::new(0.7, 0.8, 1.);
+            look_from = Vec3::new(0., 4., 12.);
+            look_at = Vec3::new(0., 0.8, 0.);
+            vfov = 35.0;
+        }
+        28 => {
+            hit_list = Arc::new(texture_graphs());
+            background = Vec3::new(0.7, 0.8, 1.);
+            look_from = Vec3::new(1., 3., 8.);
+            look_at = Vec3::new(0., 0.8, 0.);
+            vfov = 30.0;
+        }
+        29 => {
+            hit_list = Arc::new(checkers());
+            background = Vec3::new(0.7, 0.8, 1.);
+            look_from = Vec3::new(0., 1.5, 6.);
+            look_at = Vec3::new(0., 0.8, 0.);
+            vfov = 40.0;
+        }
+        30 => {
+            hit_list = Arc::new(noise_controls());
+            background = Vec3::new(0.7, 0.8, 1.);
+            look_from = Vec3::new(0., 4., 10.);
+            look_at = Vec3::new(0., 0.8, 0.);
+            vfov = 35.0;
+        }
+        31 => {
+            hit_list = Arc::new(primitives());
+            background = Vec3::new(0.1, 0.12, 0.15);
+            look_from = Vec3::new(0., 3., 11.);
+            look_at = Vec3::new(0., 1., 0.);
+            vfov = 40.0;
+        }
         _ => {
             hit_list = Arc::new(HitList::new());
             background = Vec3::zero();
@@ -201,6 +464,9 @@ fn main() {
         }
     }
     let world = Arc::new(BVHNode::new(hit_list.list.clone(), 0., 1.));
+    // Every emissive primitive becomes a light that is sampled directly.
+    lights.add_emitters(hit_list.as_ref());
+    let lights = Arc::new(lights);
 
     // let r = (PI / 4.0).cos();
 
@@ -244,11 +510,12 @@ fn main() {
     let dist_to_focus = 10.;
     let image_height: usize = (image_width as f64 / aspect_ratio) as usize;
     println!(
-        "Image size: {}\nJPEG quality: {}\nSamples per pixel: {}\nReflection max depth: {}",
+        "Image size: {}\nJPEG quality: {}\nSamples per pixel: {}\nReflection max depth: {}\nLight sources: {}",
         style(image_width.to_string() + &'x'.to_string() + &image_height.to_string()).yellow(),
         style(QUALITY.to_string()).yellow(),
         style(samples_per_pixel.to_string()).yellow(),
-        style(MAX_DEPTH.to_string()).yellow()
+        style(MAX_DEPTH.to_string()).yellow(),
+        style(lights.len().to_string()).yellow()
     );
 
     // Create image data
@@ -256,17 +523,20 @@ fn main() {
         image_width.try_into().unwrap(),
         image_height.try_into().unwrap(),
     );
-    let camera = Arc::new(Camera::new(
-        look_from,
-        look_at,
-        vup,
-        vfov,
-        aspect_ratio,
-        aperture,
-        dist_to_focus,
-        0.,
-        1.,
-    ));
+    let camera = Arc::new(
+        Camera::new(
+            look_from,
+            look_at,
+            vup,
+            vfov,
+            aspect_ratio,
+            aperture,
+            dist_to_focus,
+            0.,
+            1.,
+        )
+        .with_ray_differentials(image_width, image_height, samples_per_pixel),
+    );
 
     // Render
 
@@ -292,7 +562,7 @@ fn main() {
         style("Threads...").green(),
     );
 
-    let section_line_num: usize = image_height as usize / THREAD_NUMBER;
+    let section_line_num: usize = image_height / THREAD_NUMBER;
 
     let mut output_pixel_color = Vec::<Vec3>::new();
     let mut thread_pool = VecDeque::<_>::new();
@@ -324,6 +594,7 @@ fn main() {
         let (tx, rx) = mpsc::channel();
         let camera_clone = camera.clone();
         let world_clone = world.clone();
+        let lights_clone = lights.clone();
         thread_pool.push_back((
             thread::spawn(move || {
                 let mut progress = 0;
@@ -340,8 +611,22 @@ fn main() {
                         for _s in 0..samples_per_pixel {
                             let u = (i as f64 + random_double()) / (image_width as f64);
                             let v = (j as f64 + random_double()) / (image_height as f64);
-                            let r = camera_clone.get_ray(u, v);
-                            pixel_color += ray_color(r, &background, &world_clone, MAX_DEPTH);
+                            let mut r = camera_clone.get_ray(u, v);
+                            if spectral {
+                                r = r.with_wavelengths(Some(Wavelengths::sample()));
+                            }
+                            let color = ray_color(
+                                r,
+                                &background,
+                                &world_clone,
+                                &lights_clone,
+                                MAX_DEPTH,
+                                false,
+                            );
+                            pixel_color += match r.wavelengths() {
+                                Some(wavelengths) => wavelengths.radiance_to_rgb(color),
+                                None => color,
+                            };
                         }
                         section_pixel_color.push(pixel_color);
                     }
@@ -456,3 +741,71 @@ where
     );
     exit(1);
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XYRectangle;
+    use crate::material::{Dielectric, DiffuseLight};
+    use crate::shapes::Cylinder;
+    use crate::sphere::Sphere;
+    #[test]
+    fn test_skip_emitters() {
+        let light = Arc::new(DiffuseLight::new(Vec3::ones()));
+        let objects: Vec<Arc<dyn Hittable>> = vec![
+            Arc::new(Cylinder::new(
+                Vec3::new(-3., -1., 0.),
+                Vec3::new(-3., 1., 0.),
+                1.,
+                light.clone(),
+            )),
+            Arc::new(Sphere::new(Vec3::new(3., 0., 0.), 1., light)),
+        ];
+        let world = Arc::new(BVHNode::new(objects, 0., 1.));
+        let mut lights = LightList::new();
+        lights.add_emitters(world.as_ref());
+        assert_eq!(lights.len(), 1);
+        let origin = Vec3::zero();
+        // After a diffuse bounce, direct lighting has already counted the sphere,
+        // but the cylinder is not sampled, so it must still show up when hit.
+        let to_cylinder = Ray::new(origin, Vec3::new(-1., 0., 0.), 0.);
+        let to_sphere = Ray::new(origin, Vec3::new(1., 0., 0.), 0.);
+        let background = Vec3::zero();
+        let cylinder = ray_color(to_cylinder, &background, &world, &lights, 1, true);
+        let sphere = ray_color(to_sphere, &background, &world, &lights, 1, true);
+        assert!(cylinder.x() > 0.);
+        assert!(sphere.near_zero());
+        let sphere = ray_color(to_sphere, &background, &world, &lights, 1, false);
+        assert!(sphere.x() > 0.);
+    }
+    /// A light seen through a glass ball is about as bright whether the glass is smooth or frosted.
+    #[test]
+    fn test_light_through_glass() {
+        let through = |glass: Dielectric| {
+            let objects: Vec<Arc<dyn Hittable>> = vec![
+                Arc::new(Sphere::new(Vec3::zero(), 1., Arc::new(glass))),
+                Arc::new(XYRectangle::new(
+                    -5.,
+                    5.,
+                    -5.,
+                    5.,
+                    -3.,
+                    Arc::new(DiffuseLight::new(Vec3::ones())),
+                )),
+            ];
+            let world = Arc::new(BVHNode::new(objects, 0., 1.));
+            let mut lights = LightList::new();
+            lights.add_emitters(world.as_ref());
+            let ray = Ray::new(Vec3::new(0., 0., 5.), Vec3::new(0., 0., -1.), 0.);
+            let n = 2000;
+            let total = (0..n).fold(Vec3::zero(), |total, _| {
+                total + ray_color(ray, &Vec3::zero(), &world, &lights, 10, false)
+            });
+            total.x() / n as f64
+        };
+        let smooth = through(Dielectric::new(1.5));
+        assert!(smooth > 0.8);
+        for roughness in [0.05, 0.3] {
+            assert!(through(Dielectric::new(1.5).with_roughness(roughness)) > 0.8 * smooth);
+        }
+    }
+}
@@ -1,4 +1,10 @@
+use crate::color::luminance;
 use crate::hit::HitRecord;
+use crate::ies::IesProfile;
+use crate::light::spot_falloff;
+use crate::microfacet::*;
+use crate::onb::ONB;
+use crate::phase::PhaseFunction;
 use crate::ray::Ray;
 use crate::rt_weekend::*;
 use crate::texture::*;
@@ -12,8 +18,27 @@ pub trait Material: Sync + Send {
         attenuation: &mut Vec3,
         scattered: &mut Ray,
     ) -> bool;
+    /// The light emitted from the hit point back along the incoming ray `r_in`.
     #[allow(unused_variables)]
-    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        Vec3::zero()
+    }
+    /// The power emitted per unit area at the point of the surface that `rec` hits.
+    /// Surfaces where this is anything but 0 are lights that the integrator samples directly.
+    #[allow(unused_variables)]
+    fn emitted_power(&self, rec: &HitRecord) -> f64 {
+        0.
+    }
+    /// Whether the material scatters into a single direction (like a mirror or glass),
+    /// in which case sampling a light directly can never line up with it.
+    fn is_specular(&self) -> bool {
+        true
+    }
+    /// The scattering function times the cosine of `scattered`,
+    /// which is what a direct light sample gets multiplied by.
+    /// Specular materials have nothing to evaluate.
+    #[allow(unused_variables)]
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vec3 {
         Vec3::zero()
     }
 }
@@ -47,9 +72,149 @@ impl Material for Lambertian {
             scatter_direction = rec.normal;
         }
         *scattered = Ray::new(rec.p, scatter_direction, _r_in.time());
-        *attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        *attenuation = self.albedo.sample(&rec.texture_point());
         true
     }
+    fn is_specular(&self) -> bool {
+        false
+    }
+    /// A Lambertian surface scatters with BRDF albedo / π.
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vec3 {
+        let cosine = rec.normal * Vec3::unit(scattered.direction());
+        if cosine <= 0. {
+            return Vec3::zero();
+        }
+        self.albedo.sample(&rec.texture_point()) * cosine / PI
+    }
+}
+/// The rough diffuse model of Oren and Nayar (1994), for clay, concrete and fabric.
+///
+/// The surface is made of tiny Lambertian V-shaped grooves, whose slopes are spread
+/// with the standard deviation `sigma`. Compared with `Lambertian`, rough surfaces
+/// look flatter, since they scatter more light back towards where it comes from.
+pub struct OrenNayar {
+    albedo: Arc<dyn Texture>,
+    a: f64,
+    b: f64,
+}
+impl OrenNayar {
+    /// `sigma` is in degrees, and 0 is the same as `Lambertian`.
+    pub fn new(albedo: Vec3, sigma: f64) -> Self {
+        Self::new_texture(Arc::new(SolidColor::new(albedo)), sigma)
+    }
+    pub fn new_texture(albedo: Arc<dyn Texture>, sigma: f64) -> Self {
+        let sigma2 = degrees_to_radians(sigma).powi(2);
+        Self {
+            albedo,
+            a: 1. - sigma2 / (2. * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+    /// The factor on the Lambertian BRDF for the local directions `wo` and `wi`.
+    fn factor(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let sin_theta = |w: &Vec3| (1. - w.z() * w.z()).max(0.).sqrt();
+        let (sin_i, sin_o) = (sin_theta(wi), sin_theta(wo));
+        // the cosine of the azimuth between the two directions
+        let max_cos = if sin_i > 1e-4 && sin_o > 1e-4 {
+            ((wi.x() * wo.x() + wi.y() * wo.y()) / (sin_i * sin_o)).max(0.)
+        } else {
+            0.
+        };
+        let (sin_alpha, tan_beta) = if wi.z().abs() > wo.z().abs() {
+            (sin_o, sin_i / wi.z().abs())
+        } else {
+            (sin_i, sin_o / wo.z().abs())
+        };
+        self.a + self.b * max_cos * sin_alpha * tan_beta
+    }
+}
+impl Material for OrenNayar {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        // Sample like `Lambertian`, and weight by what differs from it.
+        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
+        if scatter_direction.near_zero() {
+            scatter_direction = rec.normal;
+        }
+        *scattered = Ray::new(rec.p, scatter_direction, r_in.time());
+        let uvw = ONB::build_from_w(&rec.normal);
+        let wo = uvw.world_to_local(&-Vec3::unit(r_in.direction()));
+        let wi = uvw.world_to_local(&Vec3::unit(scatter_direction));
+        *attenuation = self.albedo.sample(&rec.texture_point()) * self.factor(&wo, &wi);
+        true
+    }
+    fn is_specular(&self) -> bool {
+        false
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vec3 {
+        let uvw = ONB::build_from_w(&rec.normal);
+        let wo = uvw.world_to_local(&-Vec3::unit(r_in.direction()));
+        let wi = uvw.world_to_local(&Vec3::unit(scattered.direction()));
+        if wi.z() <= 0. {
+            return Vec3::zero();
+        }
+        self.albedo.sample(&rec.texture_point()) * (self.factor(&wo, &wi) * wi.z() / PI)
+    }
+}
+/// A thin diffuse surface that lets part of the light through to its other side,
+/// like leaves, paper and lamp shades.
+///
+/// Both the reflected and the transmitted light leave in a Lambertian distribution,
+/// tinted by `albedo`, and `transmission` is the fraction that goes through.
+pub struct Translucent {
+    albedo: Arc<dyn Texture>,
+    transmission: f64,
+}
+impl Translucent {
+    pub fn new(albedo: Vec3, transmission: f64) -> Self {
+        Self::new_texture(Arc::new(SolidColor::new(albedo)), transmission)
+    }
+    pub fn new_texture(albedo: Arc<dyn Texture>, transmission: f64) -> Self {
+        Self {
+            albedo,
+            transmission: transmission.clamp(0., 1.),
+        }
+    }
+}
+impl Material for Translucent {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        // Going through with the probability of the transmission leaves just the albedo.
+        let side = if random_double() < self.transmission {
+            -rec.normal
+        } else {
+            rec.normal
+        };
+        let mut scatter_direction = side + Vec3::random_unit_vector();
+        if scatter_direction.near_zero() {
+            scatter_direction = side;
+        }
+        *scattered = Ray::new(rec.p, scatter_direction, r_in.time());
+        *attenuation = self.albedo.sample(&rec.texture_point());
+        true
+    }
+    fn is_specular(&self) -> bool {
+        false
+    }
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vec3 {
+        let cosine = rec.normal * Vec3::unit(scattered.direction());
+        let fraction = if cosine > 0. {
+            1. - self.transmission
+        } else {
+            self.transmission
+        };
+        self.albedo.sample(&rec.texture_point()) * (fraction * cosine.abs() / PI)
+    }
 }
 /// Metal material with reflectance function
 pub struct Metal {
@@ -83,6 +248,113 @@ impl Material for Metal {
         scattered.direction() * rec.normal > 0.0
     }
 }
+/// A conductor with a rough surface, built on the GGX microfacet model.
+///
+/// Unlike the fuzzy `Metal`, its roughness follows a physical distribution of microfacets,
+/// it conserves energy, and its color comes from the Fresnel equations
+/// of the complex index of refraction `eta + i k` of the metal.
+pub struct Conductor {
+    eta: Vec3,
+    k: Vec3,
+    distribution: TrowbridgeReitz,
+}
+impl Conductor {
+    pub fn new(eta: Vec3, k: Vec3, roughness: f64) -> Self {
+        Self::new_anisotropic(eta, k, roughness, roughness)
+    }
+    /// A conductor that is rougher along one tangent than the other, like brushed metal.
+    ///
+    /// The surfaces have no preferred tangent, so the direction of the brushing
+    /// around the normal is arbitrary.
+    pub fn new_anisotropic(eta: Vec3, k: Vec3, roughness_u: f64, roughness_v: f64) -> Self {
+        Self {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::new(
+                TrowbridgeReitz::roughness_to_alpha(roughness_u),
+                TrowbridgeReitz::roughness_to_alpha(roughness_v),
+            ),
+        }
+    }
+    /// Measured RGB indices of refraction of common metals.
+    pub fn gold(roughness: f64) -> Self {
+        Self::new(
+            Vec3::new(0.143119, 0.374957, 1.44248),
+            Vec3::new(3.98316, 2.38572, 1.60322),
+            roughness,
+        )
+    }
+    pub fn copper(roughness: f64) -> Self {
+        Self::new(
+            Vec3::new(0.200438, 0.924033, 1.10221),
+            Vec3::new(3.91295, 2.45285, 2.14219),
+            roughness,
+        )
+    }
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::new(
+            Vec3::new(1.65746, 0.880369, 0.521229),
+            Vec3::new(9.22387, 6.26952, 4.837),
+            roughness,
+        )
+    }
+    pub fn silver(roughness: f64) -> Self {
+        Self::new(
+            Vec3::new(0.155265, 0.116723, 0.138342),
+            Vec3::new(4.82835, 3.12225, 2.14696),
+            roughness,
+        )
+    }
+}
+impl Material for Conductor {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let unit_direction = Vec3::unit(r_in.direction());
+        if self.distribution.is_smooth() {
+            let reflected = Vec3::reflect(&unit_direction, &rec.normal);
+            *scattered = Ray::new(rec.p, reflected, r_in.time());
+            *attenuation =
+                fresnel_conductor_rgb(-(unit_direction * rec.normal), &self.eta, &self.k);
+            return true;
+        }
+        let uvw = ONB::build_from_w(&rec.normal);
+        let wo = uvw.world_to_local(&-unit_direction);
+        if wo.z() <= 0. {
+            return false;
+        }
+        let wm = self.distribution.sample_wm(&wo);
+        let wi = -wo + wm * (2. * (wo * wm));
+        // The microfacet may reflect the ray below the surface.
+        if wi.z() <= 0. {
+            return false;
+        }
+        *scattered = Ray::new(rec.p, uvw.local_vec(&wi), r_in.time());
+        // Sampling the visible normals leaves only the Fresnel term and the masking of `wi`.
+        *attenuation = fresnel_conductor_rgb(wo * wm, &self.eta, &self.k)
+            * (self.distribution.g(&wo, &wi) / self.distribution.g1(&wo));
+        true
+    }
+    fn is_specular(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vec3 {
+        let uvw = ONB::build_from_w(&rec.normal);
+        let wo = uvw.world_to_local(&-Vec3::unit(r_in.direction()));
+        let wi = uvw.world_to_local(&Vec3::unit(scattered.direction()));
+        if wo.z() <= 0. || wi.z() <= 0. {
+            return Vec3::zero();
+        }
+        let wm = Vec3::unit(wo + wi);
+        // The cosine of `wi` cancels against the denominator of the BRDF.
+        fresnel_conductor_rgb(wo * wm, &self.eta, &self.k)
+            * (self.distribution.d(&wm) * self.distribution.g(&wo, &wi) / (4. * wo.z()))
+    }
+}
 /// Dielectric material class that always refracts when possible
 ///
 /// # Approximation
@@ -99,13 +371,121 @@ impl Material for Metal {
 /// the geometry is unaffected, but the surface normal points inward.
 /// This can be used as a bubble to make a hollow glass sphere
 ///
+/// # Rough, Colored and Coated Glass
+///
+/// A roughness turns the surface into GGX microfacets that blur both the reflection and the refraction,
+/// like frosted glass.
+/// An absorption coefficient tints the light by the distance it travels inside,
+/// following the Beer-Lambert law. This needs a closed surface,
+/// since the distance is measured whenever a ray leaves through a back face
+/// (for the hollow sphere, that includes the inner surface, so only the shell absorbs).
+/// A thin film on the surface makes the reflection iridescent.
+///
+/// # Dispersion
+///
+/// Real glass refracts blue light more than red, which splits white light into a rainbow.
+/// A dispersive glass gives each wavelength its own index of refraction,
+/// which only shows in spectral mode; RGB rays see the index at the sodium d-line.
+#[derive(Clone)]
 pub struct Dielectric {
     /// Index of Refraction
     ref_idx: f64,
+    dispersion: Option<Dispersion>,
+    distribution: TrowbridgeReitz,
+    /// the absorption coefficient for each channel, per unit distance
+    absorption: Vec3,
+    film: Option<ThinFilm>,
+}
+/// How the index of refraction of a dispersive glass varies with the wavelength.
+#[derive(Clone, Copy, Debug)]
+pub enum Dispersion {
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+impl Dispersion {
+    /// The wavelength in nanometers that glass catalogs quote the index of refraction at.
+    const D_LINE: f64 = 587.6;
+    /// The index of refraction at `lambda` nanometers.
+    pub fn ior(&self, lambda: f64) -> f64 {
+        let micrometers = lambda / 1000.;
+        let l2 = micrometers * micrometers;
+        match self {
+            Self::Cauchy { a, b } => a + b / l2,
+            Self::Sellmeier { b, c } => {
+                (1. + b[0] * l2 / (l2 - c[0]) + b[1] * l2 / (l2 - c[1]) + b[2] * l2 / (l2 - c[2]))
+                    .sqrt()
+            }
+        }
+    }
+}
+/// A non-absorbing film coating a surface.
+#[derive(Clone, Copy, Debug)]
+pub struct ThinFilm {
+    /// in nanometers
+    thickness: f64,
+    ior: f64,
 }
 impl Dielectric {
     pub fn new(ref_idx: f64) -> Self {
-        Self { ref_idx }
+        Self {
+            ref_idx,
+            dispersion: None,
+            distribution: TrowbridgeReitz::new(0., 0.),
+            absorption: Vec3::zero(),
+            film: None,
+        }
+    }
+    /// Glass whose index of refraction follows Cauchy's equation `a + b / λ²`, with λ in micrometers.
+    pub fn new_cauchy(a: f64, b: f64) -> Self {
+        Self::new_dispersive(Dispersion::Cauchy { a, b })
+    }
+    /// Glass whose index of refraction follows the Sellmeier equation,
+    /// with the `c` coefficients in square micrometers as glass catalogs list them.
+    pub fn new_sellmeier(b: [f64; 3], c: [f64; 3]) -> Self {
+        Self::new_dispersive(Dispersion::Sellmeier { b, c })
+    }
+    fn new_dispersive(dispersion: Dispersion) -> Self {
+        let mut dielectric = Self::new(dispersion.ior(Dispersion::D_LINE));
+        dielectric.dispersion = Some(dispersion);
+        dielectric
+    }
+    /// The common borosilicate crown glass.
+    pub fn bk7() -> Self {
+        Self::new_sellmeier(
+            [1.03961212, 0.231792344, 1.01046945],
+            [0.00600069867, 0.0200179144, 103.560653],
+        )
+    }
+    /// A dense flint glass, which disperses light much more than crown glass.
+    pub fn dense_flint() -> Self {
+        Self::new_sellmeier(
+            [1.73759695, 0.313747346, 1.89878101],
+            [0.013188707, 0.0623068142, 155.23629],
+        )
+    }
+    /// Frosted glass with a perceptual `roughness` in [0,1].
+    pub fn with_roughness(mut self, roughness: f64) -> Self {
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness);
+        self.distribution = TrowbridgeReitz::new(alpha, alpha);
+        self
+    }
+    /// Colored glass that lets through the fraction `color` of the light
+    /// after travelling `distance` inside of it.
+    pub fn with_absorption(mut self, color: Vec3, distance: f64) -> Self {
+        let coefficient = |c: f64| -c.clamp(1e-6, 1.).ln() / distance;
+        self.absorption = Vec3::new(
+            coefficient(color.x()),
+            coefficient(color.y()),
+            coefficient(color.z()),
+        );
+        self
+    }
+    /// Coat the surface with a film `thickness` nanometers thick with the index of refraction `ior`.
+    ///
+    /// A glass of index 1 with a film of about 1.33 is a soap bubble.
+    pub fn with_film(mut self, thickness: f64, ior: f64) -> Self {
+        self.film = Some(ThinFilm { thickness, ior });
+        self
     }
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
         // Use Schlick's approximation for reflectance.
@@ -113,6 +493,65 @@ impl Dielectric {
         r0 = r0 * r0;
         r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
     }
+    /// The reflectance of the surface for light arriving at `cosine` to the (micro)normal.
+    ///
+    /// `refraction_ratio` is the index of refraction on the incoming side over the other one.
+    /// Smooth uncoated glass keeps Schlick's approximation.
+    fn fresnel(&self, cosine: f64, refraction_ratio: f64, front_face: bool) -> Vec3 {
+        match self.film {
+            Some(film) => {
+                let (outside, inside) = if front_face {
+                    (1., 1. / refraction_ratio)
+                } else {
+                    (refraction_ratio, 1.)
+                };
+                thin_film_reflectance(cosine, outside, film.ior, inside, film.thickness)
+            }
+            None if self.distribution.is_smooth() => {
+                Vec3::ones() * Self::reflectance(cosine, refraction_ratio)
+            }
+            None => Vec3::ones() * fresnel_dielectric(cosine, 1. / refraction_ratio),
+        }
+    }
+    /// The fraction of the light that is left after travelling from the origin of `r_in` to the hit point.
+    /// Only rays leaving through a back face have travelled inside.
+    fn transmittance(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        if rec.front_face || self.absorption.near_zero() {
+            return Vec3::ones();
+        }
+        let distance = rec.t * r_in.direction().length();
+        Vec3::new(
+            (-self.absorption.x() * distance).exp(),
+            (-self.absorption.y() * distance).exp(),
+            (-self.absorption.z() * distance).exp(),
+        )
+    }
+    /// The index of refraction for the hero wavelength of `r_in`.
+    fn ior(&self, r_in: &Ray) -> f64 {
+        match (&self.dispersion, r_in.wavelengths()) {
+            (Some(dispersion), Some(wavelengths)) => dispersion.ior(wavelengths.hero()),
+            _ => self.ref_idx,
+        }
+    }
+    fn refraction_ratio(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        let ior = self.ior(r_in);
+        if rec.front_face {
+            1.0 / ior
+        } else {
+            ior
+        }
+    }
+    /// A ray leaving the glass. If the glass is dispersive,
+    /// only the hero wavelength of a spectral path can follow it.
+    fn scattered_ray(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Ray {
+        let ray = Ray::new(rec.p, direction, r_in.time());
+        match (&self.dispersion, r_in.wavelengths()) {
+            (Some(_), Some(wavelengths)) => {
+                ray.with_wavelengths(Some(wavelengths.terminate_secondary()))
+            }
+            _ => ray,
+        }
+    }
 }
 impl Material for Dielectric {
     fn scatter(
@@ -122,41 +561,724 @@ impl Material for Dielectric {
         attenuation: &mut Vec3,
         scattered: &mut Ray,
     ) -> bool {
-        *attenuation = Vec3::ones();
-        let refraction_ratio = if rec.front_face {
-            1.0 / self.ref_idx
-        } else {
-            self.ref_idx
-        };
+        let transmittance = self.transmittance(r_in, rec);
+        let refraction_ratio = self.refraction_ratio(r_in, rec);
         let unit_direction = Vec3::unit(r_in.direction());
-        let cos_theta = (-unit_direction * rec.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-        // If "cannot_refract", all the light is reflected,
-        // and because in practice that is usually inside solid objects, it is called “total internal reflection”.
-        let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction =
-            if cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > random_double() {
+        if self.distribution.is_smooth() {
+            let cos_theta = (-unit_direction * rec.normal).min(1.0);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            // If "cannot_refract", all the light is reflected,
+            // and because in practice that is usually inside solid objects, it is called “total internal reflection”.
+            let cannot_refract = refraction_ratio * sin_theta > 1.0;
+            let fresnel = self.fresnel(cos_theta, refraction_ratio, rec.front_face);
+            // Choose by the average reflectance, and weight the colors of a film accordingly.
+            let probability = (fresnel.x() + fresnel.y() + fresnel.z()) / 3.;
+            let direction = if cannot_refract || probability > random_double() {
+                *attenuation = if cannot_refract {
+                    transmittance
+                } else {
+                    Vec3::elemul(transmittance, fresnel) / probability
+                };
                 Vec3::reflect(&unit_direction, &rec.normal)
             } else {
+                *attenuation =
+                    Vec3::elemul(transmittance, Vec3::ones() - fresnel) / (1. - probability);
                 Vec3::refract(&unit_direction, &rec.normal, refraction_ratio)
             };
-        *scattered = Ray::new(rec.p, direction, r_in.time());
+            *scattered = self.scattered_ray(r_in, rec, direction);
+            return true;
+        }
+        let uvw = ONB::build_from_w(&rec.normal);
+        let wo = uvw.world_to_local(&-unit_direction);
+        if wo.z() <= 0. {
+            return false;
+        }
+        let wm = self.distribution.sample_wm(&wo);
+        let fresnel = self.fresnel(wo * wm, refraction_ratio, rec.front_face);
+        let probability = (fresnel.x() + fresnel.y() + fresnel.z()) / 3.;
+        let (wi, weight) = if probability > random_double() {
+            let wi = -wo + wm * (2. * (wo * wm));
+            // Reflections must stay above the surface, and refractions below it.
+            if wi.z() <= 0. {
+                return false;
+            }
+            (wi, fresnel / probability)
+        } else {
+            let wi = Vec3::refract(&-wo, &wm, refraction_ratio);
+            if wi.z() >= 0. {
+                return false;
+            }
+            (wi, (Vec3::ones() - fresnel) / (1. - probability))
+        };
+        *scattered = self.scattered_ray(r_in, rec, uvw.local_vec(&wi));
+        // As for the conductor, sampling the visible normals leaves the masking of `wi`.
+        // The radiance is not scaled by the squared ratio of the indices, just like smooth glass.
+        *attenuation = Vec3::elemul(transmittance, weight)
+            * (self.distribution.g(&wo, &wi) / self.distribution.g1(&wo));
+        true
+    }
+    /// Rough glass counts as specular too: shadow rays from inside a closed object
+    /// would all be blocked by its own surface, so the light has to be found by the scattered rays.
+    fn is_specular(&self) -> bool {
+        true
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vec3 {
+        if self.distribution.is_smooth() {
+            return Vec3::zero();
+        }
+        let uvw = ONB::build_from_w(&rec.normal);
+        let wo = uvw.world_to_local(&-Vec3::unit(r_in.direction()));
+        let wi = uvw.world_to_local(&Vec3::unit(scattered.direction()));
+        if wo.z() <= 0. || wi.z() == 0. {
+            return Vec3::zero();
+        }
+        let refraction_ratio = self.refraction_ratio(r_in, rec);
+        let transmittance = self.transmittance(r_in, rec);
+        let g = self.distribution.g(&wo, &wi);
+        if wi.z() > 0. {
+            let wm = Vec3::unit(wo + wi);
+            let fresnel = self.fresnel(wo * wm, refraction_ratio, rec.front_face);
+            return Vec3::elemul(transmittance, fresnel)
+                * (self.distribution.d(&wm) * g / (4. * wo.z()));
+        }
+        // The generalized half vector of a refraction, turned to the side of `wo`.
+        let eta = 1. / refraction_ratio;
+        let mut wm = Vec3::unit(wo + wi * eta);
+        if wm.z() < 0. {
+            wm = -wm;
+        }
+        if wm * wo <= 0. || wm * wi >= 0. {
+            return Vec3::zero();
+        }
+        let fresnel = self.fresnel(wo * wm, refraction_ratio, rec.front_face);
+        let denominator = (wi * wm + wo * wm / eta).powi(2);
+        // The cosine of `wi` cancels against the denominator of the BTDF.
+        Vec3::elemul(transmittance, Vec3::ones() - fresnel)
+            * (self.distribution.d(&wm) * g * (wi * wm).abs() * (wo * wm) / (wo.z() * denominator))
+    }
+}
+
+/// A "principled" uber material in the style of the Disney BSDF (Burley 2012, 2015),
+/// with the artist friendly parameters of content creation tools.
+///
+/// It blends these lobes:
+/// * a diffuse base with retro-reflection at grazing angles, plus a sheen for cloth,
+/// * a GGX specular reflection, colored by the base color as the surface turns metallic,
+/// * a rough glass for the transmissive part,
+/// * a clear coat on top of everything.
+///
+/// Every parameter is a texture, where scalar parameters read the red channel,
+/// and has a `with_*` method that takes a constant and a `with_*_texture` one.
+/// Each scattering samples a single lobe, chosen by a rough estimate of its weight.
+pub struct Principled {
+    base_color: Arc<dyn Texture>,
+    metallic: Arc<dyn Texture>,
+    roughness: Arc<dyn Texture>,
+    /// the strength of the specular reflection of the non-metallic part, where 0.5 is 4%
+    specular: Arc<dyn Texture>,
+    sheen: Arc<dyn Texture>,
+    clearcoat: Arc<dyn Texture>,
+    clearcoat_roughness: Arc<dyn Texture>,
+    transmission: Arc<dyn Texture>,
+    /// whether the transmission can be anything but 0, which makes the material specular like glass
+    transmissive: bool,
+    /// the index of refraction of the transmissive part
+    ior: f64,
+    emission: Arc<dyn Texture>,
+}
+/// The parameters of a `Principled` material at a hit point.
+struct PrincipledParameters {
+    base_color: Vec3,
+    metallic: f64,
+    roughness: f64,
+    specular: f64,
+    sheen: f64,
+    clearcoat: f64,
+    clearcoat_roughness: f64,
+    transmission: f64,
+}
+/// The lobes of a `Principled` material.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PrincipledLobe {
+    Diffuse,
+    Specular,
+    Transmission,
+    Clearcoat,
+}
+impl Principled {
+    /// Smoother surfaces are clamped to this roughness,
+    /// since the integrator samples lights directly on all of them.
+    const MIN_ROUGHNESS: f64 = 0.05;
+    /// the index of refraction of the clear coat
+    const CLEARCOAT_IOR: f64 = 1.5;
+
+    pub fn new(base_color: Vec3) -> Self {
+        Self::new_texture(Arc::new(SolidColor::new(base_color)))
+    }
+    pub fn new_texture(base_color: Arc<dyn Texture>) -> Self {
+        let constant = Self::constant;
+        Self {
+            base_color,
+            metallic: constant(0.),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            sheen: constant(0.),
+            clearcoat: constant(0.),
+            clearcoat_roughness: constant(0.03),
+            transmission: constant(0.),
+            transmissive: false,
+            ior: 1.5,
+            emission: constant(0.),
+        }
+    }
+    fn constant(value: f64) -> Arc<dyn Texture> {
+        Arc::new(SolidColor::new(Vec3::ones() * value))
+    }
+    pub fn with_metallic(self, metallic: f64) -> Self {
+        self.with_metallic_texture(Self::constant(metallic))
+    }
+    pub fn with_metallic_texture(mut self, metallic: Arc<dyn Texture>) -> Self {
+        self.metallic = metallic;
+        self
+    }
+    pub fn with_roughness(self, roughness: f64) -> Self {
+        self.with_roughness_texture(Self::constant(roughness))
+    }
+    pub fn with_roughness_texture(mut self, roughness: Arc<dyn Texture>) -> Self {
+        self.roughness = roughness;
+        self
+    }
+    pub fn with_specular(self, specular: f64) -> Self {
+        self.with_specular_texture(Self::constant(specular))
+    }
+    pub fn with_specular_texture(mut self, specular: Arc<dyn Texture>) -> Self {
+        self.specular = specular;
+        self
+    }
+    pub fn with_sheen(self, sheen: f64) -> Self {
+        self.with_sheen_texture(Self::constant(sheen))
+    }
+    pub fn with_sheen_texture(mut self, sheen: Arc<dyn Texture>) -> Self {
+        self.sheen = sheen;
+        self
+    }
+    pub fn with_clearcoat(self, clearcoat: f64, roughness: f64) -> Self {
+        self.with_clearcoat_texture(Self::constant(clearcoat), Self::constant(roughness))
+    }
+    pub fn with_clearcoat_texture(
+        mut self,
+        clearcoat: Arc<dyn Texture>,
+        roughness: Arc<dyn Texture>,
+    ) -> Self {
+        self.clearcoat = clearcoat;
+        self.clearcoat_roughness = roughness;
+        self
+    }
+    pub fn with_transmission(self, transmission: f64, ior: f64) -> Self {
+        let mut material = self.with_transmission_texture(Self::constant(transmission), ior);
+        material.transmissive = transmission > 0.;
+        material
+    }
+    pub fn with_transmission_texture(mut self, transmission: Arc<dyn Texture>, ior: f64) -> Self {
+        self.transmission = transmission;
+        self.transmissive = true;
+        self.ior = ior;
+        self
+    }
+    pub fn with_emission(self, emission: Vec3) -> Self {
+        self.with_emission_texture(Arc::new(SolidColor::new(emission)))
+    }
+    /// Emission is on both sides of the surface, like a `DiffuseLight`.
+    pub fn with_emission_texture(mut self, emission: Arc<dyn Texture>) -> Self {
+        self.emission = emission;
+        self
+    }
+    fn parameters(&self, rec: &HitRecord) -> PrincipledParameters {
+        let scalar =
+            |texture: &Arc<dyn Texture>| texture.sample(&rec.texture_point()).x().clamp(0., 1.);
+        PrincipledParameters {
+            base_color: self.base_color.sample(&rec.texture_point()),
+            metallic: scalar(&self.metallic),
+            roughness: scalar(&self.roughness).max(Self::MIN_ROUGHNESS),
+            specular: scalar(&self.specular),
+            sheen: scalar(&self.sheen),
+            clearcoat: scalar(&self.clearcoat),
+            clearcoat_roughness: scalar(&self.clearcoat_roughness).max(Self::MIN_ROUGHNESS),
+            transmission: scalar(&self.transmission),
+        }
+    }
+    /// The probabilities of sampling each lobe.
+    /// They only need to be roughly in proportion to how much light the lobes scatter.
+    fn lobe_probabilities(parameters: &PrincipledParameters) -> [(PrincipledLobe, f64); 4] {
+        let dielectric = 1. - parameters.metallic;
+        let weights = [
+            (
+                PrincipledLobe::Diffuse,
+                dielectric * (1. - parameters.transmission),
+            ),
+            (
+                PrincipledLobe::Specular,
+                dielectric * (1. - parameters.transmission) * 0.25 + parameters.metallic,
+            ),
+            (
+                PrincipledLobe::Transmission,
+                dielectric * parameters.transmission,
+            ),
+            (PrincipledLobe::Clearcoat, 0.25 * parameters.clearcoat),
+        ];
+        let total: f64 = weights.iter().map(|(_, weight)| weight).sum();
+        weights.map(|(lobe, weight)| (lobe, weight / total))
+    }
+    fn distribution(roughness: f64) -> TrowbridgeReitz {
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness);
+        TrowbridgeReitz::new(alpha, alpha)
+    }
+    /// The rough glass of the transmissive part.
+    fn glass(&self, parameters: &PrincipledParameters) -> Dielectric {
+        Dielectric::new(self.ior).with_roughness(parameters.roughness)
+    }
+    fn schlick(f0: Vec3, cosine: f64) -> Vec3 {
+        f0 + (Vec3::ones() - f0) * (1. - cosine).clamp(0., 1.).powi(5)
+    }
+    /// The Fresnel reflectance of the specular lobe,
+    /// blending the reflection of the opaque dielectric with that of the metal.
+    fn specular_fresnel(parameters: &PrincipledParameters, cosine: f64) -> Vec3 {
+        let opaque = (1. - parameters.metallic) * (1. - parameters.transmission);
+        let f0 = 0.08 * parameters.specular;
+        Self::schlick(Vec3::ones() * f0, cosine) * opaque
+            + Self::schlick(parameters.base_color, cosine) * parameters.metallic
+    }
+    fn clearcoat_fresnel(parameters: &PrincipledParameters, cosine: f64) -> Vec3 {
+        Vec3::ones() * (parameters.clearcoat * fresnel_dielectric(cosine, Self::CLEARCOAT_IOR))
+    }
+    /// The fraction of the light that passes through the coat into the base and back out,
+    /// for directions at the cosines `cos_o` and `cos_i` to the normal.
+    fn clearcoat_transmittance(parameters: &PrincipledParameters, cos_o: f64, cos_i: f64) -> f64 {
+        let through = |cosine: f64| {
+            1. - parameters.clearcoat * fresnel_dielectric(cosine.abs(), Self::CLEARCOAT_IOR)
+        };
+        through(cos_o) * through(cos_i)
+    }
+    /// The diffuse and sheen lobes times the cosine of `wi`, all in the local frame.
+    fn diffuse(parameters: &PrincipledParameters, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let weight = (1. - parameters.metallic) * (1. - parameters.transmission);
+        if weight <= 0. || wi.z() <= 0. {
+            return Vec3::zero();
+        }
+        let wh = *wo + *wi;
+        if wh.near_zero() {
+            return Vec3::zero();
+        }
+        let cos_d = *wi * Vec3::unit(wh);
+        // Burley's diffuse gets brighter at grazing angles on rough surfaces.
+        // It is renormalized as in Frostbite (Lagarde and de Rousiers 2014) so that it never gains energy.
+        let roughness = parameters.roughness;
+        let fd90 = 0.5 * roughness + 2. * roughness * cos_d * cos_d;
+        let energy_factor = 1. - roughness * (1. - 1. / 1.51);
+        let retro = |cosine: f64| 1. + (fd90 - 1.) * (1. - cosine).powi(5);
+        // What the specular lobe reflects never reaches the diffuse base.
+        let specular = Self::schlick(Vec3::ones() * (0.08 * parameters.specular), wo.z()).x();
+        let diffuse = parameters.base_color
+            * (retro(wo.z()) * retro(wi.z()) * energy_factor * (1. - specular) / PI);
+        // The sheen is tinted halfway towards the base color.
+        let tint = (Vec3::ones() + parameters.base_color) * 0.5;
+        let sheen = tint * (parameters.sheen * (1. - cos_d).powi(5));
+        (diffuse + sheen) * (weight * wi.z())
+    }
+    /// A GGX reflection lobe times the cosine of `wi`, with the Fresnel term `fresnel`.
+    fn reflection(distribution: &TrowbridgeReitz, fresnel: Vec3, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if wo.z() <= 0. || wi.z() <= 0. {
+            return Vec3::zero();
+        }
+        let wm = Vec3::unit(*wo + *wi);
+        fresnel * (distribution.d(&wm) * distribution.g(wo, wi) / (4. * wo.z()))
+    }
+}
+impl Material for Principled {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let parameters = self.parameters(rec);
+        // Pick a lobe.
+        let probabilities = Self::lobe_probabilities(&parameters);
+        let mut choice = random_double();
+        let (lobe, probability) = probabilities
+            .iter()
+            .copied()
+            .find(|(_, probability)| {
+                choice -= probability;
+                choice < 0.
+            })
+            .unwrap_or(probabilities[3]);
+        if probability <= 0. {
+            return false;
+        }
+        if lobe == PrincipledLobe::Transmission {
+            let dielectric_weight = (1. - parameters.metallic) * parameters.transmission;
+            if !self
+                .glass(&parameters)
+                .scatter(r_in, rec, attenuation, scattered)
+            {
+                return false;
+            }
+            let coat = Self::clearcoat_transmittance(
+                &parameters,
+                rec.normal * Vec3::unit(r_in.direction()),
+                rec.normal * Vec3::unit(scattered.direction()),
+            );
+            *attenuation = Vec3::elemul(*attenuation, parameters.base_color)
+                * (dielectric_weight * coat / probability);
+            return true;
+        }
+
+        let uvw = ONB::build_from_w(&rec.normal);
+        let wo = uvw.world_to_local(&-Vec3::unit(r_in.direction()));
+        if wo.z() <= 0. {
+            return false;
+        }
+        let (wi, weight) = if lobe == PrincipledLobe::Diffuse {
+            // Cosine weighted, so the pdf is cos / π.
+            let wi = Vec3::new(0., 0., 1.) + Vec3::random_unit_vector();
+            let wi = if wi.near_zero() {
+                Vec3::new(0., 0., 1.)
+            } else {
+                Vec3::unit(wi)
+            };
+            (wi, Self::diffuse(&parameters, &wo, &wi) * (PI / wi.z()))
+        } else {
+            let roughness = if lobe == PrincipledLobe::Specular {
+                parameters.roughness
+            } else {
+                parameters.clearcoat_roughness
+            };
+            let distribution = Self::distribution(roughness);
+            let wm = distribution.sample_wm(&wo);
+            let wi = -wo + wm * (2. * (wo * wm));
+            let fresnel = if lobe == PrincipledLobe::Specular {
+                Self::specular_fresnel(&parameters, wo * wm)
+            } else {
+                Self::clearcoat_fresnel(&parameters, wo * wm)
+            };
+            // Sampling the visible normals leaves the Fresnel term and the masking of `wi`.
+            (
+                wi,
+                fresnel * (distribution.g(&wo, &wi) / distribution.g1(&wo)),
+            )
+        };
+        if wi.z() <= 0. {
+            return false;
+        }
+        let weight = if lobe == PrincipledLobe::Clearcoat {
+            weight
+        } else {
+            weight * Self::clearcoat_transmittance(&parameters, wo.z(), wi.z())
+        };
+        *scattered = Ray::new(rec.p, uvw.local_vec(&wi), r_in.time());
+        *attenuation = weight / probability;
         true
     }
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.emission.sample(&rec.texture_point())
+    }
+    fn emitted_power(&self, rec: &HitRecord) -> f64 {
+        luminance(self.emission.sample(&rec.texture_point())) * PI * 2.
+    }
+    /// Transmission goes through rough glass, which hides lights from direct sampling like a `Dielectric`.
+    fn is_specular(&self) -> bool {
+        self.transmissive
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vec3 {
+        let parameters = self.parameters(rec);
+        let uvw = ONB::build_from_w(&rec.normal);
+        let wo = uvw.world_to_local(&-Vec3::unit(r_in.direction()));
+        let wi = uvw.world_to_local(&Vec3::unit(scattered.direction()));
+        let dielectric_weight = (1. - parameters.metallic) * parameters.transmission;
+        // Whatever the coat reflects is taken from the lobes of the base.
+        let coat = Self::clearcoat_transmittance(&parameters, wo.z(), wi.z());
+        let mut f = Vec3::zero();
+        if dielectric_weight > 0. {
+            f += Vec3::elemul(
+                self.glass(&parameters).eval(r_in, rec, scattered),
+                parameters.base_color,
+            ) * (dielectric_weight * coat);
+        }
+        if wo.z() <= 0. || wi.z() <= 0. {
+            return f;
+        }
+        let wm = Vec3::unit(wo + wi);
+        f += Self::diffuse(&parameters, &wo, &wi) * coat;
+        f += Self::reflection(
+            &Self::distribution(parameters.roughness),
+            Self::specular_fresnel(&parameters, wo * wm),
+            &wo,
+            &wi,
+        ) * coat;
+        if parameters.clearcoat > 0. {
+            f += Self::reflection(
+                &Self::distribution(parameters.clearcoat_roughness),
+                Self::clearcoat_fresnel(&parameters, wo * wm),
+                &wo,
+                &wi,
+            );
+        }
+        f
+    }
 }
 
+/// A blend of two materials, where `mask` gives the weight of the second one.
+///
+/// Every scattering picks one of the materials at random, so any material can be mixed.
+/// Direct light sampling can't know which one will be picked,
+/// so a mix with a specular component is specular as a whole.
+pub struct MixMaterial {
+    first: Arc<dyn Material>,
+    second: Arc<dyn Material>,
+    mask: Arc<dyn Texture>,
+}
+impl MixMaterial {
+    pub fn new(first: Arc<dyn Material>, second: Arc<dyn Material>, amount: f64) -> Self {
+        Self::new_texture(
+            first,
+            second,
+            Arc::new(SolidColor::new(Vec3::ones() * amount)),
+        )
+    }
+    /// Mix by the red channel of `mask`.
+    pub fn new_texture(
+        first: Arc<dyn Material>,
+        second: Arc<dyn Material>,
+        mask: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            first,
+            second,
+            mask,
+        }
+    }
+    fn amount(&self, rec: &HitRecord) -> f64 {
+        self.mask.sample(&rec.texture_point()).x().clamp(0., 1.)
+    }
+}
+impl Material for MixMaterial {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        // Picking each material with its weight leaves its throughput as it is.
+        if random_double() < self.amount(rec) {
+            self.second.scatter(r_in, rec, attenuation, scattered)
+        } else {
+            self.first.scatter(r_in, rec, attenuation, scattered)
+        }
+    }
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        let amount = self.amount(rec);
+        self.first.emitted(r_in, rec) * (1. - amount) + self.second.emitted(r_in, rec) * amount
+    }
+    fn emitted_power(&self, rec: &HitRecord) -> f64 {
+        let amount = self.amount(rec);
+        self.first.emitted_power(rec) * (1. - amount) + self.second.emitted_power(rec) * amount
+    }
+    fn is_specular(&self) -> bool {
+        self.first.is_specular() || self.second.is_specular()
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vec3 {
+        let amount = self.amount(rec);
+        self.first.eval(r_in, rec, scattered) * (1. - amount)
+            + self.second.eval(r_in, rec, scattered) * amount
+    }
+}
+/// A clear coat over any `base` material, like varnish or lacquer.
+///
+/// The coat is a rough dielectric interface that reflects by the Fresnel equations,
+/// and what it lets through scatters off the base as usual.
+/// It is thought of as infinitely thin, so it doesn't bend the rays that reach the base.
+pub struct LayeredMaterial {
+    base: Arc<dyn Material>,
+    ior: f64,
+    distribution: TrowbridgeReitz,
+}
+impl LayeredMaterial {
+    /// Like `Principled`, the coat always has a little roughness,
+    /// so that its highlights can be sampled directly.
+    const MIN_ROUGHNESS: f64 = 0.05;
+
+    pub fn new(base: Arc<dyn Material>, ior: f64, roughness: f64) -> Self {
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness.max(Self::MIN_ROUGHNESS));
+        Self {
+            base,
+            ior,
+            distribution: TrowbridgeReitz::new(alpha, alpha),
+        }
+    }
+    /// The fraction of the light the coat reflects towards the local direction `w`.
+    fn coat_reflectance(&self, w: &Vec3) -> f64 {
+        fresnel_dielectric(w.z().abs(), self.ior)
+    }
+}
+impl Material for LayeredMaterial {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let uvw = ONB::build_from_w(&rec.normal);
+        let wo = uvw.world_to_local(&-Vec3::unit(r_in.direction()));
+        if wo.z() <= 0. {
+            return false;
+        }
+        // Pick the coat as often as it reflects.
+        let probability = self.coat_reflectance(&wo);
+        if random_double() < probability {
+            let wm = self.distribution.sample_wm(&wo);
+            let wi = -wo + wm * (2. * (wo * wm));
+            if wi.z() <= 0. {
+                return false;
+            }
+            *scattered = Ray::new(rec.p, uvw.local_vec(&wi), r_in.time());
+            *attenuation = Vec3::ones()
+                * (fresnel_dielectric(wo * wm, self.ior) / probability
+                    * self.distribution.g(&wo, &wi)
+                    / self.distribution.g1(&wo));
+            return true;
+        }
+        if !self.base.scatter(r_in, rec, attenuation, scattered) {
+            return false;
+        }
+        // The light has to get through the coat on its way in and on its way out,
+        // and the first one cancels against the probability of picking the base.
+        let wi = uvw.world_to_local(&Vec3::unit(scattered.direction()));
+        *attenuation *= 1. - self.coat_reflectance(&wi);
+        true
+    }
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        let wo = -Vec3::unit(r_in.direction()) * rec.normal;
+        self.base.emitted(r_in, rec) * (1. - fresnel_dielectric(wo.abs(), self.ior))
+    }
+    fn emitted_power(&self, rec: &HitRecord) -> f64 {
+        self.base.emitted_power(rec)
+    }
+    fn is_specular(&self) -> bool {
+        self.base.is_specular()
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vec3 {
+        let uvw = ONB::build_from_w(&rec.normal);
+        let wo = uvw.world_to_local(&-Vec3::unit(r_in.direction()));
+        let wi = uvw.world_to_local(&Vec3::unit(scattered.direction()));
+        if wo.z() <= 0. {
+            return Vec3::zero();
+        }
+        let base = self.base.eval(r_in, rec, scattered)
+            * ((1. - self.coat_reflectance(&wo)) * (1. - self.coat_reflectance(&wi)));
+        if wi.z() <= 0. {
+            return base;
+        }
+        let wm = Vec3::unit(wo + wi);
+        // The cosine of `wi` cancels against the denominator of the BRDF.
+        base + Vec3::ones()
+            * (fresnel_dielectric(wo * wm, self.ior)
+                * self.distribution.d(&wm)
+                * self.distribution.g(&wo, &wi)
+                / (4. * wo.z()))
+    }
+}
+/// Which sides of a surface a `DiffuseLight` emits from,
+/// relative to the outward normal of the primitive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmissionSides {
+    Front,
+    Back,
+    Both,
+}
+/// How the emitted radiance varies with the angle to the surface normal.
+#[derive(Clone)]
+pub enum EmissionProfile {
+    /// the same radiance in every direction, like an ideal diffuse emitter
+    Uniform,
+    /// a cone of light around the normal, like a recessed spotlight,
+    /// constant up to `falloff_start` degrees and fading out until `total_width` degrees
+    Spot {
+        cos_total_width: f64,
+        cos_falloff_start: f64,
+    },
+    /// the candela distribution of a real fixture pointing along the normal.
+    /// The surface has no preferred tangent, so the horizontal angles of the profile
+    /// start from an arbitrary direction around the normal.
+    Ies(Arc<IesProfile>),
+}
+impl EmissionProfile {
+    pub fn spot(total_width: f64, falloff_start: f64) -> Self {
+        Self::Spot {
+            cos_total_width: degrees_to_radians(total_width).cos(),
+            cos_falloff_start: degrees_to_radians(falloff_start.min(total_width)).cos(),
+        }
+    }
+    /// The factor on the radiance leaving towards the unit direction `w`
+    /// from a surface with the unit `normal`.
+    pub fn scale(&self, normal: &Vec3, w: &Vec3) -> f64 {
+        match self {
+            Self::Uniform => 1.,
+            Self::Spot {
+                cos_total_width,
+                cos_falloff_start,
+            } => spot_falloff(*normal * *w, *cos_total_width, *cos_falloff_start),
+            Self::Ies(profile) => profile.value_towards(&ONB::build_from_w(normal), w),
+        }
+    }
+    /// The fraction of the power of a uniform emitter that is left after the profile,
+    /// integrated over the cosine-weighted hemisphere.
+    fn power_fraction(&self) -> f64 {
+        if let Self::Ies(profile) = self {
+            return profile.power_fraction();
+        }
+        const STEPS: usize = 256;
+        let normal = Vec3::new(0., 0., 1.);
+        let mut fraction = 0.;
+        for i in 0..STEPS {
+            let theta = (i as f64 + 0.5) / STEPS as f64 * PI / 2.;
+            let w = Vec3::new(theta.sin(), 0., theta.cos());
+            fraction += self.scale(&normal, &w) * (2. * theta).sin();
+        }
+        // A uniform profile integrates sin(2θ) over [0, π/2], which is 1.
+        fraction * PI / 2. / STEPS as f64
+    }
+}
 pub struct DiffuseLight {
     emit: Arc<dyn Texture>,
+    sides: EmissionSides,
+    profile: EmissionProfile,
+    /// the power emitted per unit area for a luminance of 1, cached for the light distribution
+    power_per_luminance: f64,
 }
 impl DiffuseLight {
     pub fn new(emit: Vec3) -> Self {
-        Self {
-            emit: Arc::new(SolidColor::new(emit)),
-        }
+        Self::new_texture(Arc::new(SolidColor::new(emit)))
     }
-    #[allow(dead_code)]
     pub fn new_texture(emit: Arc<dyn Texture>) -> Self {
-        Self { emit }
+        Self::new_directional(emit, EmissionSides::Both, EmissionProfile::Uniform)
+    }
+    /// A light that only emits from the given `sides`, following the angular `profile`.
+    pub fn new_directional(
+        emit: Arc<dyn Texture>,
+        sides: EmissionSides,
+        profile: EmissionProfile,
+    ) -> Self {
+        let side_count = if sides == EmissionSides::Both { 2. } else { 1. };
+        Self {
+            emit,
+            sides,
+            power_per_luminance: PI * profile.power_fraction() * side_count,
+            profile,
+        }
     }
 }
 impl Material for DiffuseLight {
@@ -169,22 +1291,44 @@ impl Material for DiffuseLight {
     ) -> bool {
         false
     }
-    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
-        self.emit.value(u, v, p)
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        let emits = match self.sides {
+            EmissionSides::Front => rec.front_face,
+            EmissionSides::Back => !rec.front_face,
+            EmissionSides::Both => true,
+        };
+        if !emits {
+            return Vec3::zero();
+        }
+        // `rec.normal` always faces the incoming ray, so it is the normal of the emitting side.
+        let w = -Vec3::unit(r_in.direction());
+        self.emit.sample(&rec.texture_point()) * self.profile.scale(&rec.normal, &w)
+    }
+    fn emitted_power(&self, rec: &HitRecord) -> f64 {
+        luminance(self.emit.sample(&rec.texture_point())) * self.power_per_luminance
     }
 }
+/// The particles of a participating medium, which scatter the light by their phase function.
+/// The name is from when they could only scatter it uniformly.
+#[derive(Clone)]
 pub struct Isotropic {
     albedo: Arc<dyn Texture>,
+    phase: PhaseFunction,
 }
 impl Isotropic {
     pub fn new(color: Vec3) -> Self {
+        Self::new_texture(Arc::new(SolidColor::new(color)))
+    }
+    /// The scattering is isotropic, unless another phase function is set.
+    pub fn new_texture(texture: Arc<dyn Texture>) -> Self {
         Self {
-            albedo: Arc::new(SolidColor::new(color)),
+            albedo: texture,
+            phase: PhaseFunction::Isotropic,
         }
     }
-    #[allow(dead_code)]
-    pub fn new_texture(texture: Arc<dyn Texture>) -> Self {
-        Self { albedo: texture }
+    pub fn with_phase_function(mut self, phase: PhaseFunction) -> Self {
+        self.phase = phase;
+        self
     }
 }
 impl Material for Isotropic {
@@ -195,8 +1339,175 @@ impl Material for Isotropic {
         attenuation: &mut Vec3,
         scattered: &mut Ray,
     ) -> bool {
-        *scattered = Ray::new(rec.p, Vec3::random_in_unit_sphere(), r_in.time());
-        *attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        // The directions are sampled exactly by the phase function, which leaves only the albedo.
+        let direction = self.phase.sample(&Vec3::unit(r_in.direction()));
+        *scattered = Ray::new(rec.p, direction, r_in.time());
+        *attenuation = self.albedo.sample(&rec.texture_point());
         true
     }
+    fn is_specular(&self) -> bool {
+        false
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vec3 {
+        let cos_theta = Vec3::unit(r_in.direction()) * Vec3::unit(scattered.direction());
+        self.albedo.sample(&rec.texture_point()) * self.phase.p(cos_theta)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::surface_power;
+    /// Average the throughput of many scatterings off a surface facing the ray.
+    fn albedo(material: &dyn Material, incoming: Vec3) -> Vec3 {
+        let r_in = Ray::new(-incoming, incoming, 0.);
+        let mut rec = HitRecord::new(Arc::new(Lambertian::new(Vec3::zero())));
+        rec.set_face_normal(&r_in, Vec3::new(0., 1., 0.));
+        const N: usize = 100000;
+        let mut sum = Vec3::zero();
+        for _ in 0..N {
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::zero();
+            if material.scatter(&r_in, &rec, &mut attenuation, &mut scattered) {
+                sum += attenuation;
+            }
+        }
+        sum / N as f64
+    }
+    #[test]
+    fn test_principled_conserves_energy() {
+        let materials = [
+            Principled::new(Vec3::ones()).with_roughness(1.),
+            Principled::new(Vec3::ones()).with_metallic(1.),
+            Principled::new(Vec3::ones())
+                .with_roughness(0.2)
+                .with_sheen(1.),
+            Principled::new(Vec3::ones()).with_transmission(1., 1.5),
+            // The coat reflects light that then never reaches the base.
+            Principled::new(Vec3::ones())
+                .with_roughness(1.)
+                .with_clearcoat(1., 0.03),
+            Principled::new(Vec3::ones())
+                .with_metallic(1.)
+                .with_clearcoat(1., 0.3),
+        ];
+        // Seen from near the normal and at a grazing angle, where the coat reflects more.
+        let incoming = [Vec3::new(0.5, -1., 0.2), Vec3::new(5., -1., 0.)];
+        for (i, material) in materials.iter().enumerate() {
+            for incoming in incoming {
+                let albedo = albedo(material, Vec3::unit(incoming));
+                for channel in [albedo.x(), albedo.y(), albedo.z()] {
+                    assert!(
+                        channel > 0.5 && channel < 1.01,
+                        "{}: albedo {:?}",
+                        i,
+                        albedo
+                    );
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_layered_and_mixed_albedo() {
+        let incoming = Vec3::unit(Vec3::new(0.3, -1., 0.));
+        let white: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::ones()));
+        let coated = albedo(&LayeredMaterial::new(white.clone(), 1.5, 0.3), incoming);
+        assert!(coated.x() > 0.8 && coated.x() < 1.01, "albedo {:?}", coated);
+        // A white surface half mixed with black is half as bright.
+        let black: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::zero()));
+        let mixed = albedo(&MixMaterial::new(white, black, 0.5), incoming);
+        assert!((mixed.x() - 0.5).abs() < 0.01, "albedo {:?}", mixed);
+    }
+    #[test]
+    fn test_oren_nayar() {
+        let r_in = Ray::new(Vec3::new(-1., 1., 0.), Vec3::new(1., -1., 0.), 0.);
+        let mut rec = HitRecord::new(Arc::new(Lambertian::new(Vec3::zero())));
+        rec.set_face_normal(&r_in, Vec3::new(0., 1., 0.));
+        let scattered = Ray::new(Vec3::zero(), Vec3::new(0.3, 1., 0.4), 0.);
+        let smooth = OrenNayar::new(Vec3::ones(), 0.);
+        let lambertian = Lambertian::new(Vec3::ones());
+        let difference =
+            smooth.eval(&r_in, &rec, &scattered) - lambertian.eval(&r_in, &rec, &scattered);
+        assert!(difference.length() < 1e-12);
+        // Rough surfaces send more light back towards where it comes from.
+        let rough = OrenNayar::new(Vec3::ones(), 30.);
+        let back = Ray::new(Vec3::zero(), Vec3::new(-1., 1., 0.), 0.);
+        let forward = Ray::new(Vec3::zero(), Vec3::new(1., 1., 0.), 0.);
+        assert!(rough.eval(&r_in, &rec, &back).x() > rough.eval(&r_in, &rec, &forward).x());
+        assert!(albedo(&rough, r_in.direction()).x() < 1.);
+    }
+    #[test]
+    fn test_principled_emission() {
+        let material = Principled::new(Vec3::ones()).with_emission(Vec3::ones());
+        let rec = HitRecord::default();
+        assert!((material.emitted_power(&rec) - 2. * PI).abs() < 1e-9);
+        assert_eq!(Principled::new(Vec3::ones()).emitted_power(&rec), 0.);
+    }
+    #[test]
+    fn test_mix_emission() {
+        let dark: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::ones()));
+        let light: Arc<dyn Material> = Arc::new(DiffuseLight::new(Vec3::ones()));
+        let rec = HitRecord::default();
+        let full = light.emitted_power(&rec);
+        // Only the red channel of the mask counts, as it does for the mix itself.
+        let red = Arc::new(SolidColor::new(Vec3::new(1., 0., 0.)));
+        let mixed = MixMaterial::new_texture(dark.clone(), light.clone(), red);
+        assert!((mixed.emitted_power(&rec) - full).abs() < 1e-9);
+        // A mask that is 0 at the origin but 1 on half of a 2 by 2 square.
+        let checker = CheckerTexture::new_rgb(Vec3::zero(), Vec3::ones()).with_scale(1.);
+        let mixed: Arc<dyn Material> =
+            Arc::new(MixMaterial::new_texture(dark, light, Arc::new(checker)));
+        assert_eq!(mixed.emitted_power(&rec), 0.);
+        let power = surface_power(&mixed, 4., |s, t| {
+            (s, t, Vec3::new(2. * s, 0.5, 2. * t), Vec3::new(0., 1., 0.))
+        });
+        assert!((power - 2. * full).abs() < 1e-9, "{}", power);
+    }
+    /// Transmission goes through glass, whose own surface would block direct light samples.
+    #[test]
+    fn test_principled_transmission_is_specular() {
+        let opaque = Principled::new(Vec3::ones());
+        assert!(!opaque.is_specular());
+        assert!(!Principled::new(Vec3::ones())
+            .with_transmission(0., 1.5)
+            .is_specular());
+        assert!(Principled::new(Vec3::ones())
+            .with_transmission(0.5, 1.5)
+            .is_specular());
+        let mask = Arc::new(SolidColor::new(Vec3::ones()));
+        assert!(opaque.with_transmission_texture(mask, 1.5).is_specular());
+    }
+    #[test]
+    fn test_emission_sides() {
+        let white: Arc<dyn Texture> = Arc::new(SolidColor::new(Vec3::ones()));
+        let light =
+            |sides| DiffuseLight::new_directional(white.clone(), sides, EmissionProfile::Uniform);
+        let (front, back, both) = (
+            light(EmissionSides::Front),
+            light(EmissionSides::Back),
+            light(EmissionSides::Both),
+        );
+        let mut rec = HitRecord::new(Arc::new(Lambertian::new(Vec3::zero())));
+        let outward = Vec3::new(0., 1., 0.);
+        let from_above = Ray::new(Vec3::new(0., 1., 0.), Vec3::new(0., -1., 0.), 0.);
+        rec.set_face_normal(&from_above, outward);
+        assert_eq!(front.emitted(&from_above, &rec), Vec3::ones());
+        assert_eq!(back.emitted(&from_above, &rec), Vec3::zero());
+        assert_eq!(both.emitted(&from_above, &rec), Vec3::ones());
+        let from_below = Ray::new(Vec3::new(0., -1., 0.), Vec3::new(0., 1., 0.), 0.);
+        rec.set_face_normal(&from_below, outward);
+        assert_eq!(front.emitted(&from_below, &rec), Vec3::zero());
+        assert_eq!(back.emitted(&from_below, &rec), Vec3::ones());
+        assert_eq!(both.emitted(&from_below, &rec), Vec3::ones());
+        // A white uniform emitter sends out π per unit area from each side.
+        assert!((front.emitted_power(&rec) - PI).abs() < 1e-3);
+        assert!((back.emitted_power(&rec) - PI).abs() < 1e-3);
+        assert!((both.emitted_power(&rec) - 2. * PI).abs() < 1e-3);
+        // A 60° spot keeps 1 - cos²(60°) of that, less the fading part.
+        let spot = DiffuseLight::new_directional(
+            white.clone(),
+            EmissionSides::Front,
+            EmissionProfile::spot(60., 60.),
+        );
+        assert!((spot.emitted_power(&rec) - 0.75 * PI).abs() < 1e-2);
+    }
 }
@@ -0,0 +1,253 @@
+use crate::rt_weekend::*;
+use crate::vec3::Vec3;
+/// The GGX (Trowbridge-Reitz) distribution of microfacet normals.
+///
+/// Every direction here is in the local shading frame,
+/// where the macroscopic surface normal is the z-axis
+/// and the two roughnesses stretch the distribution along x and y.
+#[derive(Clone, Copy, Debug)]
+pub struct TrowbridgeReitz {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+impl TrowbridgeReitz {
+    /// Below this alpha the surface is treated as a perfect mirror,
+    /// since the distribution becomes too sharp to evaluate.
+    const SMOOTH_ALPHA: f64 = 1e-3;
+
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        Self { alpha_x, alpha_y }
+    }
+    /// Map a perceptual roughness in [0,1] to the alpha of the distribution.
+    pub fn roughness_to_alpha(roughness: f64) -> f64 {
+        roughness * roughness
+    }
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < Self::SMOOTH_ALPHA
+    }
+    /// The density of microfacets with normal `wm`.
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        if wm.z() <= 0. {
+            return 0.;
+        }
+        let x = wm.x() / self.alpha_x;
+        let y = wm.y() / self.alpha_y;
+        let denominator = x * x + y * y + wm.z() * wm.z();
+        1. / (PI * self.alpha_x * self.alpha_y * denominator * denominator)
+    }
+    /// The Smith auxiliary function, how much microfacet area is hidden when seen from `w`.
+    pub fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2 = w.z() * w.z();
+        if cos2 == 0. {
+            return f64::INFINITY;
+        }
+        let ax = self.alpha_x * w.x();
+        let ay = self.alpha_y * w.y();
+        ((1. + (ax * ax + ay * ay) / cos2).sqrt() - 1.) / 2.
+    }
+    /// The fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1. / (1. + self.lambda(w))
+    }
+    /// The fraction of microfacets visible from both `wo` and `wi`.
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+    /// Sample a microfacet normal from the distribution of normals visible from `wo`,
+    /// following Heitz, "Sampling the GGX Distribution of Visible Normals" (2018).
+    pub fn sample_wm(&self, wo: &Vec3) -> Vec3 {
+        // Stretch the view direction so the distribution becomes a hemisphere.
+        let vh = Vec3::unit(Vec3::new(
+            self.alpha_x * wo.x(),
+            self.alpha_y * wo.y(),
+            wo.z(),
+        ));
+        let length_squared = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if length_squared > 0. {
+            Vec3::new(-vh.y(), vh.x(), 0.) / length_squared.sqrt()
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let t2 = Vec3::cross(vh, t1);
+        // Sample the projected area of the hemisphere.
+        let r = random_double().sqrt();
+        let phi = 2. * PI * random_double();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1. + vh.z());
+        let p2 = (1. - s) * (1. - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1. - p1 * p1 - p2 * p2).max(0.).sqrt();
+        // Unstretch back to the ellipsoid.
+        Vec3::unit(Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        ))
+    }
+}
+/// The Fresnel reflectance of a conductor with the complex index of refraction `eta + i k`,
+/// for light arriving at `cos_theta` to the normal.
+///
+/// This is the exact form of the Fresnel equations for an absorbing medium,
+/// averaged over both polarizations.
+pub fn fresnel_conductor(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = (cos_theta * cos_theta).min(1.);
+    let sin2 = 1. - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4. * eta * eta * k * k).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+    let t2 = 2. * cos_theta * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+/// `fresnel_conductor` for each of the RGB channels.
+pub fn fresnel_conductor_rgb(cos_theta: f64, eta: &Vec3, k: &Vec3) -> Vec3 {
+    Vec3::new(
+        fresnel_conductor(cos_theta, eta.x(), k.x()),
+        fresnel_conductor(cos_theta, eta.y(), k.y()),
+        fresnel_conductor(cos_theta, eta.z(), k.z()),
+    )
+}
+/// The Fresnel reflectance of a dielectric interface for light arriving at `cos_theta` to the normal,
+/// where `eta` is the index of refraction on the far side over the one on the near side.
+///
+/// Returns 1 under total internal reflection.
+pub fn fresnel_dielectric(cos_theta: f64, eta: f64) -> f64 {
+    let cos_i = cos_theta.clamp(0., 1.);
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+/// The wavelengths in nanometers that stand for the red, green and blue channels.
+const RGB_WAVELENGTHS: [f64; 3] = [650., 550., 450.];
+/// The reflectance of a thin non-absorbing film of index `film_ior` and `thickness` nanometers
+/// lying between the media of index `eta_outside` (where the light comes from) and `eta_inside`.
+///
+/// The light bouncing between the two faces of the film interferes with itself,
+/// which is what colors soap bubbles and the coatings of camera lenses.
+pub fn thin_film_reflectance(
+    cos_theta: f64,
+    eta_outside: f64,
+    film_ior: f64,
+    eta_inside: f64,
+    thickness: f64,
+) -> Vec3 {
+    let cos1 = cos_theta.clamp(0., 1.);
+    let sin2_1 = 1. - cos1 * cos1;
+    let sin2_2 = sin2_1 * (eta_outside / film_ior).powi(2);
+    let sin2_3 = sin2_1 * (eta_outside / eta_inside).powi(2);
+    // Whatever is totally reflected inside the film never gets through.
+    if sin2_2 >= 1. || sin2_3 >= 1. {
+        return Vec3::ones();
+    }
+    let cos2 = (1. - sin2_2).sqrt();
+    let cos3 = (1. - sin2_3).sqrt();
+    // The amplitude reflection coefficients of both faces for each polarization.
+    let s = |n_i: f64, cos_i: f64, n_j: f64, cos_j: f64| {
+        (n_i * cos_i - n_j * cos_j) / (n_i * cos_i + n_j * cos_j)
+    };
+    let p = |n_i: f64, cos_i: f64, n_j: f64, cos_j: f64| {
+        (n_j * cos_i - n_i * cos_j) / (n_j * cos_i + n_i * cos_j)
+    };
+    let polarizations = [
+        (
+            s(eta_outside, cos1, film_ior, cos2),
+            s(film_ior, cos2, eta_inside, cos3),
+        ),
+        (
+            p(eta_outside, cos1, film_ior, cos2),
+            p(film_ior, cos2, eta_inside, cos3),
+        ),
+    ];
+    let reflectance = |wavelength: f64| {
+        // the phase difference picked up by one round trip through the film
+        let delta = 4. * PI * film_ior * thickness * cos2 / wavelength;
+        let mut sum = 0.;
+        for (r12, r23) in polarizations {
+            let cross = 2. * r12 * r23 * delta.cos();
+            sum += (r12 * r12 + r23 * r23 + cross) / (1. + r12 * r12 * r23 * r23 + cross);
+        }
+        0.5 * sum
+    };
+    Vec3::new(
+        reflectance(RGB_WAVELENGTHS[0]),
+        reflectance(RGB_WAVELENGTHS[1]),
+        reflectance(RGB_WAVELENGTHS[2]),
+    )
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_fresnel_conductor_normal_incidence() {
+        let (eta, k) = (0.2, 3.9);
+        let expected = ((eta - 1.) * (eta - 1.) + k * k) / ((eta + 1.) * (eta + 1.) + k * k);
+        assert!((fresnel_conductor(1., eta, k) - expected).abs() < 1e-12);
+        // Every conductor turns into a mirror at grazing angles.
+        assert!((fresnel_conductor(0., eta, k) - 1.).abs() < 1e-12);
+    }
+    /// The projected area of the microfacets must add up to the area of the surface.
+    #[test]
+    fn test_distribution_is_normalized() {
+        let distribution = TrowbridgeReitz::new(0.3, 0.6);
+        // Integrate D(wm) cos(θm) over the hemisphere with a midpoint rule.
+        const STEPS: usize = 400;
+        let mut integral = 0.;
+        for i in 0..STEPS {
+            let theta = (i as f64 + 0.5) / STEPS as f64 * PI / 2.;
+            for j in 0..STEPS {
+                let phi = (j as f64 + 0.5) / STEPS as f64 * 2. * PI;
+                let wm = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                integral += distribution.d(&wm) * theta.cos() * theta.sin();
+            }
+        }
+        integral *= (PI / 2. / STEPS as f64) * (2. * PI / STEPS as f64);
+        assert!((integral - 1.).abs() < 1e-2);
+    }
+    #[test]
+    fn test_visible_normals_face_the_viewer() {
+        let distribution = TrowbridgeReitz::new(0.5, 0.1);
+        let wo = Vec3::unit(Vec3::new(0.6, -0.3, 0.4));
+        for _ in 0..1000 {
+            let wm = distribution.sample_wm(&wo);
+            assert!((wm.length() - 1.).abs() < 1e-9);
+            assert!(wm * wo > 0.);
+        }
+    }
+    #[test]
+    fn test_fresnel_dielectric() {
+        let expected = (0.5_f64 / 2.5).powi(2);
+        assert!((fresnel_dielectric(1., 1.5) - expected).abs() < 1e-12);
+        // Seen from inside the glass, light beyond the critical angle is totally reflected.
+        assert_eq!(fresnel_dielectric(0.5, 1. / 1.5), 1.);
+        assert!(fresnel_dielectric(0.9, 1. / 1.5) < 1.);
+    }
+    #[test]
+    fn test_thin_film() {
+        // A film without thickness is just the interface between the outer media.
+        let bare = thin_film_reflectance(0.8, 1., 1.38, 1.5, 0.);
+        let expected = fresnel_dielectric(0.8, 1.5);
+        for channel in [bare.x(), bare.y(), bare.z()] {
+            assert!((channel - expected).abs() < 1e-12);
+        }
+        // A quarter-wave layer of magnesium fluoride is an anti-reflective coating for green.
+        let coated = thin_film_reflectance(1., 1., 1.38, 1.5, 550. / 4. / 1.38);
+        let expected = (1.5 - 1.38 * 1.38) / (1.5 + 1.38 * 1.38);
+        assert!((coated.y() - expected * expected).abs() < 1e-9);
+        // A soap film in air reflects the colors differently.
+        let bubble = thin_film_reflectance(1., 1., 1.33, 1., 400.);
+        assert!((bubble.x() - bubble.y()).abs() > 0.01);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::hit::HitRecord;
+use crate::material::Material;
+use crate::texture::Texture;
+use crate::{Ray, Vec3};
+use std::sync::Arc;
+
+/// Any `material` shaded with the normals of a tangent space normal map,
+/// which adds surface detail without adding geometry.
+///
+/// The texture holds the normal in the frame of the tangent along U, the tangent along V
+/// and the normal of the surface, each component mapped from [-1,1] to [0,1] as usual,
+/// so it should be loaded with `ImageTexture::new_non_color`.
+pub struct NormalMap {
+    material: Arc<dyn Material>,
+    normals: Arc<dyn Texture>,
+    strength: f64,
+}
+impl NormalMap {
+    pub fn new(material: Arc<dyn Material>, normals: Arc<dyn Texture>) -> Self {
+        Self {
+            material,
+            normals,
+            strength: 1.,
+        }
+    }
+    /// Scale the tilt of the normals, from 0 for a flat surface.
+    pub fn with_strength(mut self, strength: f64) -> Self {
+        self.strength = strength;
+        self
+    }
+}
+impl ShadingNormal for NormalMap {
+    fn outward_normal(&self, rec: &HitRecord, normal: Vec3) -> Vec3 {
+        let tangent = (rec.dpdu - normal * (normal * rec.dpdu)).unit();
+        let mut bitangent = Vec3::cross(normal, tangent);
+        if bitangent * rec.dpdv < 0. {
+            bitangent = -bitangent;
+        }
+        let m = self.normals.sample(&rec.texture_point()) * 2. - Vec3::ones();
+        if m.z() <= 0. {
+            return normal;
+        }
+        (tangent * (m.x() * self.strength) + bitangent * (m.y() * self.strength) + normal * m.z())
+            .unit()
+    }
+}
+/// Any `material` shaded as if its surface were displaced along the normal by a height field,
+/// like a `TurbulenceTexture` for hammered metal or rough plaster.
+///
+/// The height is the red channel of the texture times `scale`, in world units.
+/// Its slope is taken by finite differences along U and V.
+pub struct BumpMap {
+    material: Arc<dyn Material>,
+    height: Arc<dyn Texture>,
+    scale: f64,
+}
+impl BumpMap {
+    pub fn new(material: Arc<dyn Material>, height: Arc<dyn Texture>, scale: f64) -> Self {
+        Self {
+            material,
+            height,
+            scale,
+        }
+    }
+}
+impl ShadingNormal for BumpMap {
+    fn outward_normal(&self, rec: &HitRecord, normal: Vec3) -> Vec3 {
+        // The steps are as small as the footprint, which smooths the bumps where it is wide.
+        let delta = (0.5 * rec.uv_footprint).max(0.0005);
+        let height = |u: f64, v: f64, p: Vec3| self.height.value(u, v, &p).x() * self.scale;
+        let base = height(rec.u, rec.v, rec.p);
+        let dhdu = (height(rec.u + delta, rec.v, rec.p + rec.dpdu * delta) - base) / delta;
+        let dhdv = (height(rec.u, rec.v + delta, rec.p + rec.dpdv * delta) - base) / delta;
+        // The normal of the displaced surface, leaving out how the normal itself turns.
+        let bumped = Vec3::cross(rec.dpdu + normal * dhdu, rec.dpdv + normal * dhdv);
+        if bumped.near_zero() {
+            return normal;
+        }
+        // The tangents may be left handed, so turn it to the side of the surface normal.
+        if bumped * normal < 0. {
+            -bumped.unit()
+        } else {
+            bumped.unit()
+        }
+    }
+}
+/// A material that hands its hits over to another one with a different shading normal.
+trait ShadingNormal {
+    /// The shading normal on the outside of the surface, given the outward geometric `normal`.
+    fn outward_normal(&self, rec: &HitRecord, normal: Vec3) -> Vec3;
+    /// The hit with its normal replaced by the shading normal, facing against the ray as before.
+    ///
+    /// A normal tilted away from the ray by more than it arrives at would put the ray
+    /// behind the surface it hits, so it is turned back until the ray sees its front.
+    fn shade(&self, r_in: &Ray, rec: &HitRecord) -> HitRecord {
+        /// the least cosine between the shading normal and the direction back along the ray
+        const MIN_COSINE: f64 = 0.01;
+        let mut shaded = rec.clone();
+        if rec.dpdu.near_zero() || rec.dpdv.near_zero() {
+            return shaded;
+        }
+        let outward = if rec.front_face {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+        let normal = self.outward_normal(rec, outward);
+        let normal = if rec.front_face { normal } else { -normal };
+        let wo = -r_in.direction().unit();
+        let cosine = normal * wo;
+        shaded.normal = if cosine < MIN_COSINE {
+            (normal + wo * (MIN_COSINE - cosine)).unit()
+        } else {
+            normal
+        };
+        shaded
+    }
+}
+impl Material for NormalMap {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.material
+            .scatter(r_in, &self.shade(r_in, rec), attenuation, scattered)
+    }
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.material.emitted(r_in, &self.shade(r_in, rec))
+    }
+    fn emitted_power(&self, rec: &HitRecord) -> f64 {
+        self.material.emitted_power(rec)
+    }
+    fn is_specular(&self) -> bool {
+        self.material.is_specular()
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vec3 {
+        self.material.eval(r_in, &self.shade(r_in, rec), scattered)
+    }
+}
+impl Material for BumpMap {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.material
+            .scatter(r_in, &self.shade(r_in, rec), attenuation, scattered)
+    }
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.material.emitted(r_in, &self.shade(r_in, rec))
+    }
+    fn emitted_power(&self, rec: &HitRecord) -> f64 {
+        self.material.emitted_power(rec)
+    }
+    fn is_specular(&self) -> bool {
+        self.material.is_specular()
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vec3 {
+        self.material.eval(r_in, &self.shade(r_in, rec), scattered)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XZRectangle;
+    use crate::hit::Hittable;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::texture::SolidColor;
+    fn ray_from_above() -> Ray {
+        Ray::new(Vec3::new(0.3, 5., 0.2), Vec3::new(0., -1., 0.), 0.)
+    }
+    fn hit_from_above(object: &dyn Hittable) -> HitRecord {
+        let mut rec = HitRecord::default();
+        assert!(object.hit(&ray_from_above(), 0.001, f64::INFINITY, &mut rec));
+        rec
+    }
+    /// The tangents of a sphere follow its U,V, and are perpendicular to its normal.
+    #[test]
+    fn test_sphere_tangents() {
+        let unused: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::zero()));
+        let sphere = Sphere::new(Vec3::zero(), 2., unused);
+        let rec = hit_from_above(&sphere);
+        assert!((rec.dpdu * rec.normal).abs() < 1e-9);
+        assert!((rec.dpdv * rec.normal).abs() < 1e-9);
+        let step = 1e-5;
+        let moved = rec.p + rec.dpdu * step;
+        let (mut u, mut v) = (0., 0.);
+        crate::sphere::get_sphere_uv(moved / 2., &mut u, &mut v);
+        assert!((u - rec.u - step).abs() < 1e-7, "{} {}", u, rec.u);
+    }
+    #[test]
+    fn test_shading_normals() {
+        let unused: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::zero()));
+        let floor = XZRectangle::new(-1., 1., -1., 1., 0., unused.clone());
+        let ray = ray_from_above();
+        let rec = hit_from_above(&floor);
+        // A flat normal map and a flat bump map change nothing.
+        let flat = NormalMap::new(
+            unused.clone(),
+            Arc::new(SolidColor::new(Vec3::new(0.5, 0.5, 1.))),
+        );
+        assert!((flat.shade(&ray, &rec).normal - rec.normal).length() < 1e-9);
+        let flat = BumpMap::new(unused.clone(), Arc::new(SolidColor::new(Vec3::ones())), 1.);
+        assert!((flat.shade(&ray, &rec).normal - rec.normal).length() < 1e-9);
+        // A normal tilted along U leans along x, which is U on the floor.
+        let tilted = NormalMap::new(
+            unused.clone(),
+            Arc::new(SolidColor::new(Vec3::new(1., 0.5, 1.))),
+        );
+        let normal = tilted.shade(&ray, &rec).normal;
+        assert!((normal - Vec3::new(1., 1., 0.).unit()).length() < 1e-9);
+        // A ramp rising along x tilts the normal back against it.
+        struct Ramp;
+        impl Texture for Ramp {
+            fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+                Vec3::ones() * p.x()
+            }
+        }
+        let ramp = BumpMap::new(unused, Arc::new(Ramp), 1.);
+        let normal = ramp.shade(&ray, &rec).normal;
+        assert!((normal - Vec3::new(-1., 1., 0.).unit()).length() < 1e-6);
+    }
+    /// Seen at a grazing angle, a normal tilted away from the ray is turned back towards it.
+    #[test]
+    fn test_visible_normal() {
+        let unused: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::zero()));
+        let floor = XZRectangle::new(-1., 1., -1., 1., 0., unused.clone());
+        let ray = Ray::new(Vec3::new(-5., 0.5, 0.), Vec3::new(10., -1., 0.), 0.);
+        let mut rec = HitRecord::default();
+        assert!(floor.hit(&ray, 0.001, f64::INFINITY, &mut rec));
+        // Tilted by 45° along U, which is x, away from where the ray comes from.
+        let tilted = NormalMap::new(unused, Arc::new(SolidColor::new(Vec3::new(1., 0.5, 1.))));
+        let normal = tilted.shade(&ray, &rec).normal;
+        let cosine = normal * -ray.direction().unit();
+        assert!(cosine > 0. && cosine < 0.02, "{}", cosine);
+        assert!((normal.length() - 1.).abs() < 1e-9 && normal.y() > 0.);
+    }
+}
@@ -0,0 +1,47 @@
+use crate::vec3::Vec3;
+/// Ortho-normal bases
+///
+/// An orthonormal basis (ONB) is a collection of three mutually orthogonal unit vectors.
+/// We build one around a given direction `w` so that
+/// directions generated relative to the z-axis can be carried into world space.
+#[derive(Clone, Debug, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct ONB {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+impl ONB {
+    pub fn build_from_w(n: &Vec3) -> Self {
+        let w = Vec3::unit(*n);
+        // Pick any axis that is not (almost) parallel to w.
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0., 1., 0.)
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let v = Vec3::unit(Vec3::cross(w, a));
+        let u = Vec3::cross(w, v);
+        Self { u, v, w }
+    }
+    pub fn u(&self) -> Vec3 {
+        self.u
+    }
+    pub fn v(&self) -> Vec3 {
+        self.v
+    }
+    pub fn w(&self) -> Vec3 {
+        self.w
+    }
+    /// Transform the local coordinates (a, b, c) into world space.
+    pub fn local(&self, a: f64, b: f64, c: f64) -> Vec3 {
+        self.u * a + self.v * b + self.w * c
+    }
+    pub fn local_vec(&self, a: &Vec3) -> Vec3 {
+        self.local(a.x(), a.y(), a.z())
+    }
+    /// Transform a world space vector into the local coordinates of this basis.
+    pub fn world_to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(*a * self.u, *a * self.v, *a * self.w)
+    }
+}
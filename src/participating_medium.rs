@@ -0,0 +1,305 @@
+use crate::constant_medium::inside_intervals;
+use crate::heterogeneous_medium::delta_track;
+use crate::hit::*;
+use crate::rt_weekend::*;
+use crate::{aabb::AABB, material::*, phase::PhaseFunction, texture::*, Ray, Vec3};
+use std::sync::Arc;
+
+/// A participating medium with its own absorption, scattering and emission coefficients
+/// for every channel, any of which may vary in space.
+/// It can be fire or glowing gas, which emit light, or a tinted liquid, which only absorbs it.
+///
+/// Collisions are sampled by delta tracking as if every channel had the extinction
+/// of the one that has the least of it, and those are all that `hit` finds.
+/// What the other channels lose on top of that, and the light emitted on the way,
+/// is then estimated along each segment of the path by ratio tracking, in `segment`.
+pub struct ParticipatingMedium {
+    boundary: Arc<dyn Hittable>,
+    coefficients: Arc<Coefficients>,
+    /// the light emitted per unit length
+    emission: Option<Arc<dyn Texture>>,
+    /// the extinction that no channel goes over anywhere inside
+    majorant: f64,
+    phase: PhaseFunction,
+    /// the materials of the collisions, made once for all of them
+    scattered: Arc<dyn Material>,
+    absorbed: Arc<dyn Material>,
+}
+impl ParticipatingMedium {
+    /// A homogeneous medium.
+    pub fn new(boundary: Arc<dyn Hittable>, absorption: Vec3, scattering: Vec3) -> Self {
+        Self::new_texture(
+            boundary,
+            Arc::new(SolidColor::new(absorption)),
+            Arc::new(SolidColor::new(scattering)),
+            max_component(absorption + scattering),
+        )
+    }
+    /// The coefficients are looked up at the points inside, like the density of a `HeterogeneousMedium`,
+    /// and their sum must not go over `majorant` in any channel.
+    pub fn new_texture(
+        boundary: Arc<dyn Hittable>,
+        absorption: Arc<dyn Texture>,
+        scattering: Arc<dyn Texture>,
+        majorant: f64,
+    ) -> Self {
+        let coefficients = Arc::new(Coefficients {
+            absorption,
+            scattering,
+        });
+        Self {
+            boundary,
+            scattered: Self::scattered(&coefficients, PhaseFunction::Isotropic),
+            coefficients,
+            emission: None,
+            majorant,
+            phase: PhaseFunction::Isotropic,
+            absorbed: Arc::new(Absorbed),
+        }
+    }
+    fn scattered(coefficients: &Arc<Coefficients>, phase: PhaseFunction) -> Arc<dyn Material> {
+        Arc::new(Isotropic::new_texture(coefficients.clone()).with_phase_function(phase))
+    }
+    pub fn with_emission(self, emission: Vec3) -> Self {
+        self.with_emission_texture(Arc::new(SolidColor::new(emission)))
+    }
+    pub fn with_emission_texture(mut self, emission: Arc<dyn Texture>) -> Self {
+        self.emission = Some(emission);
+        self
+    }
+    pub fn with_phase_function(mut self, phase: PhaseFunction) -> Self {
+        self.phase = phase;
+        self.scattered = Self::scattered(&self.coefficients, phase);
+        self
+    }
+    fn coefficients(&self, p: &Vec3) -> (Vec3, Vec3) {
+        self.coefficients.at(p)
+    }
+}
+struct Coefficients {
+    absorption: Arc<dyn Texture>,
+    scattering: Arc<dyn Texture>,
+}
+impl Coefficients {
+    /// The absorption and scattering coefficients at `p`.
+    fn at(&self, p: &Vec3) -> (Vec3, Vec3) {
+        let non_negative = |v: Vec3| Vec3::new(v.x().max(0.), v.y().max(0.), v.z().max(0.));
+        (
+            non_negative(self.absorption.value(0., 0., p)),
+            non_negative(self.scattering.value(0., 0., p)),
+        )
+    }
+}
+/// As a texture, the coefficients give the albedo of a scattering collision at `p`:
+/// the scattering over the extinction of the channel `hit` samples,
+/// and over the probability of scattering rather than being absorbed.
+impl Texture for Coefficients {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        let (absorption, scattering) = self.at(p);
+        let extinction = absorption + scattering;
+        let scatter_probability = average(scattering) / average(extinction);
+        scattering / (min_component(extinction) * scatter_probability)
+    }
+}
+fn min_component(v: Vec3) -> f64 {
+    v.x().min(v.y()).min(v.z())
+}
+fn max_component(v: Vec3) -> f64 {
+    v.x().max(v.y()).max(v.z())
+}
+fn average(v: Vec3) -> f64 {
+    (v.x() + v.y() + v.z()) / 3.
+}
+impl Hittable for ParticipatingMedium {
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.boundary.bounding_box(time0, time1, output_box)
+    }
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let collision = delta_track(
+            self.boundary.as_ref(),
+            self.majorant,
+            ray,
+            t_min,
+            t_max,
+            |t| {
+                let (absorption, scattering) = self.coefficients(&ray.at(t));
+                random_double() * self.majorant < min_component(absorption + scattering)
+            },
+        );
+        let t = match collision {
+            Some(t) => t,
+            None => return false,
+        };
+        rec.t = t;
+        rec.p = ray.at(t);
+        rec.normal = Vec3::new(1., 0., 0.); // arbitrary
+        rec.front_face = true; // also arbitrary
+        rec.sampled_light = false;
+
+        // The light scatters with the probability of the average albedo, and is absorbed otherwise.
+        let (absorption, scattering) = self.coefficients(&rec.p);
+        let extinction = absorption + scattering;
+        let scatter_probability = average(scattering) / average(extinction);
+        rec.material = if random_double() < scatter_probability {
+            self.scattered.clone()
+        } else {
+            self.absorbed.clone()
+        };
+        true
+    }
+    /// Ratio tracking, with every channel on its own.
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec3 {
+        let mut transmittance = Vec3::ones();
+        delta_track(
+            self.boundary.as_ref(),
+            self.majorant,
+            ray,
+            t_min,
+            t_max,
+            |t| {
+                let (absorption, scattering) = self.coefficients(&ray.at(t));
+                let null = Vec3::ones() - (absorption + scattering) / self.majorant;
+                transmittance = Vec3::elemul(transmittance, null);
+                // Russian roulette, once there is little light left.
+                if max_component(transmittance) < 0.1 {
+                    if random_double() < 0.5 {
+                        transmittance = Vec3::zero();
+                        return true;
+                    }
+                    transmittance *= 2.;
+                }
+                false
+            },
+        );
+        transmittance
+    }
+    /// The transmittance of each channel over that of the channel with the least extinction,
+    /// and the emission dimmed by it, which makes up for `hit` only sampling that channel.
+    fn segment(&self, ray: &Ray, t_min: f64, t_max: f64) -> Segment {
+        let ray_length = ray.direction().length();
+        let mut segment = Segment::default();
+        for (t0, t1) in inside_intervals(self.boundary.as_ref(), ray, t_min, t_max) {
+            // The emission is sampled at a single point, uniformly along the interval.
+            let mut emission_at = self
+                .emission
+                .as_ref()
+                .map(|emission| (emission, t0 + random_double() * (t1 - t0)));
+            let mut t = t0;
+            loop {
+                let next = if self.majorant > 0. {
+                    t - (1. - random_double()).ln() / (self.majorant * ray_length)
+                } else {
+                    f64::INFINITY
+                };
+                if let Some((emission, t_emission)) = emission_at {
+                    if t_emission < next {
+                        let p = ray.at(t_emission);
+                        segment.emission += Vec3::elemul(
+                            segment.transmittance,
+                            emission.value(0., 0., &p) * ((t1 - t0) * ray_length),
+                        );
+                        emission_at = None;
+                    }
+                }
+                if next >= t1 {
+                    break;
+                }
+                t = next;
+                let (absorption, scattering) = self.coefficients(&ray.at(t));
+                let extinction = absorption + scattering;
+                let excess = extinction - Vec3::ones() * min_component(extinction);
+                segment.transmittance =
+                    Vec3::elemul(segment.transmittance, Vec3::ones() - excess / self.majorant);
+            }
+        }
+        segment
+    }
+    fn has_segments(&self) -> bool {
+        true
+    }
+}
+/// A collision that absorbs the light, ending the path.
+struct Absorbed;
+impl Material for Absorbed {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _attenuation: &mut Vec3,
+        _scattered: &mut Ray,
+    ) -> bool {
+        false
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    /// Many estimates of the light through a sphere of radius 1 toward a ray that crosses its center,
+    /// when the light behind it is 1 for every channel, which is how `ray_color` goes
+    /// when the medium neither scatters nor is lit.
+    fn radiance_through(medium: &ParticipatingMedium) -> Vec3 {
+        let ray = Ray::new(Vec3::new(0., 0., -5.), Vec3::new(0., 0., 1.), 0.);
+        const N: usize = 50000;
+        let mut sum = Vec3::zero();
+        for _ in 0..N {
+            let mut rec = HitRecord::default();
+            if medium.hit(&ray, 0.001, f64::INFINITY, &mut rec) {
+                let mut attenuation = Vec3::zero();
+                let mut scattered = Ray::zero();
+                assert!(!rec
+                    .material
+                    .scatter(&ray, &rec, &mut attenuation, &mut scattered));
+                sum += medium.segment(&ray, 0.001, rec.t).apply(Vec3::zero());
+            } else {
+                sum += medium
+                    .segment(&ray, 0.001, f64::INFINITY)
+                    .apply(Vec3::ones());
+            }
+        }
+        sum / N as f64
+    }
+    fn sphere() -> Arc<dyn Hittable> {
+        let unused: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::zero()));
+        Arc::new(Sphere::new(Vec3::zero(), 1., unused))
+    }
+    fn assert_close(a: Vec3, b: Vec3) {
+        for (a, b) in [(a.x(), b.x()), (a.y(), b.y()), (a.z(), b.z())] {
+            assert!((a - b).abs() < 0.02, "{} {}", a, b);
+        }
+    }
+    #[test]
+    fn test_tinted_absorption() {
+        let absorption = Vec3::new(0.2, 0.6, 1.2);
+        let medium = ParticipatingMedium::new(sphere(), absorption, Vec3::zero());
+        let expected = Vec3::new(
+            (-2. * absorption.x()).exp(),
+            (-2. * absorption.y()).exp(),
+            (-2. * absorption.z()).exp(),
+        );
+        assert_close(radiance_through(&medium), expected);
+        let ray = Ray::new(Vec3::new(0., 0., -5.), Vec3::new(0., 0., 1.), 0.);
+        const N: usize = 20000;
+        let mut sum = Vec3::zero();
+        for _ in 0..N {
+            sum += medium.transmittance(&ray, 0.001, f64::INFINITY);
+        }
+        assert_close(sum / N as f64, expected);
+    }
+    /// The light emitted along a stretch of length d with absorption σ is (1 - e^(-σd)) / σ.
+    #[test]
+    fn test_emission() {
+        let absorption = Vec3::new(0.3, 0.8, 1.5);
+        let medium = ParticipatingMedium::new(sphere(), absorption, Vec3::zero())
+            .with_emission(Vec3::ones());
+        let expected = |sigma: f64| (1. - (-2. * sigma).exp()) / sigma + (-2. * sigma).exp();
+        assert_close(
+            radiance_through(&medium),
+            Vec3::new(
+                expected(absorption.x()),
+                expected(absorption.y()),
+                expected(absorption.z()),
+            ),
+        );
+    }
+}
@@ -12,33 +12,64 @@ static POINT_COUNT: usize = 256;
 pub struct Perlin {
     /// Using Random Vectors on the Lattice Points
     ran_vec: Vec<Vec3>,
+    /// random unit vectors in four dimensions, for noise that changes with time
+    ran_vec4: Vec<[f64; 4]>,
     perm_x: Vec<i32>,
     perm_y: Vec<i32>,
     perm_z: Vec<i32>,
+    perm_w: Vec<i32>,
+    /// the number of lattice cells after which the noise repeats along x, y and z, if it does
+    period: Option<i32>,
 }
 impl Perlin {
+    /// Noise that is different on every run.
     pub fn new() -> Self {
+        Self::with_seed(thread_rng().gen())
+    }
+    /// Noise that is the same for the same `seed`.
+    pub fn with_seed(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut ran_vec = vec![Vec3::zero(); POINT_COUNT];
         for item in ran_vec.iter_mut().take(POINT_COUNT) {
-            *item = Vec3::unit(Vec3::random_in_range(-1., 1.));
+            *item = Vec3::unit(Vec3::new(
+                rng.gen_range(-1.0..1.),
+                rng.gen_range(-1.0..1.),
+                rng.gen_range(-1.0..1.),
+            ));
         }
+        let ran_vec4 = (0..POINT_COUNT)
+            .map(|_| {
+                let g: [f64; 4] = std::array::from_fn(|_| rng.gen_range(-1.0..1.));
+                let length = g.iter().map(|x| x * x).sum::<f64>().sqrt();
+                g.map(|x| x / length)
+            })
+            .collect();
         Self {
             ran_vec,
-            perm_x: Self::perlin_generate_perm(),
-            perm_y: Self::perlin_generate_perm(),
-            perm_z: Self::perlin_generate_perm(),
+            ran_vec4,
+            perm_x: Self::perlin_generate_perm(&mut rng),
+            perm_y: Self::perlin_generate_perm(&mut rng),
+            perm_z: Self::perlin_generate_perm(&mut rng),
+            perm_w: Self::perlin_generate_perm(&mut rng),
+            period: None,
         }
     }
+    /// Make the noise repeat every `period` units along x, y and z, so that it tiles seamlessly.
+    /// The octaves of `turb`, `fbm` and `ridged` tile as well when their lacunarity is a whole number.
+    pub fn with_period(mut self, period: i32) -> Self {
+        assert!(period > 0, "The period of noise must be positive");
+        self.period = Some(period);
+        self
+    }
+    /// A single octave of noise, in about [-1,1], which repeats with the period if there is one.
+    #[allow(dead_code)]
+    pub fn noise(&self, p: &Vec3) -> f64 {
+        self.periodic_noise(p, self.period)
+    }
     /// Smoothing yields an improved result, but there are obvious grid features in there.
     /// Some of it is Mach bands, a known perceptual artifact of linear interpolation of color.
     /// A standard trick is to use a Hermite cubic to round off the interpolation:
-    pub fn noise(&self, p: &Vec3) -> f64 {
-        // // 按位与取后 8 位
-        // let i = ((p.x() * 4.) as i32 & 255) as usize;
-        // let j = ((p.y() * 4.) as i32 & 255) as usize;
-        // let k = ((p.z() * 4.) as i32 & 255) as usize;
-        // // 按位异或
-        // self.ran_float[(self.perm_x[i] ^ self.perm_y[j] ^ self.perm_z[k]) as usize]
+    fn periodic_noise(&self, p: &Vec3, period: Option<i32>) -> f64 {
         let u = p.x() - p.x().floor();
         let v = p.y() - p.y().floor();
         let w = p.z() - p.z().floor();
@@ -50,41 +81,154 @@ impl Perlin {
         for di in 0..2 {
             for dj in 0..2 {
                 for dk in 0..2 {
-                    c[di][dj][dk] = self.ran_vec[(self.perm_x[((i + di as i32) & 255) as usize]
-                        ^ self.perm_y[((j + dj as i32) & 255) as usize]
-                        ^ self.perm_z[((k + dk as i32) & 255) as usize])
+                    c[di][dj][dk] = self.ran_vec[(self.perm_x[lattice(i + di as i32, period)]
+                        ^ self.perm_y[lattice(j + dj as i32, period)]
+                        ^ self.perm_z[lattice(k + dk as i32, period)])
                         as usize];
                 }
             }
         }
         Self::perlin_interp(c, u, v, w)
     }
+    /// Noise in space and time, which changes smoothly as `t` goes on
+    /// and repeats in space with the period but not in time.
+    fn periodic_noise4(&self, p: &Vec3, t: f64, period: Option<i32>) -> f64 {
+        let x = [p.x(), p.y(), p.z(), t];
+        let cell = x.map(|x| x.floor() as i32);
+        let f: [f64; 4] = std::array::from_fn(|a| x[a] - x[a].floor());
+        let smooth = f.map(|f| f * f * (3. - 2. * f));
+        let mut accum = 0.;
+        for corner in 0..16 {
+            let d: [i32; 4] = std::array::from_fn(|a| (corner >> a) & 1);
+            let gradient = self.ran_vec4[(self.perm_x[lattice(cell[0] + d[0], period)]
+                ^ self.perm_y[lattice(cell[1] + d[1], period)]
+                ^ self.perm_z[lattice(cell[2] + d[2], period)]
+                ^ self.perm_w[lattice(cell[3] + d[3], None)])
+                as usize];
+            let mut weight = 1.;
+            let mut dot = 0.;
+            for a in 0..4 {
+                dot += gradient[a] * (f[a] - d[a] as f64);
+                weight *= if d[a] == 1 { smooth[a] } else { 1. - smooth[a] };
+            }
+            accum += weight * dot;
+        }
+        accum
+    }
     /// Very often, a composite noise that has multiple summed frequencies is used. This is usually called turbulence, and is a sum of repeated calls to noise:
     pub fn turb(&self, p: &Vec3, depth: usize) -> f64 {
         let mut accum = 0.0;
         let mut temp_p = *p;
         let mut weight = 1.0;
+        let mut period = self.period;
 
         for _i in 0..depth {
-            accum += weight * self.noise(&temp_p);
+            accum += weight * self.periodic_noise(&temp_p, period);
             weight *= 0.5;
             temp_p *= 2.;
+            period = period.map(|period| period * 2);
         }
 
         accum.abs()
     }
-    fn perlin_generate_perm() -> Vec<i32> {
+    /// `turb` of noise that changes with `t`, with time sped up along with space in each octave.
+    pub fn turb4(&self, p: &Vec3, t: f64, depth: usize) -> f64 {
+        let mut accum = 0.;
+        let mut p = *p;
+        let mut t = t;
+        let mut weight = 1.;
+        let mut period = self.period;
+        for _ in 0..depth {
+            accum += weight * self.periodic_noise4(&p, t, period);
+            weight *= 0.5;
+            p *= 2.;
+            t *= 2.;
+            period = period.map(|period| period * 2);
+        }
+        accum.abs()
+    }
+    /// Fractional Brownian motion: `octaves` layers of noise, each `lacunarity` times the frequency
+    /// and `gain` times the amplitude of the one before, normalized to about [-1,1].
+    /// `turb` is the absolute value of this with a lacunarity of 2 and a gain of 0.5, not normalized.
+    pub fn fbm(&self, p: &Vec3, octaves: usize, lacunarity: f64, gain: f64) -> f64 {
+        let mut sum = 0.;
+        let mut total = 0.;
+        let mut amplitude = 1.;
+        let mut p = *p;
+        for octave in 0..octaves {
+            sum += amplitude * self.periodic_noise(&p, self.octave_period(octave, lacunarity));
+            total += amplitude;
+            amplitude *= gain;
+            p *= lacunarity;
+        }
+        if total > 0. {
+            sum / total
+        } else {
+            0.
+        }
+    }
+    /// Musgrave's ridged multifractal, in [0,1]: the noise folded into sharp ridges where it is 0,
+    /// with each octave weighted by the one before, so that the ridges get detail and the valleys stay smooth.
+    pub fn ridged(&self, p: &Vec3, octaves: usize, lacunarity: f64, gain: f64) -> f64 {
+        let mut sum = 0.;
+        let mut total = 0.;
+        let mut amplitude = 1.;
+        let mut weight = 1.;
+        let mut p = *p;
+        for octave in 0..octaves {
+            let period = self.octave_period(octave, lacunarity);
+            let ridge = 1. - self.periodic_noise(&p, period).abs().min(1.);
+            let signal = ridge * ridge * weight;
+            weight = (2. * signal).clamp(0., 1.);
+            sum += amplitude * signal;
+            total += amplitude;
+            amplitude *= gain;
+            p *= lacunarity;
+        }
+        if total > 0. {
+            sum / total
+        } else {
+            0.
+        }
+    }
+    /// `fbm` of noise that changes with `t`, with time sped up along with space in each octave.
+    pub fn fbm4(&self, p: &Vec3, t: f64, octaves: usize, lacunarity: f64, gain: f64) -> f64 {
+        let mut sum = 0.;
+        let mut total = 0.;
+        let mut amplitude = 1.;
+        let mut p = *p;
+        let mut t = t;
+        for octave in 0..octaves {
+            let period = self.octave_period(octave, lacunarity);
+            sum += amplitude * self.periodic_noise4(&p, t, period);
+            total += amplitude;
+            amplitude *= gain;
+            p *= lacunarity;
+            t *= lacunarity;
+        }
+        if total > 0. {
+            sum / total
+        } else {
+            0.
+        }
+    }
+    /// The period of an octave, which grows with its frequency.
+    fn octave_period(&self, octave: usize, lacunarity: f64) -> Option<i32> {
+        self.period
+            .map(|period| (period as f64 * lacunarity.powi(octave as i32)).round() as i32)
+    }
+    fn perlin_generate_perm(rng: &mut StdRng) -> Vec<i32> {
         let mut p: Vec<i32> = vec![0; POINT_COUNT];
 
         for (i, item) in p.iter_mut().enumerate().take(POINT_COUNT) {
             *item = i as i32;
         }
-        Self::permute(&mut p, POINT_COUNT);
+        Self::permute(&mut p, POINT_COUNT, rng);
         p
     }
-    fn permute(p: &mut [i32], n: usize) {
+    fn permute(p: &mut [i32], n: usize, rng: &mut StdRng) {
         for i in (1..n).rev() {
-            let target = random_int_in_range(0, i as i32) as usize;
+            let target = rng.gen_range(0..=i);
             p.swap(i, target);
         }
     }
@@ -96,8 +240,8 @@ impl Perlin {
             for j in 0..2 {
                 for k in 0..2 {
                     accum += (i as f64 * u + (1. - i as f64) * (1. - u))
-                        * (j as f64 * v + (1. - j as f64) * (1. - v as f64))
-                        * (k as f64 * w as f64 + (1. - k as f64) * (1. - w as f64))
+                        * (j as f64 * v + (1. - j as f64) * (1. - v))
+                        * (k as f64 * w + (1. - k as f64) * (1. - w))
                         * c[i][j][k];
                 }
             }
@@ -115,8 +259,8 @@ impl Perlin {
                 for k in 0..2 {
                     let weight_v = Vec3::new(u - i as f64, v - j as f64, w - k as f64);
                     accum += (i as f64 * uu + (1. - i as f64) * (1. - uu))
-                        * (j as f64 * vv + (1. - j as f64) * (1. - vv as f64))
-                        * (k as f64 * ww as f64 + (1. - k as f64) * (1. - ww as f64))
+                        * (j as f64 * vv + (1. - j as f64) * (1. - vv))
+                        * (k as f64 * ww + (1. - k as f64) * (1. - ww))
                         * (c[i][j][k] * weight_v);
                 }
             }
@@ -124,3 +268,59 @@ impl Perlin {
         accum
     }
 }
+/// The index into the permutation tables of a lattice coordinate,
+/// wrapped around the period first if there is one.
+fn lattice(i: i32, period: Option<i32>) -> usize {
+    let i = match period {
+        Some(period) => i.rem_euclid(period),
+        None => i,
+    };
+    (i & 255) as usize
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_seed() {
+        let p = Vec3::new(1.3, -0.4, 2.7);
+        let a = Perlin::with_seed(42);
+        let b = Perlin::with_seed(42);
+        let c = Perlin::with_seed(43);
+        assert_eq!(a.noise(&p), b.noise(&p));
+        assert_ne!(a.noise(&p), c.noise(&p));
+        assert_eq!(a.fbm(&p, 4, 2., 0.5), b.fbm(&p, 4, 2., 0.5));
+        assert_ne!(a.fbm(&p, 4, 2., 0.5), c.fbm(&p, 4, 2., 0.5));
+    }
+    /// Periodic noise and its octaves repeat along every axis, at any point.
+    #[test]
+    fn test_period() {
+        let noise = Perlin::with_seed(7).with_period(3);
+        for p in [Vec3::new(0.2, 0.7, 1.1), Vec3::new(-4.6, 2.25, 0.9)] {
+            for shift in [
+                Vec3::new(3., 0., 0.),
+                Vec3::new(0., -6., 0.),
+                Vec3::new(0., 0., 9.),
+            ] {
+                let q = p + shift;
+                assert!((noise.noise(&p) - noise.noise(&q)).abs() < 1e-12);
+                assert!((noise.turb(&p, 5) - noise.turb(&q, 5)).abs() < 1e-12);
+                assert!(
+                    (noise.ridged(&p, 5, 2., 0.5) - noise.ridged(&q, 5, 2., 0.5)).abs() < 1e-12
+                );
+                assert!(
+                    (noise.fbm4(&p, 0.4, 5, 3., 0.5) - noise.fbm4(&q, 0.4, 5, 3., 0.5)).abs()
+                        < 1e-12
+                );
+            }
+        }
+    }
+    /// Noise over time changes, but smoothly.
+    #[test]
+    fn test_time() {
+        let noise = Perlin::with_seed(3);
+        let p = Vec3::new(0.3, 0.6, 0.2);
+        let at = |t: f64| noise.fbm4(&p, t, 1, 2., 0.5);
+        assert!((at(0.5) - at(0.5001)).abs() < 1e-3);
+        assert!((0..10).any(|i| (at(0.5) - at(1.5 + i as f64)).abs() > 0.01));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::onb::ONB;
+use crate::rt_weekend::*;
+use crate::vec3::Vec3;
+
+/// How much light a particle of a participating medium scatters into each direction.
+///
+/// Phase functions here take the cosine of the angle between the direction the light was going
+/// and the direction it goes on in after scattering, so 1 is straight ahead.
+/// Each of them integrates to 1 over the sphere.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PhaseFunction {
+    /// The same in every direction.
+    Isotropic,
+    /// The Henyey-Greenstein phase function, where the asymmetry `g` in (-1, 1)
+    /// is the average cosine of scattering: positive scatters forward, like fog around a light,
+    /// and negative scatters back.
+    HenyeyGreenstein { g: f64 },
+    /// A blend of two Henyey-Greenstein lobes, usually a strong forward one and a weaker
+    /// backward one, as in clouds. `weight` is the share of the first lobe.
+    DoubleHenyeyGreenstein { g1: f64, g2: f64, weight: f64 },
+    /// The scattering by particles much smaller than the wavelength, like air molecules,
+    /// which scatter as much backward as forward.
+    Rayleigh,
+}
+impl PhaseFunction {
+    /// The value of the phase function for the cosine `cos_theta` of the scattering angle.
+    pub fn p(&self, cos_theta: f64) -> f64 {
+        match *self {
+            PhaseFunction::Isotropic => 1. / (4. * PI),
+            PhaseFunction::HenyeyGreenstein { g } => henyey_greenstein(cos_theta, g),
+            PhaseFunction::DoubleHenyeyGreenstein { g1, g2, weight } => {
+                weight * henyey_greenstein(cos_theta, g1)
+                    + (1. - weight) * henyey_greenstein(cos_theta, g2)
+            }
+            PhaseFunction::Rayleigh => 3. / (16. * PI) * (1. + cos_theta * cos_theta),
+        }
+    }
+    /// Sample a direction to go on in for light going in `direction`,
+    /// with a pdf equal to the phase function.
+    pub fn sample(&self, direction: &Vec3) -> Vec3 {
+        let cos_theta = match *self {
+            PhaseFunction::Isotropic => 1. - 2. * random_double(),
+            PhaseFunction::HenyeyGreenstein { g } => sample_henyey_greenstein(g),
+            PhaseFunction::DoubleHenyeyGreenstein { g1, g2, weight } => {
+                if random_double() < weight {
+                    sample_henyey_greenstein(g1)
+                } else {
+                    sample_henyey_greenstein(g2)
+                }
+            }
+            PhaseFunction::Rayleigh => {
+                // Invert the cdf, which solves cos³θ + 3 cosθ = 8u - 4 (Cardano).
+                let b = 8. * random_double() - 4.;
+                let root = (b * b / 4. + 1.).sqrt();
+                (b / 2. + root).cbrt() + (b / 2. - root).cbrt()
+            }
+        }
+        .clamp(-1., 1.);
+        let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+        let phi = 2. * PI * random_double();
+        ONB::build_from_w(direction).local(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+    }
+}
+fn henyey_greenstein(cos_theta: f64, g: f64) -> f64 {
+    let denom = 1. + g * g - 2. * g * cos_theta;
+    (1. - g * g) / (4. * PI * denom * denom.sqrt())
+}
+/// The cosine of the scattering angle, by inverting the cdf of Henyey-Greenstein.
+fn sample_henyey_greenstein(g: f64) -> f64 {
+    let u = random_double();
+    if g.abs() < 1e-3 {
+        return 1. - 2. * u;
+    }
+    let s = (1. - g * g) / (1. - g + 2. * g * u);
+    (1. + g * g - s * s) / (2. * g)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const PHASE_FUNCTIONS: [PhaseFunction; 5] = [
+        PhaseFunction::Isotropic,
+        PhaseFunction::HenyeyGreenstein { g: 0.7 },
+        PhaseFunction::HenyeyGreenstein { g: -0.4 },
+        PhaseFunction::DoubleHenyeyGreenstein {
+            g1: 0.8,
+            g2: -0.3,
+            weight: 0.7,
+        },
+        PhaseFunction::Rayleigh,
+    ];
+    #[test]
+    fn test_normalized() {
+        const N: usize = 100000;
+        for phase in PHASE_FUNCTIONS {
+            // Integrate over cosθ, which is uniform over the sphere.
+            let sum: f64 = (0..N)
+                .map(|i| phase.p(-1. + 2. * (i as f64 + 0.5) / N as f64))
+                .sum();
+            let integral = sum / N as f64 * 4. * PI;
+            assert!((integral - 1.).abs() < 1e-3, "{:?} {}", phase, integral);
+        }
+    }
+    /// The samples follow the phase function: their average cosine is the one it predicts.
+    #[test]
+    fn test_sampling_mean_cosine() {
+        let direction = Vec3::unit(Vec3::new(1., 2., -1.));
+        const N: usize = 200000;
+        for phase in PHASE_FUNCTIONS {
+            let expected = match phase {
+                PhaseFunction::HenyeyGreenstein { g } => g,
+                PhaseFunction::DoubleHenyeyGreenstein { g1, g2, weight } => {
+                    weight * g1 + (1. - weight) * g2
+                }
+                _ => 0.,
+            };
+            let sum: f64 = (0..N)
+                .map(|_| {
+                    let sampled = phase.sample(&direction);
+                    assert!((sampled.length() - 1.).abs() < 1e-9);
+                    sampled * direction
+                })
+                .sum();
+            let mean = sum / N as f64;
+            assert!((mean - expected).abs() < 0.01, "{:?} {}", phase, mean);
+        }
+    }
+}
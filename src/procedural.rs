@@ -0,0 +1,525 @@
+use crate::perlin::Perlin;
+use crate::rt_weekend::*;
+use crate::texture::{Texture, TexturePoint};
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+/// A gradient of colors along [0,1], interpolated linearly between its stops
+/// and constant beyond the first and the last.
+#[derive(Clone, Debug)]
+pub struct ColorRamp {
+    /// the positions and colors of the stops, in increasing order of position
+    stops: Vec<(f64, Vec3)>,
+}
+impl ColorRamp {
+    pub fn new(mut stops: Vec<(f64, Vec3)>) -> Self {
+        assert!(!stops.is_empty(), "A color ramp needs at least one stop");
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { stops }
+    }
+    pub fn at(&self, t: f64) -> Vec3 {
+        let next = self.stops.partition_point(|&(position, _)| position <= t);
+        if next == 0 {
+            return self.stops[0].1;
+        }
+        if next == self.stops.len() {
+            return self.stops[next - 1].1;
+        }
+        let (t0, c0) = self.stops[next - 1];
+        let (t1, c1) = self.stops[next];
+        let f = (t - t0) / (t1 - t0);
+        c0 * (1. - f) + c1 * f
+    }
+}
+/// Fractional Brownian motion, the sum of octaves of Perlin noise, as a gray level in [0,1].
+pub struct FbmTexture {
+    noise: Perlin,
+    scale: f64,
+    octaves: usize,
+    lacunarity: f64,
+    gain: f64,
+    /// how fast the noise changes with the time of the rays, 0 for not at all
+    speed: f64,
+}
+impl FbmTexture {
+    /// Six octaves, each twice the frequency and half the amplitude of the one before.
+    pub fn new(scale: f64) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            octaves: 6,
+            lacunarity: 2.,
+            gain: 0.5,
+            speed: 0.,
+        }
+    }
+    pub fn with_octaves(mut self, octaves: usize, lacunarity: f64, gain: f64) -> Self {
+        self.octaves = octaves;
+        self.lacunarity = lacunarity;
+        self.gain = gain;
+        self
+    }
+    /// Use `noise`, for instance one with a seed or a period, instead of a random one.
+    pub fn with_noise(mut self, noise: Perlin) -> Self {
+        self.noise = noise;
+        self
+    }
+    /// Let the noise change over time, `speed` lattice cells per unit of time,
+    /// which blurs it under motion blur and animates it across frames.
+    pub fn with_evolution(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+}
+impl Texture for FbmTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.sample(&TexturePoint::new(u, v, *p))
+    }
+    fn sample(&self, point: &TexturePoint) -> Vec3 {
+        let p = point.p * self.scale;
+        let n = if self.speed == 0. {
+            self.noise.fbm(&p, self.octaves, self.lacunarity, self.gain)
+        } else {
+            let t = point.time * self.speed;
+            self.noise
+                .fbm4(&p, t, self.octaves, self.lacunarity, self.gain)
+        };
+        Vec3::ones() * (0.5 * (1. + n)).clamp(0., 1.)
+    }
+}
+/// Ridged multifractal noise, like mountain ranges or veins, as a gray level in [0,1].
+pub struct RidgedTexture {
+    noise: Perlin,
+    scale: f64,
+    octaves: usize,
+    lacunarity: f64,
+    gain: f64,
+}
+impl RidgedTexture {
+    pub fn new(scale: f64) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            octaves: 6,
+            lacunarity: 2.,
+            gain: 0.5,
+        }
+    }
+    pub fn with_octaves(mut self, octaves: usize, lacunarity: f64, gain: f64) -> Self {
+        self.octaves = octaves;
+        self.lacunarity = lacunarity;
+        self.gain = gain;
+        self
+    }
+    pub fn with_noise(mut self, noise: Perlin) -> Self {
+        self.noise = noise;
+        self
+    }
+}
+impl Texture for RidgedTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        let n = self
+            .noise
+            .ridged(&(*p * self.scale), self.octaves, self.lacunarity, self.gain);
+        Vec3::ones() * n
+    }
+}
+/// What a `WorleyTexture` shows of the distances to the feature points.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cellular {
+    /// The distance to the nearest point, dark at the points, like bubbles or scales.
+    F1,
+    /// The distance to the second nearest point minus that to the nearest,
+    /// dark along the borders between cells, like cracked mud or cobblestones.
+    F2MinusF1,
+}
+/// Worley (cellular) noise: space is scattered with a random feature point in every unit cell,
+/// and the texture is the distance to them, as a gray level clamped to [0,1].
+pub struct WorleyTexture {
+    seed: u64,
+    scale: f64,
+    feature: Cellular,
+}
+impl WorleyTexture {
+    pub fn new(scale: f64, feature: Cellular) -> Self {
+        Self {
+            seed: thread_rng().gen(),
+            scale,
+            feature,
+        }
+    }
+    /// Cells that are the same for the same `seed`.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+    /// The feature point of the cell (i, j, k).
+    fn feature_point(&self, cell: [i64; 3]) -> Vec3 {
+        let mut h = self.seed;
+        for c in cell {
+            h = mix_bits(h ^ c as u64);
+        }
+        let unit = |bits: u64| (bits >> 11) as f64 / (1u64 << 53) as f64;
+        let (a, b) = (mix_bits(h ^ 1), mix_bits(h ^ 2));
+        Vec3::new(
+            cell[0] as f64 + unit(h),
+            cell[1] as f64 + unit(a),
+            cell[2] as f64 + unit(b),
+        )
+    }
+    /// The distances to the nearest and the second nearest feature points.
+    fn distances(&self, p: &Vec3) -> (f64, f64) {
+        let cell = [p.x().floor(), p.y().floor(), p.z().floor()].map(|c| c as i64);
+        let (mut f1, mut f2) = (f64::INFINITY, f64::INFINITY);
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let point = self.feature_point([cell[0] + di, cell[1] + dj, cell[2] + dk]);
+                    let distance = (point - *p).length();
+                    if distance < f1 {
+                        f2 = f1;
+                        f1 = distance;
+                    } else if distance < f2 {
+                        f2 = distance;
+                    }
+                }
+            }
+        }
+        (f1, f2)
+    }
+}
+/// The finalizer of SplitMix64, which scrambles all the bits of `x`.
+fn mix_bits(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+impl Texture for WorleyTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        let (f1, f2) = self.distances(&(*p * self.scale));
+        let n = match self.feature {
+            Cellular::F1 => f1,
+            Cellular::F2MinusF1 => f2 - f1,
+        };
+        Vec3::ones() * n.clamp(0., 1.)
+    }
+}
+/// Wood with growth rings around the y axis, made irregular by noise.
+pub struct WoodTexture {
+    noise: Perlin,
+    /// rings per unit of distance from the axis
+    rings: f64,
+    /// how far the noise pushes the rings around, in rings
+    distortion: f64,
+    light: Vec3,
+    dark: Vec3,
+}
+impl WoodTexture {
+    pub fn new(rings: f64, light: Vec3, dark: Vec3) -> Self {
+        Self {
+            noise: Perlin::new(),
+            rings,
+            distortion: 0.6,
+            light,
+            dark,
+        }
+    }
+    pub fn with_distortion(mut self, distortion: f64) -> Self {
+        self.distortion = distortion;
+        self
+    }
+    pub fn with_noise(mut self, noise: Perlin) -> Self {
+        self.noise = noise;
+        self
+    }
+}
+impl Texture for WoodTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        let radius = (p.x() * p.x() + p.z() * p.z()).sqrt() * self.rings;
+        // The grain is stretched along the axis, like the fibers of a trunk.
+        let grain = Vec3::new(
+            p.x() * self.rings,
+            p.y() * 0.2 * self.rings,
+            p.z() * self.rings,
+        );
+        let ring = radius + self.distortion * self.noise.fbm(&grain, 4, 2., 0.5);
+        // Each ring is light early in the year and turns dark quickly at its end.
+        let t = ring - ring.floor();
+        let t = t * t * t;
+        self.light * (1. - t) + self.dark * t
+    }
+}
+/// Marble veins from a sine wave along z disturbed by turbulence, colored by a ramp.
+pub struct MarbleTexture {
+    noise: Perlin,
+    /// the frequency of the veins
+    scale: f64,
+    /// how far the turbulence pushes the veins
+    turbulence: f64,
+    ramp: ColorRamp,
+}
+impl MarbleTexture {
+    pub fn new(scale: f64, ramp: ColorRamp) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            turbulence: 10.,
+            ramp,
+        }
+    }
+    pub fn with_turbulence(mut self, turbulence: f64) -> Self {
+        self.turbulence = turbulence;
+        self
+    }
+    pub fn with_noise(mut self, noise: Perlin) -> Self {
+        self.noise = noise;
+        self
+    }
+}
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        let t = 0.5 * (1. + (self.scale * p.z() + self.turbulence * self.noise.turb(p, 7)).sin());
+        self.ramp.at(t)
+    }
+}
+/// Another texture looked up at points pushed around by fBm noise,
+/// which swirls it like smoke or stretched paint.
+pub struct DomainWarp {
+    texture: Arc<dyn Texture>,
+    noise: Perlin,
+    /// the frequency of the warping noise
+    scale: f64,
+    /// how far the points are pushed, in world units
+    strength: f64,
+}
+impl DomainWarp {
+    pub fn new(texture: Arc<dyn Texture>, scale: f64, strength: f64) -> Self {
+        Self {
+            texture,
+            noise: Perlin::new(),
+            scale,
+            strength,
+        }
+    }
+    pub fn with_noise(mut self, noise: Perlin) -> Self {
+        self.noise = noise;
+        self
+    }
+}
+impl Texture for DomainWarp {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.sample(&TexturePoint::new(u, v, *p))
+    }
+    fn sample(&self, point: &TexturePoint) -> Vec3 {
+        let q = point.p * self.scale;
+        // Three far apart samples of the same noise make three unrelated offsets.
+        let offset = Vec3::new(
+            self.noise.fbm(&q, 4, 2., 0.5),
+            self.noise.fbm(&(q + Vec3::new(5.2, 1.3, 7.1)), 4, 2., 0.5),
+            self.noise.fbm(&(q + Vec3::new(-3.7, 9.2, 2.8)), 4, 2., 0.5),
+        );
+        self.texture.sample(&TexturePoint {
+            p: point.p + offset * self.strength,
+            ..*point
+        })
+    }
+}
+/// Another solid texture moved, rotated and scaled in space.
+///
+/// Each of `with_scale`, `with_rotation` and `with_translation` applies to the texture
+/// as it is after the ones before, in world space.
+pub struct TransformedTexture {
+    texture: Arc<dyn Texture>,
+    /// the map from world space to the space of the texture, `matrix * p + offset`,
+    /// with the matrix in row major order
+    matrix: [f64; 9],
+    offset: Vec3,
+}
+impl TransformedTexture {
+    pub fn new(texture: Arc<dyn Texture>) -> Self {
+        Self {
+            texture,
+            matrix: [1., 0., 0., 0., 1., 0., 0., 0., 1.],
+            offset: Vec3::zero(),
+        }
+    }
+    /// Make the texture `scale` times as large along each axis.
+    pub fn with_scale(mut self, scale: Vec3) -> Self {
+        let inverse = [
+            1. / scale.x(),
+            0.,
+            0.,
+            0.,
+            1. / scale.y(),
+            0.,
+            0.,
+            0.,
+            1. / scale.z(),
+        ];
+        self.matrix = mat_mul(&self.matrix, &inverse);
+        self
+    }
+    /// Rotate the texture by `degrees` around `axis`, counterclockwise looking down the axis.
+    pub fn with_rotation(mut self, axis: Vec3, degrees: f64) -> Self {
+        // Rotating the texture one way looks its points up rotated the other way.
+        let a = axis.unit();
+        let (sin, cos) = (-degrees_to_radians(degrees)).sin_cos();
+        let t = 1. - cos;
+        let inverse = [
+            cos + a.x() * a.x() * t,
+            a.x() * a.y() * t - a.z() * sin,
+            a.x() * a.z() * t + a.y() * sin,
+            a.y() * a.x() * t + a.z() * sin,
+            cos + a.y() * a.y() * t,
+            a.y() * a.z() * t - a.x() * sin,
+            a.z() * a.x() * t - a.y() * sin,
+            a.z() * a.y() * t + a.x() * sin,
+            cos + a.z() * a.z() * t,
+        ];
+        self.matrix = mat_mul(&self.matrix, &inverse);
+        self
+    }
+    pub fn with_translation(mut self, translation: Vec3) -> Self {
+        self.offset -= Vec3::transform(&self.matrix, &translation);
+        self
+    }
+}
+impl Texture for TransformedTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.sample(&TexturePoint::new(u, v, *p))
+    }
+    /// Only the position is transformed; the normal, footprint and time pass through as they are.
+    fn sample(&self, point: &TexturePoint) -> Vec3 {
+        self.texture.sample(&TexturePoint {
+            p: Vec3::transform(&self.matrix, &point.p) + self.offset,
+            ..*point
+        })
+    }
+}
+fn mat_mul(a: &[f64; 9], b: &[f64; 9]) -> [f64; 9] {
+    let mut product = [0.; 9];
+    for row in 0..3 {
+        for column in 0..3 {
+            product[3 * row + column] = (0..3).map(|k| a[3 * row + k] * b[3 * k + column]).sum();
+        }
+    }
+    product
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_color_ramp() {
+        let ramp = ColorRamp::new(vec![
+            (1., Vec3::new(0., 0., 1.)),
+            (0., Vec3::zero()),
+            (0.5, Vec3::new(1., 0., 0.)),
+        ]);
+        assert_eq!(ramp.at(-1.), Vec3::zero());
+        assert_eq!(ramp.at(0.25), Vec3::new(0.5, 0., 0.));
+        assert_eq!(ramp.at(0.75), Vec3::new(0.5, 0., 0.5));
+        assert_eq!(ramp.at(2.), Vec3::new(0., 0., 1.));
+    }
+    #[test]
+    fn test_worley() {
+        let worley = WorleyTexture::new(1., Cellular::F1);
+        let point = worley.feature_point([3, -2, 5]);
+        assert_eq!(point, worley.feature_point([3, -2, 5]));
+        assert!(point.x() >= 3. && point.x() < 4. && point.y() >= -2. && point.z() >= 5.);
+        assert_eq!(worley.value(0., 0., &point), Vec3::zero());
+        for i in 0..100 {
+            let p = Vec3::new(i as f64 * 0.37, i as f64 * -0.21, i as f64 * 0.13);
+            let (f1, f2) = worley.distances(&p);
+            assert!(f1 <= f2 && f1 < 3f64.sqrt());
+        }
+    }
+    /// The octaves of fBm and ridged noise stay in their ranges, and still vary.
+    #[test]
+    fn test_fractal_ranges() {
+        let noise = Perlin::new();
+        let ridged = RidgedTexture::new(3.);
+        let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+        for i in 0..1000 {
+            let p = Vec3::new(i as f64 * 0.137, i as f64 * 0.071, i as f64 * -0.029);
+            let value = noise.fbm(&(p * 3.), 8, 2.1, 0.6);
+            assert!(value.abs() <= 1.);
+            min = min.min(value);
+            max = max.max(value);
+            let value = ridged.value(0., 0., &p).x();
+            assert!((0. ..=1.).contains(&value));
+        }
+        assert!(max - min > 0.5, "{} {}", min, max);
+    }
+    #[test]
+    fn test_wood() {
+        let (light, dark) = (Vec3::new(0.8, 0.6, 0.4), Vec3::new(0.3, 0.2, 0.1));
+        // Without distortion the rings are circles, 2 per unit, darkening towards their ends.
+        let wood = WoodTexture::new(2., light, dark).with_distortion(0.);
+        let early = wood.value(0., 0., &Vec3::new(0.1, 0., 0.));
+        assert!((early - (light * 0.992 + dark * 0.008)).length() < 1e-9);
+        let late = wood.value(0., 0., &Vec3::new(0., 3., 0.45));
+        assert!((late - (light * 0.271 + dark * 0.729)).length() < 1e-9);
+        // Distorted, it still only blends the two colors.
+        let wood = WoodTexture::new(2., light, dark);
+        for i in 0..100 {
+            let p = Vec3::new(i as f64 * 0.13, i as f64 * 0.07, i as f64 * -0.11);
+            let color = wood.value(0., 0., &p);
+            assert!(color.x() >= dark.x() - 1e-9 && color.x() <= light.x() + 1e-9);
+        }
+    }
+    #[test]
+    fn test_marble() {
+        let ramp = ColorRamp::new(vec![(0., Vec3::zero()), (1., Vec3::ones())]);
+        // Without turbulence the veins are a sine wave along z.
+        let marble = MarbleTexture::new(2., ramp.clone()).with_turbulence(0.);
+        assert!((marble.value(0., 0., &Vec3::new(1., 2., 0.)).x() - 0.5).abs() < 1e-9);
+        let crest = Vec3::new(0., 0., PI / 4.);
+        assert!((marble.value(0., 0., &crest).x() - 1.).abs() < 1e-9);
+        let marble = MarbleTexture::new(2., ramp);
+        let values: Vec<f64> = (0..100)
+            .map(|i| {
+                marble
+                    .value(0., 0., &Vec3::new(0.3, 0.1, i as f64 * 0.05))
+                    .x()
+            })
+            .collect();
+        assert!(values.iter().all(|value| (0. ..=1.).contains(value)));
+        assert!(values.iter().any(|&value| value < 0.2) && values.iter().any(|&value| value > 0.8));
+    }
+    #[test]
+    fn test_domain_warp() {
+        let still = DomainWarp::new(Arc::new(Position), 2., 0.);
+        let p = Vec3::new(0.3, -1.2, 4.5);
+        assert_eq!(still.value(0., 0., &p), p);
+        // Each offset is fBm, at most 1, times the strength.
+        let warp = DomainWarp::new(Arc::new(Position), 2., 0.5);
+        let mut moved = 0;
+        for i in 0..100 {
+            let p = Vec3::new(i as f64 * 0.13, i as f64 * 0.07, i as f64 * -0.11);
+            let offset = warp.value(0., 0., &p) - p;
+            assert!(offset.length() <= 0.5 * 3f64.sqrt());
+            if offset.length() > 0.01 {
+                moved += 1;
+            }
+        }
+        assert!(moved > 50);
+    }
+    /// A texture that shows the point it is looked up at.
+    struct Position;
+    impl Texture for Position {
+        fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+            *p
+        }
+    }
+    #[test]
+    fn test_transform() {
+        let texture = TransformedTexture::new(Arc::new(Position))
+            .with_scale(Vec3::new(2., 2., 2.))
+            .with_rotation(Vec3::new(0., 1., 0.), 90.)
+            .with_translation(Vec3::new(1., 0., 0.));
+        // The texture point (1, 0, 0) is scaled to (2, 0, 0), rotated to (0, 0, -2), then moved.
+        let looked_up = texture.value(0., 0., &Vec3::new(1., 0., -2.));
+        assert!((looked_up - Vec3::new(1., 0., 0.)).length() < 1e-12);
+    }
+}
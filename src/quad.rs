@@ -0,0 +1,165 @@
+use crate::aabb::*;
+use crate::hit::*;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::rt_weekend::*;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+/// A parallelogram in any orientation: the corner `q` and the two edges `u` and `v` from it.
+///
+/// The U,V coordinates go from 0 to 1 along the edges,
+/// and the front face is the side that `u × v` points to.
+pub struct Quad {
+    q: Vec3,
+    u: Vec3,
+    v: Vec3,
+    /// `u × v`, whose length is the area
+    n: Vec3,
+    /// `n / (n · n)`, which turns a point in the plane into its U,V
+    w: Vec3,
+    material: Arc<dyn Material>,
+    /// the emitted power, cached for the light distribution
+    power: f64,
+}
+impl Quad {
+    pub fn new(q: Vec3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Self {
+        let n = Vec3::cross(u, v);
+        let power = surface_power(&material, n.length(), |s, t| {
+            (s, t, q + u * s + v * t, n.unit())
+        });
+        Self {
+            q,
+            u,
+            v,
+            n,
+            w: n / n.squared_length(),
+            material,
+            power,
+        }
+    }
+    fn area(&self) -> f64 {
+        self.n.length()
+    }
+}
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let denominator = self.n * ray.direction();
+        // The ray runs along the plane.
+        if denominator.abs() < 1e-12 {
+            return false;
+        }
+        let t = (self.n * (self.q - ray.origin())) / denominator;
+        if t < t_min || t > t_max {
+            return false;
+        }
+        let p = ray.at(t);
+        let planar = p - self.q;
+        let alpha = self.w * Vec3::cross(planar, self.v);
+        let beta = self.w * Vec3::cross(self.u, planar);
+        if !(0. ..=1.).contains(&alpha) || !(0. ..=1.).contains(&beta) {
+            return false;
+        }
+        rec.u = alpha;
+        rec.v = beta;
+        rec.dpdu = self.u;
+        rec.dpdv = self.v;
+        rec.t = t;
+        rec.p = p;
+        rec.set_face_normal(ray, self.n.unit());
+        rec.material = self.material.clone();
+        rec.sampled_light = self.power > 0.;
+        true
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let corners = [self.q + self.u, self.q + self.v, self.q + self.u + self.v];
+        *output_box = corners.iter().fold(padded_box(self.q, self.q), |b, &c| {
+            surrounding_box(b, padded_box(c, c))
+        });
+        true
+    }
+    fn pdf_value(&self, origin: &Vec3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new(self.material.clone());
+        if !self.hit(&Ray::new(*origin, *v, 0.), 0.001, f64::INFINITY, &mut rec) {
+            return 0.;
+        }
+        let distance_squared = rec.t * rec.t * v.squared_length();
+        let cosine = (*v * rec.normal).abs() / v.length();
+        distance_squared / (cosine * self.area())
+    }
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        self.q + self.u * random_double() + self.v * random_double() - *origin
+    }
+    fn emitted_power(&self) -> f64 {
+        self.power
+    }
+}
+/// The box from `min` to `max` grown a little on every side,
+/// so that flat objects still have some width in each dimension.
+pub fn padded_box(min: Vec3, max: Vec3) -> AABB {
+    let padding = Vec3::ones() * 0.0001;
+    AABB::new(min - padding, max + padding)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::HitList;
+    use crate::light::LightList;
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::texture::CheckerTexture;
+    #[test]
+    fn test_quad() {
+        let material = Arc::new(Lambertian::new(Vec3::ones()));
+        // A tilted square with sides of 2, facing up and towards +z.
+        let quad = Quad::new(
+            Vec3::new(-1., 0., 0.),
+            Vec3::new(2., 0., 0.),
+            Vec3::new(0., 2f64.sqrt(), -(2f64.sqrt())),
+            material,
+        );
+        let mut rec = HitRecord::default();
+        let ray = Ray::new(Vec3::new(0.5, 5., -0.5), Vec3::new(0., -1., 0.), 0.);
+        assert!(quad.hit(&ray, 0.001, f64::INFINITY, &mut rec));
+        assert!((rec.p - Vec3::new(0.5, 0.5, -0.5)).length() < 1e-9);
+        assert!((rec.u - 0.75).abs() < 1e-9 && (rec.v - 0.5 / 2f64.sqrt()).abs() < 1e-9);
+        assert!(rec.front_face);
+        assert!((quad.area() - 4.).abs() < 1e-9);
+        let miss = Ray::new(Vec3::new(1.5, 5., -0.5), Vec3::new(0., -1., 0.), 0.);
+        assert!(!quad.hit(&miss, 0.001, f64::INFINITY, &mut rec));
+        // Sampled points land on the quad, where the PDF is that of its solid angle.
+        let origin = Vec3::new(0., 3., 0.);
+        for _ in 0..10 {
+            let v = quad.random(&origin);
+            assert!(quad.pdf_value(&origin, &v) > 0.);
+        }
+    }
+    #[test]
+    fn test_emitter_power() {
+        // A checker that is dark at the origin but bright on half of the quad.
+        let checker = CheckerTexture::new_rgb(Vec3::zero(), Vec3::ones() * 4.);
+        let light = Arc::new(Quad::new(
+            Vec3::new(-1., 0., -1.),
+            Vec3::new(2., 0., 0.),
+            Vec3::new(0., 0., 2.),
+            Arc::new(DiffuseLight::new_texture(Arc::new(checker))),
+        ));
+        assert!(light.emitted_power() > 0.);
+        let mut world = HitList::new();
+        world.add(light.clone());
+        let mut lights = LightList::new();
+        lights.add_emitters(&world);
+        assert_eq!(lights.len(), 1);
+        let mut rec = HitRecord::default();
+        let ray = Ray::new(Vec3::new(0.1, 5., 0.1), Vec3::new(0., -1., 0.), 0.);
+        assert!(light.hit(&ray, 0.001, f64::INFINITY, &mut rec));
+        assert!(rec.sampled_light);
+        // Surfaces left out of the light list keep their emission in the path.
+        let dark = Quad::new(
+            Vec3::new(-1., 0., -1.),
+            Vec3::new(2., 0., 0.),
+            Vec3::new(0., 0., 2.),
+            Arc::new(Lambertian::new(Vec3::ones())),
+        );
+        assert!(dark.hit(&ray, 0.001, f64::INFINITY, &mut rec));
+        assert!(!rec.sampled_light);
+    }
+}
@@ -1,9 +1,23 @@
+use crate::spectrum::Wavelengths;
 use crate::vec3::Vec3;
+/// The rays through the points one pixel to the right (x) and one pixel up (y) from a camera ray,
+/// which tell how large a part of the scene the ray stands for.
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct RayDifferential {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3,
+}
 #[derive(Clone, Debug, Copy, PartialEq)]
 pub struct Ray {
     orig: Vec3,
     dir: Vec3,
     time: f64,
+    /// the wavelengths of the path in spectral mode, `None` when rendering RGB
+    wavelengths: Option<Wavelengths>,
+    /// the offset rays of camera rays and of their specular bounces, `None` after anything else
+    differential: Option<RayDifferential>,
 }
 impl Ray {
     pub fn origin(&self) -> Vec3 {
@@ -15,20 +29,47 @@ impl Ray {
     pub fn time(&self) -> f64 {
         self.time
     }
+    pub fn wavelengths(&self) -> Option<Wavelengths> {
+        self.wavelengths
+    }
+    pub fn differential(&self) -> Option<RayDifferential> {
+        self.differential
+    }
     pub fn new(origin: Vec3, direction: Vec3, time: f64) -> Self {
         Self {
             orig: origin,
             dir: direction,
             time,
+            wavelengths: None,
+            differential: None,
         }
     }
-    pub fn zero() -> Self {
-        Self {
-            orig: Vec3::zero(),
-            dir: Vec3::zero(),
-            time: 0.,
+    pub fn with_wavelengths(mut self, wavelengths: Option<Wavelengths>) -> Self {
+        self.wavelengths = wavelengths;
+        self
+    }
+    pub fn with_differential(mut self, differential: Option<RayDifferential>) -> Self {
+        self.differential = differential;
+        self
+    }
+    /// Carry on the wavelengths of the path that `parent` belongs to,
+    /// unless the material has already given this ray its own.
+    pub fn inherit_wavelengths(&mut self, parent: &Ray) {
+        if self.wavelengths.is_none() {
+            self.wavelengths = parent.wavelengths;
+        }
+    }
+    /// An RGB color as this ray sees it: unchanged in RGB mode,
+    /// or upsampled to the wavelengths of the path in spectral mode.
+    pub fn spectrum(&self, rgb: Vec3) -> Vec3 {
+        match &self.wavelengths {
+            Some(wavelengths) => wavelengths.upsample(rgb),
+            None => rgb,
         }
     }
+    pub fn zero() -> Self {
+        Self::new(Vec3::zero(), Vec3::zero(), 0.)
+    }
     pub fn at(&self, t: f64) -> Vec3 {
         self.orig + self.dir * t
     }
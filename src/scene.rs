@@ -1,13 +1,27 @@
 use crate::aarect::*;
+use crate::alpha_mask::*;
 use crate::bvh::BVHNode;
 use crate::constant_medium::ConstantMedium;
 use crate::cornell_box::CornellBox;
+use crate::heterogeneous_medium::*;
 pub use crate::hit::*;
+use crate::ies::IesProfile;
+use crate::light::*;
 use crate::material::*;
+use crate::normal_map::*;
+use crate::participating_medium::ParticipatingMedium;
+use crate::perlin::Perlin;
+use crate::phase::PhaseFunction;
+use crate::procedural::*;
+use crate::quad::Quad;
 pub use crate::rt_weekend::*;
+use crate::shapes::*;
 use crate::sphere::*;
+use crate::subsurface::Subsurface;
 use crate::texture::*;
+use crate::texture_graph::*;
 pub use crate::vec3::Vec3;
+use crate::voxel_grid::VoxelGrid;
 use std::sync::Arc;
 
 pub fn random_scene() -> HitList {
@@ -117,7 +131,9 @@ pub fn two_perlin_spheres() -> HitList {
     world
 }
 pub fn earth() -> HitList {
-    let earth_texture = Arc::new(ImageTexture::new("input/earthmap.jpg"));
+    // The map goes once around the globe, and stops at the poles.
+    let earth_texture =
+        Arc::new(ImageTexture::new("input/earthmap.jpg").with_wrap(Wrap::Repeat, Wrap::Clamp));
     let earth_surface = Arc::new(Lambertian::new_texture(earth_texture));
     let globe = Arc::new(Sphere::new(Vec3::new(0., 0., 0.), 2., earth_surface));
     let mut world = HitList::new();
@@ -146,7 +162,12 @@ pub fn cornell_box() -> HitList {
     let red = Arc::new(Lambertian::new(Vec3::new(0.65, 0.05, 0.05)));
     let white = Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
     let green = Arc::new(Lambertian::new(Vec3::new(0.12, 0.45, 0.15)));
-    let light = Arc::new(DiffuseLight::new(Vec3::new(15., 15., 15.)));
+    // The ceiling light faces down into the room, which is the back of an XZRectangle.
+    let light = Arc::new(DiffuseLight::new_directional(
+        Arc::new(SolidColor::new(Vec3::new(15., 15., 15.))),
+        EmissionSides::Back,
+        EmissionProfile::Uniform,
+    ));
 
     world.add(Arc::new(YZRectangle::new(0., 555., 0., 555., 555., green)));
     world.add(Arc::new(YZRectangle::new(0., 555., 0., 555., 0., red)));
@@ -208,7 +229,11 @@ pub fn cornell_smoke() -> HitList {
     let red = Arc::new(Lambertian::new(Vec3::new(0.65, 0.05, 0.05)));
     let white = Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
     let green = Arc::new(Lambertian::new(Vec3::new(0.12, 0.45, 0.15)));
-    let light = Arc::new(DiffuseLight::new(Vec3::new(7., 7., 7.)));
+    let light = Arc::new(DiffuseLight::new_directional(
+        Arc::new(SolidColor::new(Vec3::new(7., 7., 7.))),
+        EmissionSides::Back,
+        EmissionProfile::Uniform,
+    ));
     world.add(Arc::new(YZRectangle::new(0., 555., 0., 555., 555., green)));
     world.add(Arc::new(YZRectangle::new(0., 555., 0., 555., 0., red)));
     world.add(Arc::new(XZRectangle::new(
@@ -295,7 +320,11 @@ pub fn final_scene() -> HitList {
 
     let mut world = HitList::new();
     world.add(Arc::new(BVHNode::new(boxes1.list, 0., 1.)));
-    let light = Arc::new(DiffuseLight::new(Vec3::new(7., 7., 7.)));
+    let light = Arc::new(DiffuseLight::new_directional(
+        Arc::new(SolidColor::new(Vec3::new(7., 7., 7.))),
+        EmissionSides::Back,
+        EmissionProfile::Uniform,
+    ));
     world.add(Arc::new(XZRectangle::new(
         123., 423., 147., 412., 554., light,
     )));
@@ -377,9 +406,17 @@ pub fn final_scene() -> HitList {
 }
 pub fn art_product() -> HitList {
     let mut world = HitList::new();
-    let red = Arc::new(DiffuseLight::new(Vec3::new(5., 1., 1.)));
-    let green = Arc::new(DiffuseLight::new(Vec3::new(1., 5., 1.)));
-    let blue = Arc::new(DiffuseLight::new(Vec3::new(1., 1., 5.)));
+    // Only the outside of the glowing spheres can be seen.
+    let glow = |color| {
+        Arc::new(DiffuseLight::new_directional(
+            Arc::new(SolidColor::new(color)),
+            EmissionSides::Front,
+            EmissionProfile::Uniform,
+        ))
+    };
+    let red = glow(Vec3::new(5., 1., 1.));
+    let green = glow(Vec3::new(1., 5., 1.));
+    let blue = glow(Vec3::new(1., 1., 5.));
     let ground = Arc::new(Lambertian::new(Vec3::new(0.48, 0.83, 0.53)));
 
     let boxes_per_side = 20;
@@ -408,9 +445,11 @@ pub fn art_product() -> HitList {
             }
         }
     }
-    let pumpkin = Arc::new(DiffuseLight::new_texture(Arc::new(ImageTexture::new(
-        "input/pumpkin_head.jpg",
-    ))));
+    let pumpkin = Arc::new(DiffuseLight::new_directional(
+        Arc::new(ImageTexture::new("input/pumpkin_head.jpg")),
+        EmissionSides::Front,
+        EmissionProfile::Uniform,
+    ));
     world.add(Arc::new(Sphere::new(
         Vec3::new(0., 200., 0.),
         100.,
@@ -418,3 +457,1056 @@ pub fn art_product() -> HitList {
     )));
     world
 }
+/// The two Perlin spheres lit only by analytic lights:
+/// a point light, a spotlight, a dim sun and three area lights of different shapes.
+pub fn analytic_lights() -> (HitList, LightList) {
+    let mut world = HitList::new();
+    let pertext = Arc::new(NoiseTexture::new(4.));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new_texture(pertext.clone())),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 2., 0.),
+        2.,
+        Arc::new(Lambertian::new_texture(pertext)),
+    )));
+
+    let mut lights = LightList::new();
+    lights.add(Arc::new(PointLight::new(
+        Vec3::new(4., 6., 4.),
+        Vec3::new(20., 20., 20.),
+    )));
+    lights.add(Arc::new(SpotLight::new(
+        Vec3::new(0., 8., -4.),
+        Vec3::new(0., 0., -4.),
+        Vec3::new(60., 30., 10.),
+        25.,
+        15.,
+    )));
+    lights.add(Arc::new(DirectionalLight::new(
+        Vec3::new(-1., -1., 0.5),
+        Vec3::new(0.1, 0.1, 0.15),
+    )));
+    lights.add(Arc::new(RectangleLight::new(
+        Vec3::new(3., 1., -2.),
+        Vec3::new(2., 0., 0.),
+        Vec3::new(0., 2., 0.),
+        Vec3::new(4., 4., 4.),
+    )));
+    lights.add(Arc::new(DiskLight::new(
+        Vec3::new(0., 7., 3.),
+        Vec3::new(0., -1., 0.),
+        1.,
+        Vec3::new(2., 4., 2.),
+    )));
+    lights.add(Arc::new(SphereLight::new(
+        Vec3::new(-3., 1., 4.),
+        0.5,
+        Vec3::new(2., 2., 6.),
+    )));
+    (world, lights)
+}
+/// A wall lit by three recessed ceiling lights,
+/// each emitting only downwards with a spotlight profile of a different width.
+pub fn recessed_lights() -> HitList {
+    let mut world = HitList::new();
+    let white = Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+    // floor, back wall and ceiling
+    world.add(Arc::new(XZRectangle::new(
+        -500.,
+        500.,
+        -500.,
+        100.,
+        0.,
+        white.clone(),
+    )));
+    world.add(Arc::new(XYRectangle::new(
+        -500.,
+        500.,
+        0.,
+        300.,
+        100.,
+        white.clone(),
+    )));
+    world.add(Arc::new(XZRectangle::new(
+        -500., 500., -500., 100., 300., white,
+    )));
+    for (i, width) in [15., 30., 60.].iter().enumerate() {
+        let x = -200. + 200. * i as f64;
+        let light = Arc::new(DiffuseLight::new_directional(
+            Arc::new(SolidColor::new(Vec3::new(160., 144., 120.))),
+            EmissionSides::Back,
+            EmissionProfile::spot(*width, width / 2.),
+        ));
+        world.add(Arc::new(XZRectangle::new(
+            x - 10.,
+            x + 10.,
+            60.,
+            80.,
+            299.,
+            light,
+        )));
+    }
+    world
+}
+/// A flat wall washed by the photometric profile of a downlight:
+/// two IES point lights close to the wall and a recessed IES emitter between them.
+pub fn ies_wall() -> (HitList, LightList) {
+    let mut world = HitList::new();
+    let white = Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+    world.add(Arc::new(XZRectangle::new(
+        -500.,
+        500.,
+        -500.,
+        100.,
+        0.,
+        white.clone(),
+    )));
+    world.add(Arc::new(XYRectangle::new(
+        -500., 500., 0., 300., 100., white,
+    )));
+
+    let profile = Arc::new(IesProfile::new("input/downlight.ies"));
+    let mut lights = LightList::new();
+    for x in [-250., 250.] {
+        lights.add(Arc::new(PointLight::new_ies(
+            Vec3::new(x, 290., 70.),
+            Vec3::new(0., -1., 0.),
+            Vec3::new(30000., 28000., 24000.),
+            profile.clone(),
+        )));
+    }
+    let light = Arc::new(DiffuseLight::new_directional(
+        Arc::new(SolidColor::new(Vec3::new(150., 150., 150.))),
+        EmissionSides::Back,
+        EmissionProfile::Ies(profile),
+    ));
+    world.add(Arc::new(XZRectangle::new(-10., 10., 60., 80., 299., light)));
+    (world, lights)
+}
+/// Spheres of gold, copper, aluminium and silver with the GGX conductor,
+/// from rough to polished, and a brushed aluminium one in front.
+pub fn microfacet_metals() -> HitList {
+    let mut world = HitList::new();
+    let checker = Arc::new(CheckerTexture::new_rgb(
+        Vec3::new(0.2, 0.3, 0.1),
+        Vec3::new(0.9, 0.9, 0.9),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new_texture(checker)),
+    )));
+    let metals: [Arc<dyn Material>; 4] = [
+        Arc::new(Conductor::gold(0.4)),
+        Arc::new(Conductor::copper(0.25)),
+        Arc::new(Conductor::aluminium(0.1)),
+        Arc::new(Conductor::silver(0.)),
+    ];
+    for (i, metal) in metals.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(0., 1., -4.5 + 3. * i as f64),
+            1.,
+            metal,
+        )));
+    }
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(3., 0.7, 0.),
+        0.7,
+        Arc::new(Conductor::new_anisotropic(
+            Vec3::new(1.65746, 0.880369, 0.521229),
+            Vec3::new(9.22387, 6.26952, 4.837),
+            0.05,
+            0.5,
+        )),
+    )));
+    let light = Arc::new(DiffuseLight::new(Vec3::new(6., 6., 6.)));
+    world.add(Arc::new(XZRectangle::new(-3., 3., -3., 3., 8., light)));
+    world
+}
+/// Smooth, frosted, colored and coated glass in front of a checkered backdrop.
+pub fn glass_materials() -> HitList {
+    let mut world = HitList::new();
+    let checker = Arc::new(CheckerTexture::new_rgb(
+        Vec3::new(0.2, 0.3, 0.1),
+        Vec3::new(0.9, 0.9, 0.9),
+    ));
+    let floor = Arc::new(Lambertian::new_texture(checker));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        floor.clone(),
+    )));
+    world.add(Arc::new(YZRectangle::new(0., 6., -8., 8., -4., floor)));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 1., -4.5),
+        1.,
+        Arc::new(Dielectric::new(1.5).with_roughness(0.3)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 1., -1.5),
+        1.,
+        Arc::new(Dielectric::new(1.5).with_absorption(Vec3::new(0.2, 0.5, 0.8), 1.)),
+    )));
+    // A hollow sphere of colored glass, only tinted through its shell.
+    let shell = Arc::new(Dielectric::new(1.5).with_absorption(Vec3::new(0.9, 0.3, 0.2), 0.2));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 1., 1.5),
+        1.,
+        shell.clone(),
+    )));
+    world.add(Arc::new(Sphere::new(Vec3::new(0., 1., 1.5), -0.85, shell)));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 1., 4.5),
+        1.,
+        Arc::new(Dielectric::new(1.).with_film(450., 1.33)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(2.5, 0.6, 0.),
+        0.6,
+        Arc::new(
+            Dielectric::new(1.5)
+                .with_roughness(0.1)
+                .with_film(110., 1.38),
+        ),
+    )));
+    let light = Arc::new(DiffuseLight::new(Vec3::new(6., 6., 6.)));
+    world.add(Arc::new(XZRectangle::new(-3., 3., -3., 3., 8., light)));
+    world
+}
+/// Spheres of dispersive glass, which split the light of a small lamp into rainbow caustics.
+/// Only rendered in spectral mode does the glass show its dispersion.
+pub fn dispersion() -> HitList {
+    let mut world = HitList::new();
+    let white = Arc::new(Lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
+    world.add(Arc::new(XZRectangle::new(-20., 20., -20., 20., 0., white)));
+    let checker = Arc::new(CheckerTexture::new_rgb(
+        Vec3::new(0.05, 0.05, 0.05),
+        Vec3::new(0.9, 0.9, 0.9),
+    ));
+    world.add(Arc::new(XYRectangle::new(
+        -20.,
+        20.,
+        0.,
+        20.,
+        -4.,
+        Arc::new(Lambertian::new_texture(checker)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(-2.2, 1., 0.),
+        1.,
+        Arc::new(Dielectric::dense_flint()),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 1., 0.),
+        1.,
+        Arc::new(Dielectric::bk7()),
+    )));
+    // A made up glass with a much stronger dispersion than any real one.
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(2.2, 1., 0.),
+        1.,
+        Arc::new(Dielectric::new_cauchy(1.5, 0.05)),
+    )));
+    let light = Arc::new(DiffuseLight::new(Vec3::new(60., 60., 60.)));
+    world.add(Arc::new(XZRectangle::new(-0.5, 0.5, -4., -3., 7., light)));
+    world
+}
+/// A row of `Principled` materials: plastic, gold, velvet, car paint, glass,
+/// a metal with rusty checkers and a glowing sphere.
+pub fn principled_materials() -> HitList {
+    let mut world = HitList::new();
+    let checker = Arc::new(CheckerTexture::new_rgb(
+        Vec3::new(0.2, 0.3, 0.1),
+        Vec3::new(0.9, 0.9, 0.9),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Principled::new_texture(checker).with_roughness(0.8)),
+    )));
+    let rust = Arc::new(CheckerTexture::new_rgb(
+        Vec3::new(0.9, 0.9, 0.9),
+        Vec3::new(0.45, 0.2, 0.1),
+    ));
+    let rust_metallic = Arc::new(CheckerTexture::new_rgb(Vec3::ones(), Vec3::zero()));
+    let rust_roughness = Arc::new(CheckerTexture::new_rgb(
+        Vec3::ones() * 0.2,
+        Vec3::ones() * 0.9,
+    ));
+    let materials: [Arc<dyn Material>; 7] = [
+        Arc::new(
+            Principled::new(Vec3::new(0.8, 0.1, 0.1))
+                .with_roughness(0.3)
+                .with_specular(0.7),
+        ),
+        Arc::new(
+            Principled::new(Vec3::new(1., 0.78, 0.34))
+                .with_metallic(1.)
+                .with_roughness(0.25),
+        ),
+        Arc::new(
+            Principled::new(Vec3::new(0.15, 0.05, 0.3))
+                .with_roughness(1.)
+                .with_sheen(1.),
+        ),
+        Arc::new(
+            Principled::new(Vec3::new(0.05, 0.2, 0.6))
+                .with_roughness(0.5)
+                .with_clearcoat(1., 0.05),
+        ),
+        Arc::new(
+            Principled::new(Vec3::new(0.9, 1., 0.95))
+                .with_roughness(0.05)
+                .with_transmission(1., 1.5),
+        ),
+        Arc::new(
+            Principled::new_texture(rust)
+                .with_metallic_texture(rust_metallic)
+                .with_roughness_texture(rust_roughness),
+        ),
+        Arc::new(Principled::new(Vec3::new(0.1, 0.1, 0.1)).with_emission(Vec3::new(4., 2.5, 1.))),
+    ];
+    for (i, material) in materials.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(0., 0.7, -6.3 + 2.1 * i as f64),
+            0.7,
+            material,
+        )));
+    }
+    let light = Arc::new(DiffuseLight::new(Vec3::new(6., 6., 6.)));
+    world.add(Arc::new(XZRectangle::new(-3., 3., -3., 3., 8., light)));
+    world
+}
+/// Rusty metal blended by a noise mask, and clear coats over ceramic, wood-like checkers and gold.
+pub fn mixed_materials() -> HitList {
+    let mut world = HitList::new();
+    let checker = Arc::new(CheckerTexture::new_rgb(
+        Vec3::new(0.2, 0.3, 0.1),
+        Vec3::new(0.9, 0.9, 0.9),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new_texture(checker)),
+    )));
+    let rust = Arc::new(MixMaterial::new_texture(
+        Arc::new(Conductor::aluminium(0.3)),
+        Arc::new(Lambertian::new(Vec3::new(0.45, 0.2, 0.1))),
+        Arc::new(NoiseTexture::new(4.)),
+    ));
+    let planks = Arc::new(CheckerTexture::new_rgb(
+        Vec3::new(0.4, 0.2, 0.08),
+        Vec3::new(0.25, 0.12, 0.05),
+    ));
+    let materials: [Arc<dyn Material>; 4] = [
+        rust,
+        Arc::new(LayeredMaterial::new(
+            Arc::new(Lambertian::new(Vec3::new(0.7, 0.1, 0.1))),
+            1.5,
+            0.,
+        )),
+        Arc::new(LayeredMaterial::new(
+            Arc::new(Lambertian::new_texture(planks)),
+            1.5,
+            0.2,
+        )),
+        Arc::new(LayeredMaterial::new(
+            Arc::new(Conductor::gold(0.5)),
+            1.5,
+            0.,
+        )),
+    ];
+    for (i, material) in materials.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(0., 1., -4.5 + 3. * i as f64),
+            1.,
+            material,
+        )));
+    }
+    // Half glass and half diffuse, which is specular as a whole.
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(3., 0.7, 0.),
+        0.7,
+        Arc::new(MixMaterial::new(
+            Arc::new(Lambertian::new(Vec3::new(0.2, 0.6, 0.3))),
+            Arc::new(Dielectric::new(1.5)),
+            0.5,
+        )),
+    )));
+    let light = Arc::new(DiffuseLight::new(Vec3::new(6., 6., 6.)));
+    world.add(Arc::new(XZRectangle::new(-3., 3., -3., 3., 8., light)));
+    world
+}
+/// Lambertian and Oren-Nayar clay side by side, lit from behind the camera,
+/// next to a backlit leaf and a paper lantern.
+pub fn rough_diffuse() -> HitList {
+    let mut world = HitList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(OrenNayar::new(Vec3::new(0.5, 0.5, 0.5), 60.)),
+    )));
+    let clay = Vec3::new(0.75, 0.45, 0.3);
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 1., -3.5),
+        1.,
+        Arc::new(Lambertian::new(clay)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 1., -1.2),
+        1.,
+        Arc::new(OrenNayar::new(clay, 40.)),
+    )));
+    // A leaf facing the camera, with a light hidden behind it.
+    world.add(Arc::new(YZRectangle::new(
+        0.2,
+        1.6,
+        0.1,
+        1.5,
+        1.,
+        Arc::new(Translucent::new(Vec3::new(0.3, 0.7, 0.1), 0.5)),
+    )));
+    world.add(Arc::new(YZRectangle::new(
+        0.5,
+        1.3,
+        0.4,
+        1.2,
+        0.5,
+        Arc::new(DiffuseLight::new(Vec3::new(4., 4., 3.5))),
+    )));
+    // A paper lantern with a bulb inside.
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 1.2, 3.),
+        1.,
+        Arc::new(Translucent::new(Vec3::new(0.9, 0.85, 0.7), 0.6)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 1.2, 3.),
+        0.2,
+        Arc::new(DiffuseLight::new(Vec3::new(20., 14., 8.))),
+    )));
+    let light = Arc::new(DiffuseLight::new(Vec3::new(3., 3., 3.)));
+    world.add(Arc::new(YZRectangle::new(1., 5., -4., 2., 12., light)));
+    world
+}
+/// The marble of `two_perlin_spheres` with real translucency, next to wax and skin,
+/// backlit by the sun.
+pub fn subsurface_marble() -> HitList {
+    let mut world = HitList::new();
+    let pertext = Arc::new(NoiseTexture::new(4.));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new_texture(pertext.clone())),
+    )));
+    let unused: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::zero()));
+    world.add(Arc::new(Subsurface::new_texture(
+        Arc::new(Sphere::new(Vec3::new(0., 2., 0.), 2., unused.clone())),
+        pertext,
+        Vec3::new(0.6, 0.5, 0.4),
+        1.5,
+    )));
+    world.add(Arc::new(Subsurface::new(
+        Arc::new(Sphere::new(Vec3::new(2., 0.6, 3.), 0.6, unused.clone())),
+        Vec3::new(0.95, 0.85, 0.6),
+        Vec3::new(0.4, 0.2, 0.1),
+        1.45,
+    )));
+    world.add(Arc::new(Subsurface::new(
+        Arc::new(Sphere::new(Vec3::new(2., 0.6, -3.), 0.6, unused)),
+        Vec3::new(0.85, 0.55, 0.45),
+        Vec3::new(0.37, 0.14, 0.08),
+        1.4,
+    )));
+    let sun = Arc::new(DiffuseLight::new(Vec3::new(8., 7.5, 7.)));
+    world.add(Arc::new(Sphere::new(Vec3::new(-15., 10., 0.), 3., sun)));
+    world
+}
+/// `cornell_smoke` with a cloud made of overlapping spheres and a box of uneven fine smoke,
+/// whose densities come from Perlin turbulence.
+pub fn cornell_clouds() -> HitList {
+    let mut world = HitList::new();
+    let red = Arc::new(Lambertian::new(Vec3::new(0.65, 0.05, 0.05)));
+    let white = Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+    let green = Arc::new(Lambertian::new(Vec3::new(0.12, 0.45, 0.15)));
+    let light = Arc::new(DiffuseLight::new_directional(
+        Arc::new(SolidColor::new(Vec3::new(7., 7., 7.))),
+        EmissionSides::Back,
+        EmissionProfile::Uniform,
+    ));
+    world.add(Arc::new(YZRectangle::new(0., 555., 0., 555., 555., green)));
+    world.add(Arc::new(YZRectangle::new(0., 555., 0., 555., 0., red)));
+    world.add(Arc::new(XZRectangle::new(
+        113., 443., 127., 432., 554., light,
+    )));
+    world.add(Arc::new(XZRectangle::new(
+        0.,
+        555.,
+        0.,
+        555.,
+        0.,
+        white.clone(),
+    )));
+    world.add(Arc::new(XZRectangle::new(
+        0.,
+        555.,
+        0.,
+        555.,
+        555.,
+        white.clone(),
+    )));
+    world.add(Arc::new(XYRectangle::new(
+        0.,
+        555.,
+        0.,
+        555.,
+        555.,
+        white.clone(),
+    )));
+    // The rays leave one sphere of the cloud and enter the next.
+    let mut cloud = HitList::new();
+    for (center, radius) in [
+        (Vec3::new(200., 380., 300.), 90.),
+        (Vec3::new(300., 400., 280.), 110.),
+        (Vec3::new(390., 370., 320.), 80.),
+    ] {
+        cloud.add(Arc::new(Sphere::new(center, radius, white.clone())));
+    }
+    world.add(Arc::new(
+        HeterogeneousMedium::new(
+            Arc::new(cloud),
+            Arc::new(TurbulenceTexture::new(0.01)),
+            0.2,
+            Vec3::ones(),
+        )
+        .with_phase_function(PhaseFunction::DoubleHenyeyGreenstein {
+            g1: 0.8,
+            g2: -0.3,
+            weight: 0.9,
+        }),
+    ));
+    world.add(Arc::new(
+        HeterogeneousMedium::new(
+            Arc::new(Translate::new(
+                Arc::new(RotateY::new(
+                    Arc::new(CornellBox::new(
+                        Vec3::new(0., 0., 0.),
+                        Vec3::new(165., 200., 165.),
+                        white,
+                    )),
+                    -18.,
+                )),
+                Vec3::new(130., 0., 65.),
+            )),
+            Arc::new(TurbulenceTexture::new(0.03)),
+            0.3,
+            Vec3::new(0.2, 0.2, 0.2),
+        )
+        .with_tracking(Tracking::Delta)
+        .with_phase_function(PhaseFunction::Rayleigh),
+    ));
+    world
+}
+/// Lamps in a foggy night. The fog scatters forward,
+/// so each lamp has a glow around it that is brightest when looked at from the other side.
+pub fn foggy_lights() -> (HitList, LightList) {
+    let mut world = HitList::new();
+    let ground = Arc::new(Lambertian::new(Vec3::new(0.4, 0.4, 0.4)));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        ground,
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 1., 0.),
+        1.,
+        Arc::new(Lambertian::new(Vec3::new(0.7, 0.3, 0.2))),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(-3., 1., -2.),
+        1.,
+        Arc::new(Conductor::aluminium(0.2)),
+    )));
+    let unused = Arc::new(Lambertian::new(Vec3::zero()));
+    world.add(Arc::new(
+        ConstantMedium::new(
+            Arc::new(Sphere::new(Vec3::zero(), 40., unused)),
+            0.04,
+            Vec3::new(0.9, 0.9, 0.9),
+        )
+        .with_phase_function(PhaseFunction::HenyeyGreenstein { g: 0.8 }),
+    ));
+
+    let mut lights = LightList::new();
+    lights.add(Arc::new(PointLight::new(
+        Vec3::new(-2., 4., -6.),
+        Vec3::new(30., 25., 15.),
+    )));
+    lights.add(Arc::new(PointLight::new(
+        Vec3::new(4., 4., -4.),
+        Vec3::new(15., 20., 30.),
+    )));
+    lights.add(Arc::new(SpotLight::new(
+        Vec3::new(1., 6., -1.),
+        Vec3::new(0., 0., 0.),
+        Vec3::new(60., 60., 60.),
+        25.,
+        15.,
+    )));
+    (world, lights)
+}
+/// A ball of fire, a cloud of glowing gas and a block of tinted liquid,
+/// with a white ball behind the liquid to see its color.
+pub fn glowing_media() -> HitList {
+    let mut world = HitList::new();
+    let checker = Arc::new(CheckerTexture::new_rgb(
+        Vec3::new(0.2, 0.2, 0.2),
+        Vec3::new(0.8, 0.8, 0.8),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new_texture(checker)),
+    )));
+    let unused: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::zero()));
+    // Fire absorbs a little and glows orange.
+    world.add(Arc::new(
+        ParticipatingMedium::new(
+            Arc::new(Sphere::new(Vec3::new(-3., 1.2, 0.), 1.2, unused.clone())),
+            Vec3::new(0.8, 0.8, 0.8),
+            Vec3::zero(),
+        )
+        .with_emission(Vec3::new(6., 2., 0.4)),
+    ));
+    // The gas scatters as much as it emits, in the same wisps.
+    let wisps: Arc<dyn Texture> = Arc::new(TurbulenceTexture::new(2.));
+    world.add(Arc::new(
+        ParticipatingMedium::new_texture(
+            Arc::new(Sphere::new(Vec3::new(3., 1.5, 0.), 1.5, unused.clone())),
+            Arc::new(SolidColor::new(Vec3::new(0.1, 0.1, 0.1))),
+            wisps.clone(),
+            2.1,
+        )
+        .with_emission_texture(wisps)
+        .with_phase_function(PhaseFunction::HenyeyGreenstein { g: 0.5 }),
+    ));
+    // The liquid only absorbs, most of all the blue and green.
+    world.add(Arc::new(ParticipatingMedium::new(
+        Arc::new(Translate::new(
+            Arc::new(RotateY::new(
+                Arc::new(CornellBox::new(
+                    Vec3::new(-1., 0., -1.),
+                    Vec3::new(1., 2., 1.),
+                    unused,
+                )),
+                30.,
+            )),
+            Vec3::new(0., 0., -1.),
+        )),
+        Vec3::new(0.1, 0.8, 1.2),
+        Vec3::zero(),
+    )));
+    // Something to see through the liquid.
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 0.6, -3.),
+        0.6,
+        Arc::new(Lambertian::new(Vec3::new(0.9, 0.9, 0.9))),
+    )));
+    world
+}
+/// A plume of smoke read from a voxel grid, rising from the ground under a warm light.
+pub fn voxel_smoke() -> HitList {
+    let mut world = HitList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world.add(Arc::new(XZRectangle::new(
+        -1.,
+        1.,
+        -1.,
+        1.,
+        6.,
+        Arc::new(DiffuseLight::new(Vec3::new(12., 10., 8.))),
+    )));
+    // The densest voxel has a density of 8.
+    let grid = VoxelGrid::load("input/plume.rtgrid").normalized();
+    let bounds = grid.bounding_box();
+    let unused = Arc::new(Lambertian::new(Vec3::zero()));
+    world.add(Arc::new(
+        HeterogeneousMedium::new(
+            Arc::new(CornellBox::new(bounds.min(), bounds.max(), unused)),
+            Arc::new(grid),
+            8.,
+            Vec3::new(0.8, 0.8, 0.8),
+        )
+        .with_phase_function(PhaseFunction::HenyeyGreenstein { g: 0.3 }),
+    ));
+    world
+}
+/// A floor tiled with the earth map into the distance, where its mipmap keeps it from shimmering,
+/// a globe close up, whose bicubic filtering keeps it from looking blocky,
+/// and a metal ball that the map makes rough on land.
+pub fn tiled_floor() -> HitList {
+    let mut world = HitList::new();
+    let tiles = ImageTexture::new("input/earthmap.jpg")
+        .with_wrap(Wrap::Mirror, Wrap::Mirror)
+        .with_uv_transform((25., 25.), (0.5, 0.));
+    world.add(Arc::new(XZRectangle::new(
+        -50.,
+        50.,
+        -50.,
+        50.,
+        0.,
+        Arc::new(Lambertian::new_texture(Arc::new(tiles))),
+    )));
+    let globe = ImageTexture::new("input/earthmap.jpg")
+        .with_wrap(Wrap::Repeat, Wrap::Clamp)
+        .with_filter(Filter::Bicubic);
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 1., 0.),
+        1.,
+        Arc::new(Lambertian::new_texture(Arc::new(globe))),
+    )));
+    // The map again as roughness, which is not a color: the dark seas are glossy and the land is not.
+    let roughness =
+        ImageTexture::new_non_color("input/earthmap.jpg").with_wrap(Wrap::Repeat, Wrap::Clamp);
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(2.2, 0.6, -0.5),
+        0.6,
+        Arc::new(
+            Principled::new(Vec3::new(0.6, 0.6, 0.65))
+                .with_metallic(1.)
+                .with_roughness_texture(Arc::new(roughness)),
+        ),
+    )));
+    world
+}
+/// Leaves on cards cut out by the alpha of their image, and their shadows on the ground.
+/// The cards in the back let rays through by chance, which smooths their edges.
+pub fn falling_leaves() -> HitList {
+    let mut world = HitList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new(Vec3::new(0.6, 0.55, 0.45))),
+    )));
+    world.add(Arc::new(XZRectangle::new(
+        -1.5,
+        1.5,
+        -1.5,
+        1.5,
+        8.,
+        Arc::new(DiffuseLight::new(Vec3::new(8., 7.5, 7.))),
+    )));
+    let leaf = Arc::new(ImageTexture::new("input/leaf.png"));
+    let material = Arc::new(Lambertian::new_texture(leaf.clone()));
+    for i in 0..12 {
+        let card = Arc::new(XYRectangle::new(-0.5, 0.5, -0.5, 0.5, 0., material.clone()));
+        let mut masked = AlphaMask::new(card, leaf.clone());
+        if i >= 6 {
+            masked = masked.with_alpha_test(AlphaTest::Stochastic);
+        }
+        let position = Vec3::new(
+            random_double_in_range(-2.5, 2.5),
+            random_double_in_range(0.4, 2.5),
+            if i >= 6 { -2. } else { 0. } + random_double_in_range(-0.5, 0.5),
+        );
+        world.add(Arc::new(Translate::new(
+            Arc::new(RotateY::new(
+                Arc::new(masked),
+                random_double_in_range(-60., 60.),
+            )),
+            position,
+        )));
+    }
+    world
+}
+/// Surface detail without geometry: a tiled floor from a normal map,
+/// and balls of hammered metal and plaster bumped by Perlin noise.
+pub fn bumpy_surfaces() -> HitList {
+    let mut world = HitList::new();
+    let tiles = ImageTexture::new_non_color("input/tiles_normal.png")
+        .with_wrap(Wrap::Repeat, Wrap::Repeat)
+        .with_uv_transform((4., 4.), (0., 0.));
+    world.add(Arc::new(XZRectangle::new(
+        -6.,
+        6.,
+        -6.,
+        6.,
+        0.,
+        Arc::new(
+            NormalMap::new(
+                Arc::new(Lambertian::new(Vec3::new(0.6, 0.55, 0.5))),
+                Arc::new(tiles),
+            )
+            .with_strength(1.5),
+        ),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(-1.3, 1., 0.),
+        1.,
+        Arc::new(BumpMap::new(
+            Arc::new(Conductor::aluminium(0.15)),
+            Arc::new(TurbulenceTexture::new(4.)),
+            0.02,
+        )),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(1.3, 1., 0.),
+        1.,
+        Arc::new(BumpMap::new(
+            Arc::new(Lambertian::new(Vec3::new(0.8, 0.8, 0.75))),
+            Arc::new(NoiseTexture::new(6.)),
+            0.01,
+        )),
+    )));
+    world.add(Arc::new(XZRectangle::new(
+        -1.,
+        1.,
+        -1.,
+        1.,
+        6.,
+        Arc::new(DiffuseLight::new(Vec3::new(10., 10., 10.))),
+    )));
+    world
+}
+/// A row of balls showing off the procedural textures:
+/// fBm, ridged noise, Worley cells, wood, marble and warped fBm.
+/// All the noise is seeded, so the scene looks the same on every run.
+pub fn procedural_textures() -> HitList {
+    let mut world = HitList::new();
+    let cobbles = TransformedTexture::new(Arc::new(
+        WorleyTexture::new(2., Cellular::F2MinusF1).with_seed(1),
+    ))
+    .with_scale(Vec3::new(1., 1., 2.));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new_texture(Arc::new(cobbles))),
+    )));
+    let marble = ColorRamp::new(vec![
+        (0., Vec3::new(0.1, 0.12, 0.1)),
+        (0.4, Vec3::new(0.35, 0.5, 0.4)),
+        (1., Vec3::new(0.9, 0.92, 0.88)),
+    ]);
+    let textures: [Arc<dyn Texture>; 6] = [
+        Arc::new(FbmTexture::new(3.).with_noise(Perlin::with_seed(2))),
+        Arc::new(
+            RidgedTexture::new(2.)
+                .with_octaves(7, 2.2, 0.6)
+                .with_noise(Perlin::with_seed(3)),
+        ),
+        Arc::new(WorleyTexture::new(4., Cellular::F1).with_seed(4)),
+        Arc::new(
+            TransformedTexture::new(Arc::new(
+                WoodTexture::new(6., Vec3::new(0.75, 0.5, 0.28), Vec3::new(0.35, 0.18, 0.08))
+                    .with_distortion(0.8)
+                    .with_noise(Perlin::with_seed(5)),
+            ))
+            .with_rotation(Vec3::new(1., 0., 0.), 80.)
+            .with_translation(Vec3::new(0.8, 1., 0.)),
+        ),
+        Arc::new(
+            MarbleTexture::new(4., marble)
+                .with_turbulence(6.)
+                .with_noise(Perlin::with_seed(6)),
+        ),
+        Arc::new(
+            DomainWarp::new(
+                Arc::new(
+                    FbmTexture::new(2.)
+                        .with_octaves(4, 2.5, 0.4)
+                        .with_noise(Perlin::with_seed(7)),
+                ),
+                1.,
+                1.5,
+            )
+            .with_noise(Perlin::with_seed(8)),
+        ),
+    ];
+    for (i, texture) in textures.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-5. + 2. * i as f64, 0.8, 0.),
+            0.8,
+            Arc::new(Lambertian::new_texture(texture)),
+        )));
+    }
+    world
+}
+/// Textures built from node graphs in text files: a sphere of paint flaking into rust,
+/// beside a box of cobblestone projected along the three axes, which needs no U,V.
+pub fn texture_graphs() -> HitList {
+    let mut world = HitList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(-1.3, 1., 0.),
+        1.,
+        Arc::new(Lambertian::new_texture(load_texture_graph(
+            "input/rusty_paint.tgraph",
+        ))),
+    )));
+    let stone = Arc::new(Lambertian::new_texture(load_texture_graph(
+        "input/stone.tgraph",
+    )));
+    world.add(Arc::new(Translate::new(
+        Arc::new(RotateY::new(
+            Arc::new(CornellBox::new(Vec3::zero(), Vec3::ones() * 1.6, stone)),
+            30.,
+        )),
+        Vec3::new(0.4, 0., -0.6),
+    )));
+    world
+}
+/// A U,V checkered floor into the distance, filtered over the footprint of the rays,
+/// in front of a sphere checkered in U,V and one cut out of a solid checker.
+pub fn checkers() -> HitList {
+    let mut world = HitList::new();
+    let floor = CheckerTexture::new_rgb(Vec3::new(0.9, 0.9, 0.9), Vec3::new(0.1, 0.1, 0.1))
+        .with_mode(CheckerMode::Uv)
+        .with_scale(100.)
+        .with_antialiasing();
+    world.add(Arc::new(XZRectangle::new(
+        -50.,
+        50.,
+        -50.,
+        50.,
+        0.,
+        Arc::new(Lambertian::new_texture(Arc::new(floor))),
+    )));
+    let wrapped = CheckerTexture::new_rgb(Vec3::new(0.8, 0.3, 0.1), Vec3::new(0.9, 0.85, 0.7))
+        .with_mode(CheckerMode::Uv)
+        .with_scale(8.);
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(-1.1, 1., 0.),
+        1.,
+        Arc::new(Lambertian::new_texture(Arc::new(wrapped))),
+    )));
+    let solid =
+        CheckerTexture::new_rgb(Vec3::new(0.1, 0.3, 0.7), Vec3::new(0.9, 0.85, 0.7)).with_scale(2.);
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(1.1, 1., 0.),
+        1.,
+        Arc::new(Lambertian::new_texture(Arc::new(solid))),
+    )));
+    world
+}
+/// Noise under control: a ground that repeats every four units,
+/// and spheres that look the same on every run, their noise churning while the shutter is open.
+pub fn noise_controls() -> HitList {
+    let mut world = HitList::new();
+    let ground = RidgedTexture::new(1.).with_noise(Perlin::with_seed(11).with_period(4));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new_texture(Arc::new(ground))),
+    )));
+    let textures: [Arc<dyn Texture>; 3] = [
+        Arc::new(
+            NoiseTexture::new(4.)
+                .with_noise(Perlin::with_seed(5))
+                .with_evolution(0.6),
+        ),
+        Arc::new(
+            TurbulenceTexture::new(3.)
+                .with_noise(Perlin::with_seed(5))
+                .with_evolution(0.6),
+        ),
+        Arc::new(
+            FbmTexture::new(3.)
+                .with_noise(Perlin::with_seed(5))
+                .with_evolution(0.6),
+        ),
+    ];
+    for (i, texture) in textures.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-2.2 + 2.2 * i as f64, 1., 0.),
+            1.,
+            Arc::new(Lambertian::new_texture(texture)),
+        )));
+    }
+    world
+}
+/// The primitives beyond spheres and rectangles, checkered in U,V to show how it wraps them,
+/// lit by a disk in the sky, with smoke held in a capped cylinder.
+pub fn primitives() -> HitList {
+    let mut world = HitList::new();
+    let checker = |even: Vec3, scale: f64| -> Arc<dyn Material> {
+        Arc::new(Lambertian::new_texture(Arc::new(
+            CheckerTexture::new_rgb(even, Vec3::new(0.9, 0.9, 0.85))
+                .with_mode(CheckerMode::Uv)
+                .with_scale(scale)
+                .with_antialiasing(),
+        )))
+    };
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world.add(Arc::new(Disk::new(
+        Vec3::new(0., 8., 2.),
+        Vec3::new(0., -1., 0.),
+        2.,
+        Arc::new(DiffuseLight::new(Vec3::new(6., 6., 6.))),
+    )));
+    world.add(Arc::new(Quad::new(
+        Vec3::new(-5., 0., -2.),
+        Vec3::new(2.5, 0., 0.5),
+        Vec3::new(-0.3, 2.5, -0.6),
+        checker(Vec3::new(0.2, 0.4, 0.8), 6.),
+    )));
+    world.add(Arc::new(
+        Cylinder::new(
+            Vec3::new(-1.8, 0., 0.),
+            Vec3::new(-1.8, 1.6, 0.),
+            0.7,
+            checker(Vec3::new(0.8, 0.3, 0.1), 8.),
+        )
+        .with_caps(),
+    ));
+    world.add(Arc::new(
+        Cone::new(
+            Vec3::new(0., 0., 0.5),
+            Vec3::new(0., 2., 0.5),
+            0.8,
+            checker(Vec3::new(0.1, 0.6, 0.3), 8.),
+        )
+        .with_cap(),
+    ));
+    world.add(Arc::new(Torus::new(
+        Vec3::new(2., 0.9, 0.),
+        Vec3::new(0., 0.6, 1.),
+        0.7,
+        0.25,
+        checker(Vec3::new(0.7, 0.1, 0.5), 12.),
+    )));
+    let smoke = Cylinder::new(
+        Vec3::new(4., 0., -1.),
+        Vec3::new(4., 2.5, -1.),
+        0.6,
+        Arc::new(Lambertian::new(Vec3::zero())),
+    )
+    .with_caps();
+    world.add(Arc::new(ConstantMedium::new(
+        Arc::new(smoke),
+        1.5,
+        Vec3::new(0.9, 0.9, 0.9),
+    )));
+    // An open tube, which shows its inside.
+    world.add(Arc::new(Cylinder::new(
+        Vec3::new(-3.6, 0., 1.5),
+        Vec3::new(-3.2, 0.6, 2.),
+        0.4,
+        Arc::new(Metal::new(Vec3::new(0.8, 0.8, 0.7), 0.1)),
+    )));
+    world
+}
@@ -0,0 +1,667 @@
+use crate::aabb::*;
+use crate::hit::*;
+use crate::material::Material;
+use crate::onb::ONB;
+use crate::quad::padded_box;
+use crate::ray::Ray;
+use crate::rt_weekend::*;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+/// A flat round disk around `center`, whose front face is the side `normal` points to.
+///
+/// U goes around the center and V from the center out to the rim.
+pub struct Disk {
+    frame: Frame,
+    radius: f64,
+    material: Arc<dyn Material>,
+    /// the emitted power, cached for the light distribution
+    power: f64,
+}
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: f64, material: Arc<dyn Material>) -> Self {
+        let frame = Frame::new(center, normal);
+        // The area inside a radius grows with its square.
+        let power = surface_power(&material, PI * radius * radius, |s, t| {
+            let (rho, phi) = (radius * t.sqrt(), 2. * PI * s);
+            let local = Vec3::new(rho * phi.cos(), rho * phi.sin(), 0.);
+            let p = center + frame.basis.local_vec(&local);
+            (
+                s,
+                t.sqrt(),
+                p,
+                frame.basis.local_vec(&Vec3::new(0., 0., 1.)),
+            )
+        });
+        Self {
+            frame,
+            radius,
+            material,
+            power,
+        }
+    }
+}
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let (o, d) = self.frame.to_local(ray);
+        match disk_hit(o, d, 0., self.radius, true, t_min, t_max) {
+            Some(hit) => {
+                self.frame.record(ray, hit, &self.material, rec);
+                rec.sampled_light = self.power > 0.;
+                true
+            }
+            None => false,
+        }
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let r = self.radius;
+        *output_box = self
+            .frame
+            .bounding_box(Vec3::new(-r, -r, 0.), Vec3::new(r, r, 0.));
+        true
+    }
+    fn pdf_value(&self, origin: &Vec3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new(self.material.clone());
+        if !self.hit(&Ray::new(*origin, *v, 0.), 0.001, f64::INFINITY, &mut rec) {
+            return 0.;
+        }
+        let area = PI * self.radius * self.radius;
+        let distance_squared = rec.t * rec.t * v.squared_length();
+        let cosine = (*v * rec.normal).abs() / v.length();
+        distance_squared / (cosine * area)
+    }
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        let rho = self.radius * random_double().sqrt();
+        let phi = 2. * PI * random_double();
+        let local = Vec3::new(rho * phi.cos(), rho * phi.sin(), 0.);
+        self.frame.origin + self.frame.basis.local_vec(&local) - *origin
+    }
+    fn emitted_power(&self) -> f64 {
+        self.power
+    }
+}
+/// A tube of `radius` from the center of its `base` to the center of its `top`,
+/// open at the ends unless it gets caps, which it needs to hold a medium.
+///
+/// On the side, U goes around the axis and V along it from the base.
+/// On the caps, U goes around the axis and V from the center out to the rim.
+pub struct Cylinder {
+    frame: Frame,
+    radius: f64,
+    height: f64,
+    capped: bool,
+    material: Arc<dyn Material>,
+}
+impl Cylinder {
+    pub fn new(base: Vec3, top: Vec3, radius: f64, material: Arc<dyn Material>) -> Self {
+        Self {
+            frame: Frame::new(base, top - base),
+            radius,
+            height: (top - base).length(),
+            capped: false,
+            material,
+        }
+    }
+    /// Close both ends with disks.
+    pub fn with_caps(mut self) -> Self {
+        self.capped = true;
+        self
+    }
+}
+impl Hittable for Cylinder {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let (o, d) = self.frame.to_local(ray);
+        let (r, h) = (self.radius, self.height);
+        let mut closest: Option<LocalHit> = None;
+        let mut t_max = t_max;
+        let a = d.x() * d.x() + d.y() * d.y();
+        let half_b = o.x() * d.x() + o.y() * d.y();
+        let c = o.x() * o.x() + o.y() * o.y() - r * r;
+        let (roots, count) = quadratic_roots(a, half_b, c);
+        for &t in &roots[..count] {
+            let p = o + d * t;
+            if t < t_min || t > t_max || p.z() < 0. || p.z() > h {
+                continue;
+            }
+            let phi = azimuth(p.x(), p.y());
+            closest = Some(LocalHit {
+                t,
+                normal: Vec3::new(p.x() / r, p.y() / r, 0.),
+                u: phi / (2. * PI),
+                v: p.z() / h,
+                dpdu: Vec3::new(-p.y(), p.x(), 0.) * (2. * PI),
+                dpdv: Vec3::new(0., 0., h),
+            });
+            t_max = t;
+            break;
+        }
+        if self.capped {
+            for (z, up) in [(0., false), (h, true)] {
+                if let Some(hit) = disk_hit(o, d, z, r, up, t_min, t_max) {
+                    t_max = hit.t;
+                    closest = Some(hit);
+                }
+            }
+        }
+        match closest {
+            Some(hit) => {
+                self.frame.record(ray, hit, &self.material, rec);
+                true
+            }
+            None => false,
+        }
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let r = self.radius;
+        *output_box = self
+            .frame
+            .bounding_box(Vec3::new(-r, -r, 0.), Vec3::new(r, r, self.height));
+        true
+    }
+}
+/// A cone with a round base of `radius` around the center of its `base`, narrowing to the `apex`,
+/// open at the base unless it gets a cap, which it needs to hold a medium.
+///
+/// U and V are laid out like on a `Cylinder`, with V reaching 1 at the apex.
+pub struct Cone {
+    frame: Frame,
+    radius: f64,
+    height: f64,
+    capped: bool,
+    material: Arc<dyn Material>,
+}
+impl Cone {
+    pub fn new(base: Vec3, apex: Vec3, radius: f64, material: Arc<dyn Material>) -> Self {
+        Self {
+            frame: Frame::new(base, apex - base),
+            radius,
+            height: (apex - base).length(),
+            capped: false,
+            material,
+        }
+    }
+    /// Close the base with a disk.
+    pub fn with_cap(mut self) -> Self {
+        self.capped = true;
+        self
+    }
+}
+impl Hittable for Cone {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let (o, d) = self.frame.to_local(ray);
+        let (r, h) = (self.radius, self.height);
+        // The side is where x² + y² = k²(h - z)², between the base and the apex.
+        let k2 = (r / h) * (r / h);
+        let a = d.x() * d.x() + d.y() * d.y() - k2 * d.z() * d.z();
+        let half_b = o.x() * d.x() + o.y() * d.y() + k2 * (h - o.z()) * d.z();
+        let c = o.x() * o.x() + o.y() * o.y() - k2 * (h - o.z()) * (h - o.z());
+        let mut closest: Option<LocalHit> = None;
+        let mut t_max = t_max;
+        let (roots, count) = quadratic_roots(a, half_b, c);
+        for &t in &roots[..count] {
+            let p = o + d * t;
+            if t < t_min || t > t_max || p.z() < 0. || p.z() > h {
+                continue;
+            }
+            let phi = azimuth(p.x(), p.y());
+            closest = Some(LocalHit {
+                t,
+                normal: Vec3::new(p.x(), p.y(), k2 * (h - p.z())).unit(),
+                u: phi / (2. * PI),
+                v: p.z() / h,
+                dpdu: Vec3::new(-p.y(), p.x(), 0.) * (2. * PI),
+                dpdv: Vec3::new(-r * phi.cos(), -r * phi.sin(), h),
+            });
+            t_max = t;
+            break;
+        }
+        if self.capped {
+            if let Some(hit) = disk_hit(o, d, 0., r, false, t_min, t_max) {
+                closest = Some(hit);
+            }
+        }
+        match closest {
+            Some(hit) => {
+                self.frame.record(ray, hit, &self.material, rec);
+                true
+            }
+            None => false,
+        }
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let r = self.radius;
+        *output_box = self
+            .frame
+            .bounding_box(Vec3::new(-r, -r, 0.), Vec3::new(r, r, self.height));
+        true
+    }
+}
+/// A ring around `center` whose tube of `minor_radius` follows a circle of `major_radius`
+/// in the plane perpendicular to `axis`.
+///
+/// U goes around the axis and V around the tube, starting from the outside.
+pub struct Torus {
+    frame: Frame,
+    major_radius: f64,
+    minor_radius: f64,
+    material: Arc<dyn Material>,
+}
+impl Torus {
+    pub fn new(
+        center: Vec3,
+        axis: Vec3,
+        major_radius: f64,
+        minor_radius: f64,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            frame: Frame::new(center, axis),
+            major_radius,
+            minor_radius,
+            material,
+        }
+    }
+}
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let (o, d) = self.frame.to_local(ray);
+        let (big, small) = (self.major_radius, self.minor_radius);
+        let extent = Vec3::new(big + small, big + small, small);
+        let (mut t0, mut t1) = (t_min, t_max);
+        let axes = [
+            (o.x(), d.x(), extent.x()),
+            (o.y(), d.y(), extent.y()),
+            (o.z(), d.z(), extent.z()),
+        ];
+        for (o, d, e) in axes {
+            if d.abs() < 1e-12 {
+                if o.abs() > e {
+                    return false;
+                }
+                continue;
+            }
+            let (near, far) = ((-e - o) / d, (e - o) / d);
+            t0 = t0.max(near.min(far));
+            t1 = t1.min(near.max(far));
+        }
+        if t0 >= t1 {
+            return false;
+        }
+        // The surface is the quartic (|p|² + R² - r²)² = 4R²(x² + y²), solved from where the ray
+        // enters the box with a unit direction, which keeps its coefficients well scaled.
+        let scale = d.length();
+        let (o, d) = (o + d * t0, d / scale);
+        let k = o.squared_length() + big * big - small * small;
+        let od = o * d;
+        let planar = d.x() * d.x() + d.y() * d.y();
+        let planar_od = o.x() * d.x() + o.y() * d.y();
+        let planar_oo = o.x() * o.x() + o.y() * o.y();
+        let (roots, count) = quartic_roots(
+            4. * od,
+            4. * od * od + 2. * k - 4. * big * big * planar,
+            4. * od * k - 8. * big * big * planar_od,
+            k * k - 4. * big * big * planar_oo,
+        );
+        let s = match roots[..count]
+            .iter()
+            .find(|&&s| s >= 0. && s <= (t1 - t0) * scale)
+        {
+            Some(&s) => s,
+            None => return false,
+        };
+        let t = t0 + s / scale;
+        let p = o + d * s;
+        let phi = azimuth(p.x(), p.y());
+        let rho = (p.x() * p.x() + p.y() * p.y()).sqrt();
+        let theta = azimuth(rho - big, p.z());
+        let ring = Vec3::new(phi.cos(), phi.sin(), 0.);
+        let hit = LocalHit {
+            t,
+            normal: ring * theta.cos() + Vec3::new(0., 0., theta.sin()),
+            u: phi / (2. * PI),
+            v: theta / (2. * PI),
+            dpdu: Vec3::new(-p.y(), p.x(), 0.) * (2. * PI),
+            dpdv: (ring * (-small * theta.sin()) + Vec3::new(0., 0., small * theta.cos()))
+                * (2. * PI),
+        };
+        self.frame.record(ray, hit, &self.material, rec);
+        true
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let extent = self.major_radius + self.minor_radius;
+        let e = Vec3::new(extent, extent, self.minor_radius);
+        *output_box = self.frame.bounding_box(-e, e);
+        true
+    }
+}
+/// Where a shape is and which way it points: its local z-axis is `axis` through `origin`.
+struct Frame {
+    origin: Vec3,
+    basis: ONB,
+}
+impl Frame {
+    fn new(origin: Vec3, axis: Vec3) -> Self {
+        Self {
+            origin,
+            basis: ONB::build_from_w(&axis),
+        }
+    }
+    /// The origin and direction of the ray in local coordinates.
+    fn to_local(&self, ray: &Ray) -> (Vec3, Vec3) {
+        (
+            self.basis.world_to_local(&(ray.origin() - self.origin)),
+            self.basis.world_to_local(&ray.direction()),
+        )
+    }
+    /// Fill in `rec` with a hit found in local coordinates.
+    fn record(&self, ray: &Ray, hit: LocalHit, material: &Arc<dyn Material>, rec: &mut HitRecord) {
+        rec.t = hit.t;
+        rec.p = ray.at(hit.t);
+        rec.set_face_normal(ray, self.basis.local_vec(&hit.normal));
+        rec.u = hit.u;
+        rec.v = hit.v;
+        rec.dpdu = self.basis.local_vec(&hit.dpdu);
+        rec.dpdv = self.basis.local_vec(&hit.dpdv);
+        rec.material = material.clone();
+        rec.sampled_light = false;
+    }
+    /// The world space box around the local box from `min` to `max`.
+    fn bounding_box(&self, min: Vec3, max: Vec3) -> AABB {
+        let mut lo = Vec3::ones() * f64::INFINITY;
+        let mut hi = Vec3::ones() * f64::NEG_INFINITY;
+        for corner in 0..8 {
+            let local = Vec3::new(
+                if corner & 1 == 0 { min.x() } else { max.x() },
+                if corner & 2 == 0 { min.y() } else { max.y() },
+                if corner & 4 == 0 { min.z() } else { max.z() },
+            );
+            let p = self.origin + self.basis.local_vec(&local);
+            lo = Vec3::new(lo.x().min(p.x()), lo.y().min(p.y()), lo.z().min(p.z()));
+            hi = Vec3::new(hi.x().max(p.x()), hi.y().max(p.y()), hi.z().max(p.z()));
+        }
+        padded_box(lo, hi)
+    }
+}
+/// A hit in the local coordinates of a `Frame`, with the outward normal.
+struct LocalHit {
+    t: f64,
+    normal: Vec3,
+    u: f64,
+    v: f64,
+    dpdu: Vec3,
+    dpdv: Vec3,
+}
+/// The hit of the local ray with the disk of `radius` around the axis at height `z`,
+/// facing up the axis if `up`.
+fn disk_hit(
+    o: Vec3,
+    d: Vec3,
+    z: f64,
+    radius: f64,
+    up: bool,
+    t_min: f64,
+    t_max: f64,
+) -> Option<LocalHit> {
+    if d.z().abs() < 1e-12 {
+        return None;
+    }
+    let t = (z - o.z()) / d.z();
+    if t < t_min || t > t_max {
+        return None;
+    }
+    let p = o + d * t;
+    let rho = (p.x() * p.x() + p.y() * p.y()).sqrt();
+    if rho > radius {
+        return None;
+    }
+    let radial = if rho > 1e-12 {
+        Vec3::new(p.x(), p.y(), 0.) / rho
+    } else {
+        Vec3::new(1., 0., 0.)
+    };
+    Some(LocalHit {
+        t,
+        normal: Vec3::new(0., 0., if up { 1. } else { -1. }),
+        u: azimuth(p.x(), p.y()) / (2. * PI),
+        v: rho / radius,
+        dpdu: Vec3::new(-p.y(), p.x(), 0.) * (2. * PI),
+        dpdv: radial * radius,
+    })
+}
+/// The real roots of `a t² + 2 half_b t + c`, in increasing order, and how many there are.
+fn quadratic_roots(a: f64, half_b: f64, c: f64) -> ([f64; 2], usize) {
+    if a.abs() < 1e-12 {
+        if half_b.abs() < 1e-12 {
+            return ([0.; 2], 0);
+        }
+        return ([-c / (2. * half_b), 0.], 1);
+    }
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0. {
+        return ([0.; 2], 0);
+    }
+    let sqrt_d = discriminant.sqrt();
+    let (t0, t1) = ((-half_b - sqrt_d) / a, (-half_b + sqrt_d) / a);
+    ([t0.min(t1), t0.max(t1)], 2)
+}
+/// The largest real root of `t³ + b t² + c t + d`.
+fn largest_cubic_root(b: f64, c: f64, d: f64) -> f64 {
+    let q = (b * b - 3. * c) / 9.;
+    let r = (2. * b * b * b - 9. * b * c + 27. * d) / 54.;
+    let mut t = if r * r < q * q * q {
+        let theta = (r / (q * q * q).sqrt()).clamp(-1., 1.).acos();
+        -2. * q.sqrt() * (theta / 3.).cos() - b / 3.
+    } else {
+        let a = -r.signum() * (r.abs() + (r * r - q * q * q).sqrt()).cbrt();
+        let a_inv = if a == 0. { 0. } else { q / a };
+        a + a_inv - b / 3.
+    };
+    // Cardano loses digits when the roots are close, which Newton's method gets back.
+    for _ in 0..2 {
+        let slope = (3. * t + 2. * b) * t + c;
+        if slope.abs() > 1e-12 {
+            t -= (((t + b) * t + c) * t + d) / slope;
+        }
+    }
+    t
+}
+/// The real roots of `t⁴ + b t³ + c t² + d t + e`, in increasing order, and how many there are,
+/// by Ferrari's method refined with Newton's.
+fn quartic_roots(b: f64, c: f64, d: f64, e: f64) -> ([f64; 4], usize) {
+    // Depressed to y⁴ + p y² + q y + r with t = y - b/4.
+    let shift = -b / 4.;
+    let p = c - 3. * b * b / 8.;
+    let q = d - b * c / 2. + b * b * b / 8.;
+    let r = e - b * d / 4. + b * b * c / 16. - 3. * b * b * b * b / 256.;
+    // Adding 2m y² + m² + mp to both sides makes each a square when m solves the resolvent.
+    let m = largest_cubic_root(p, p * p / 4. - r, -q * q / 8.).max(0.);
+    let mut roots = [0.; 4];
+    let mut count = 0;
+    let mut push = |(found, n): ([f64; 2], usize)| {
+        for y in &found[..n] {
+            roots[count] = y + shift;
+            count += 1;
+        }
+    };
+    if m < 1e-12 {
+        // Biquadratic, y⁴ + p y² + r.
+        let (squares, n) = quadratic_roots(1., p / 2., r);
+        for &y2 in &squares[..n] {
+            if y2 >= 0. {
+                push(([-y2.sqrt(), y2.sqrt()], 2));
+            }
+        }
+    } else {
+        let root_2m = (2. * m).sqrt();
+        let offset = q / (2. * root_2m);
+        push(quadratic_roots(1., -root_2m / 2., p / 2. + m + offset));
+        push(quadratic_roots(1., root_2m / 2., p / 2. + m - offset));
+    }
+    for t in &mut roots[..count] {
+        for _ in 0..2 {
+            let value = (((*t + b) * *t + c) * *t + d) * *t + e;
+            let slope = ((4. * *t + 3. * b) * *t + 2. * c) * *t + d;
+            if slope.abs() > 1e-12 {
+                *t -= value / slope;
+            }
+        }
+    }
+    roots[..count].sort_by(|a, b| a.partial_cmp(b).unwrap());
+    (roots, count)
+}
+/// The angle of (x, y) around the origin from the x-axis, in [0, 2π).
+fn azimuth(x: f64, y: f64) -> f64 {
+    let phi = y.atan2(x);
+    if phi < 0. {
+        phi + 2. * PI
+    } else {
+        phi
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constant_medium::inside_intervals;
+    use crate::material::Lambertian;
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Vec3::ones()))
+    }
+    /// The closed shapes are entered and left once by a ray through their middle,
+    /// over the length of the chord, which is what media inside them rely on.
+    #[test]
+    fn test_inside() {
+        let ray = Ray::new(Vec3::new(-5., 0.5, 0.), Vec3::new(1., 0., 0.), 0.);
+        let chord = |object: &dyn Hittable| -> Vec<(f64, f64)> {
+            inside_intervals(object, &ray, 0., f64::INFINITY)
+        };
+        let cylinder = Cylinder::new(Vec3::zero(), Vec3::new(0., 2., 0.), 1., material());
+        assert_eq!(chord(&cylinder.with_caps()).len(), 1);
+        let (t0, t1) = chord(&Cylinder::new(
+            Vec3::zero(),
+            Vec3::new(0., 2., 0.),
+            1.,
+            material(),
+        ))[0];
+        assert!((t0 - 4.).abs() < 1e-9 && (t1 - 6.).abs() < 1e-9);
+        // Half way up the cone, its radius is half of that at the base.
+        let cone = Cone::new(Vec3::zero(), Vec3::new(0., 1., 0.), 1., material()).with_cap();
+        let (t0, t1) = chord(&cone)[0];
+        assert!((t0 - 4.5).abs() < 1e-9 && (t1 - 5.5).abs() < 1e-9);
+        // Through the tube on both sides of the hole.
+        let torus = Torus::new(
+            Vec3::new(0., 0.5, 0.),
+            Vec3::new(0., 1., 0.),
+            2.,
+            0.5,
+            material(),
+        );
+        let intervals = chord(&torus);
+        assert_eq!(intervals.len(), 2);
+        assert!((intervals[0].0 - 2.5).abs() < 1e-9 && (intervals[0].1 - 3.5).abs() < 1e-9);
+        assert!((intervals[1].0 - 6.5).abs() < 1e-9 && (intervals[1].1 - 7.5).abs() < 1e-9);
+    }
+    /// Normals are unit length and perpendicular to the tangents, which follow U and V.
+    #[test]
+    fn test_surfaces() {
+        let shapes: Vec<Box<dyn Hittable>> = vec![
+            Box::new(Disk::new(
+                Vec3::zero(),
+                Vec3::new(0.3, 1., 0.2),
+                2.,
+                material(),
+            )),
+            Box::new(
+                Cylinder::new(
+                    Vec3::new(0., -1., 0.),
+                    Vec3::new(0.5, 1., 0.),
+                    1.,
+                    material(),
+                )
+                .with_caps(),
+            ),
+            Box::new(Cone::new(
+                Vec3::new(0., -1., 0.),
+                Vec3::new(0., 1., 0.3),
+                1.5,
+                material(),
+            )),
+            Box::new(Torus::new(
+                Vec3::zero(),
+                Vec3::new(0.2, 1., 0.),
+                1.2,
+                0.4,
+                material(),
+            )),
+        ];
+        let ray = Ray::new(Vec3::new(0.7, 5., 0.4), Vec3::new(0.05, -1., -0.1), 0.);
+        for shape in shapes {
+            let mut rec = HitRecord::default();
+            assert!(shape.hit(&ray, 0.001, f64::INFINITY, &mut rec));
+            assert!(rec.front_face);
+            assert!((rec.normal.length() - 1.).abs() < 1e-9);
+            assert!((rec.normal * rec.dpdu).abs() < 1e-6 * rec.dpdu.length());
+            assert!((rec.normal * rec.dpdv).abs() < 1e-6 * rec.dpdv.length());
+            assert!((0. ..=1.).contains(&rec.u) && (0. ..=1.).contains(&rec.v));
+            let mut bbox = AABB::default();
+            assert!(shape.bounding_box(0., 1., &mut bbox));
+            assert!(bbox.hit(&ray, 0.001, f64::INFINITY));
+            let (mut min, mut max, mut p) = (bbox.min(), bbox.max(), rec.p);
+            for a in 0..3 {
+                assert!(*min.get(a) <= *p.get(a) && *p.get(a) <= *max.get(a));
+            }
+        }
+    }
+    /// The quartic solver finds separated, double and missing roots.
+    #[test]
+    fn test_quartic_roots() {
+        let expand = |r: [f64; 4]| {
+            let b = -(r[0] + r[1] + r[2] + r[3]);
+            let c =
+                r[0] * r[1] + r[0] * r[2] + r[0] * r[3] + r[1] * r[2] + r[1] * r[3] + r[2] * r[3];
+            let d = -(r[0] * r[1] * r[2]
+                + r[0] * r[1] * r[3]
+                + r[0] * r[2] * r[3]
+                + r[1] * r[2] * r[3]);
+            (b, c, d, r[0] * r[1] * r[2] * r[3])
+        };
+        for expected in [[-3., -1., 0.5, 4.], [1., 2., 2.5, 7.], [-2., -1., 1., 2.]] {
+            let (b, c, d, e) = expand(expected);
+            let (roots, count) = quartic_roots(b, c, d, e);
+            assert_eq!(count, 4);
+            for (root, expected) in roots.iter().zip(expected) {
+                assert!((root - expected).abs() < 1e-9);
+            }
+        }
+        // (t² + 1)(t² - 4) only crosses at ±2.
+        let (roots, count) = quartic_roots(0., -3., 0., -4.);
+        assert_eq!(count, 2);
+        assert!((roots[0] + 2.).abs() < 1e-9 && (roots[1] - 2.).abs() < 1e-9);
+        assert_eq!(quartic_roots(0., 2., 0., 1.).1, 0);
+    }
+    /// Rays from all around land on the surface of the torus, including nearly grazing ones.
+    #[test]
+    fn test_torus_hits() {
+        let (big, small) = (2., 0.5);
+        let torus = Torus::new(Vec3::zero(), Vec3::new(0., 0., 1.), big, small, material());
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = Vec3::random_in_unit_sphere() * 4. + Vec3::new(0., 0., 5.);
+            let target = Vec3::new(
+                random_double_in_range(-2.6, 2.6),
+                random_double_in_range(-2.6, 2.6),
+                random_double_in_range(-0.6, 0.6),
+            );
+            let ray = Ray::new(origin, (target - origin) * 3., 0.);
+            let mut rec = HitRecord::default();
+            if !torus.hit(&ray, 0.001, f64::INFINITY, &mut rec) {
+                continue;
+            }
+            hits += 1;
+            let p = rec.p;
+            let rho = (p.x() * p.x() + p.y() * p.y()).sqrt();
+            let distance = ((rho - big) * (rho - big) + p.z() * p.z()).sqrt();
+            assert!((distance - small).abs() < 1e-9);
+            assert!(rec.front_face);
+        }
+        assert!(hits > 200);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::rt_weekend::*;
+use crate::vec3::Vec3;
+use std::sync::OnceLock;
+/// The range of wavelengths in nanometers that a spectral path samples.
+pub const LAMBDA_MIN: f64 = 380.;
+pub const LAMBDA_MAX: f64 = 780.;
+/// The wavelengths carried by a path in spectral mode, one for each channel of a `Vec3`.
+///
+/// This is hero wavelength sampling (Wilkie et al. 2014):
+/// the hero wavelength is picked uniformly, and the other two are spread evenly from it
+/// across the visible range, so that every path still resolves color at once.
+/// When a path meets something that bends each wavelength differently, like dispersive glass,
+/// it can only follow the hero, and the others are terminated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wavelengths {
+    lambda: Vec3,
+    secondary_terminated: bool,
+}
+impl Wavelengths {
+    pub fn sample() -> Self {
+        let u = random_double();
+        let at = |offset: f64| LAMBDA_MIN + (u + offset).fract() * (LAMBDA_MAX - LAMBDA_MIN);
+        Self {
+            lambda: Vec3::new(at(0.), at(1. / 3.), at(2. / 3.)),
+            secondary_terminated: false,
+        }
+    }
+    pub fn hero(&self) -> f64 {
+        self.lambda.x()
+    }
+    pub fn lambda(&self) -> Vec3 {
+        self.lambda
+    }
+    pub fn secondary_terminated(&self) -> bool {
+        self.secondary_terminated
+    }
+    /// The same wavelengths with only the hero left to follow.
+    pub fn terminate_secondary(&self) -> Self {
+        Self {
+            lambda: self.lambda,
+            secondary_terminated: true,
+        }
+    }
+    /// The factor on the throughput of a path whose secondary wavelengths were just terminated.
+    /// The hero then stands in for all three of them.
+    pub fn termination_weight() -> Vec3 {
+        Vec3::new(3., 0., 0.)
+    }
+    /// Upsample a linear RGB color into its spectrum at these wavelengths.
+    pub fn upsample(&self, rgb: Vec3) -> Vec3 {
+        Vec3::new(
+            rgb_to_spectrum(&rgb, self.lambda.x()),
+            rgb_to_spectrum(&rgb, self.lambda.y()),
+            rgb_to_spectrum(&rgb, self.lambda.z()),
+        )
+    }
+    /// Turn the radiance sampled at these wavelengths into the linear RGB it contributes,
+    /// through the CIE XYZ color matching functions.
+    pub fn radiance_to_rgb(&self, radiance: Vec3) -> Vec3 {
+        // Every wavelength has the pdf 1 / (LAMBDA_MAX - LAMBDA_MIN).
+        let mut xyz = Vec3::zero();
+        for (lambda, value) in [
+            (self.lambda.x(), radiance.x()),
+            (self.lambda.y(), radiance.y()),
+            (self.lambda.z(), radiance.z()),
+        ] {
+            xyz += cie_xyz(lambda) * value;
+        }
+        xyz_to_balanced_rgb(xyz * ((LAMBDA_MAX - LAMBDA_MIN) / 3.))
+    }
+}
+/// The CIE 1931 color matching functions at `lambda` nanometers,
+/// with the multi-lobe Gaussian fit of Wyman, Sloan and Shirley,
+/// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions" (2013).
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    let g = |mu: f64, sigma_below: f64, sigma_above: f64| {
+        let sigma = if lambda < mu {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+/// Convert CIE XYZ to linear sRGB, white balanced so that a constant spectrum of 1 becomes (1, 1, 1).
+///
+/// Without the balance, the equal energy white of a constant spectrum would look pink,
+/// since sRGB takes D65 as its white.
+fn xyz_to_balanced_rgb(xyz: Vec3) -> Vec3 {
+    static WHITE: OnceLock<Vec3> = OnceLock::new();
+    let white = WHITE.get_or_init(|| {
+        const STEPS: usize = 4000;
+        let mut integral = Vec3::zero();
+        for i in 0..STEPS {
+            let lambda = LAMBDA_MIN + (i as f64 + 0.5) / STEPS as f64 * (LAMBDA_MAX - LAMBDA_MIN);
+            integral += cie_xyz(lambda);
+        }
+        xyz_to_rgb(integral * ((LAMBDA_MAX - LAMBDA_MIN) / STEPS as f64))
+    });
+    let rgb = xyz_to_rgb(xyz);
+    Vec3::new(
+        rgb.x() / white.x(),
+        rgb.y() / white.y(),
+        rgb.z() / white.z(),
+    )
+}
+fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    let (x, y, z) = (xyz.x(), xyz.y(), xyz.z());
+    Vec3::new(
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    )
+}
+/// The basis spectra of Smits, "An RGB-to-Spectrum Conversion for Reflectances" (1999),
+/// sampled in 10 bins evenly spread over 380-720 nm.
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+/// The value at `lambda` of a smooth spectrum for `rgb`, following Smits:
+/// the smallest channel is made of white, the middle one of a secondary color,
+/// and what is left of the largest one of a primary color.
+///
+/// The result is linear in `rgb`, so it works for emitters brighter than 1 as well.
+pub fn rgb_to_spectrum(rgb: &Vec3, lambda: f64) -> f64 {
+    let bin = (((lambda - 380.) / 34.).floor().max(0.) as usize).min(9);
+    let (r, g, b) = (rgb.x(), rgb.y(), rgb.z());
+    let white = SMITS_WHITE[bin];
+    if r <= g && r <= b {
+        r * white
+            + if g <= b {
+                (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+            } else {
+                (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+            }
+    } else if g <= r && g <= b {
+        g * white
+            + if r <= b {
+                (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+            } else {
+                (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+            }
+    } else {
+        b * white
+            + if r <= g {
+                (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+            } else {
+                (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+            }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    /// Average many spectral samples of an upsampled color back into RGB.
+    fn round_trip(rgb: Vec3) -> Vec3 {
+        const N: usize = 200000;
+        let mut sum = Vec3::zero();
+        for _ in 0..N {
+            let wavelengths = Wavelengths::sample();
+            sum += wavelengths.radiance_to_rgb(wavelengths.upsample(rgb));
+        }
+        sum / N as f64
+    }
+    #[test]
+    fn test_white_round_trip() {
+        let white = round_trip(Vec3::ones());
+        for channel in [white.x(), white.y(), white.z()] {
+            assert!((channel - 1.).abs() < 0.02);
+        }
+    }
+    #[test]
+    fn test_primaries_keep_their_hue() {
+        let red = round_trip(Vec3::new(1., 0., 0.));
+        assert!(red.x() > 2. * red.y() && red.x() > 2. * red.z());
+        let blue = round_trip(Vec3::new(0., 0., 1.));
+        assert!(blue.z() > 2. * blue.x() && blue.z() > 2. * blue.y());
+    }
+    #[test]
+    fn test_hero_wavelengths_are_spread() {
+        for _ in 0..100 {
+            let lambda = Wavelengths::sample().lambda();
+            for value in [lambda.x(), lambda.y(), lambda.z()] {
+                assert!((LAMBDA_MIN..LAMBDA_MAX).contains(&value));
+            }
+            let gap = (lambda.y() - lambda.x()).rem_euclid(LAMBDA_MAX - LAMBDA_MIN);
+            assert!((gap - (LAMBDA_MAX - LAMBDA_MIN) / 3.).abs() < 1e-9);
+        }
+    }
+}
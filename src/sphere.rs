@@ -1,6 +1,7 @@
 use crate::aabb::*;
 use crate::hit::*;
 use crate::material::Material;
+use crate::onb::ONB;
 use crate::ray::Ray;
 use crate::rt_weekend::*;
 use crate::vec3::Vec3;
@@ -9,13 +10,27 @@ pub struct Sphere {
     center: Vec3,
     radius: f64,
     material: Arc<dyn Material>,
+    /// the emitted power, cached for the light distribution
+    power: f64,
 }
 impl Sphere {
     pub fn new(center: Vec3, radius: f64, material: Arc<dyn Material>) -> Self {
+        let area = 4. * PI * radius * radius;
+        // Even steps of y cover even areas of the sphere.
+        let power = surface_power(&material, area, |s, t| {
+            let y = 2. * t - 1.;
+            let phi = 2. * PI * s;
+            let ring = (1. - y * y).sqrt();
+            let normal = Vec3::new(ring * phi.cos(), y, ring * phi.sin());
+            let (mut u, mut v) = (0., 0.);
+            get_sphere_uv(normal, &mut u, &mut v);
+            (u, v, center + normal * radius, normal)
+        });
         Self {
             center,
             radius,
             material,
+            power,
         }
     }
 }
@@ -43,7 +58,9 @@ impl Hittable for Sphere {
         let outward_normal = (rec.p - self.center) / self.radius;
         rec.set_face_normal(ray, outward_normal);
         get_sphere_uv(outward_normal, &mut rec.u, &mut rec.v);
+        (rec.dpdu, rec.dpdv) = sphere_tangents(outward_normal, self.radius);
         rec.material = self.material.clone();
+        rec.sampled_light = self.power > 0.;
         true
     }
 
@@ -54,6 +71,48 @@ impl Hittable for Sphere {
         );
         true
     }
+    /// Seen from outside, the directions towards the sphere fill a cone,
+    /// and we sample that cone uniformly.
+    fn pdf_value(&self, origin: &Vec3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new(self.material.clone());
+        if !self.hit(&Ray::new(*origin, *v, 0.), 0.001, f64::INFINITY, &mut rec) {
+            return 0.;
+        }
+        let distance_squared = (self.center - *origin).squared_length();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            // From inside, every direction hits the sphere exactly once.
+            return 1. / (4. * PI);
+        }
+        let cos_theta_max = (1. - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2. * PI * (1. - cos_theta_max);
+        1. / solid_angle
+    }
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        let direction = self.center - *origin;
+        let distance_squared = direction.squared_length();
+        if distance_squared <= self.radius * self.radius {
+            return Vec3::random_unit_vector();
+        }
+        let uvw = ONB::build_from_w(&direction);
+        uvw.local_vec(&random_to_sphere(self.radius, distance_squared))
+    }
+    fn emitted_power(&self) -> f64 {
+        self.power
+    }
+}
+/// A uniformly random direction inside the cone that a sphere of `radius`
+/// subtends from `distance_squared` away, around the z-axis.
+pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
+    let r1 = random_double();
+    let r2 = random_double();
+    let z = 1. + r2 * ((1. - radius * radius / distance_squared).sqrt() - 1.);
+
+    let phi = 2. * PI * r1;
+    let x = phi.cos() * (1. - z * z).sqrt();
+    let y = phi.sin() * (1. - z * z).sqrt();
+
+    Vec3::new(x, y, z)
 }
 /// # Arguments
 ///  
@@ -74,6 +133,23 @@ pub fn get_sphere_uv(p: Vec3, u: &mut f64, v: &mut f64) {
     *u = phi / (2. * PI);
     *v = theta / PI;
 }
+/// The derivatives of the point on a sphere of `radius` by the U,V of `get_sphere_uv`,
+/// at the point with unit normal `n`.
+fn sphere_tangents(n: Vec3, radius: f64) -> (Vec3, Vec3) {
+    let sin_theta = (n.x() * n.x() + n.z() * n.z()).sqrt();
+    if sin_theta < 1e-8 {
+        // At the poles, where U is undefined, take the tangents of u = 0 right next to them.
+        let dpdu = Vec3::new(0., 0., 2. * PI * radius * 1e-8);
+        return (dpdu, Vec3::new(n.y(), 0., 0.) * (PI * radius));
+    }
+    let dpdu = Vec3::new(n.z(), 0., -n.x()) * (2. * PI * radius);
+    let dpdv = Vec3::new(
+        -n.x() * n.y() / sin_theta,
+        sin_theta,
+        -n.y() * n.z() / sin_theta,
+    ) * (PI * radius);
+    (dpdu, dpdv)
+}
 pub struct MovingSphere {
     center0: Vec3,
     center1: Vec3,
@@ -128,7 +204,10 @@ impl Hittable for MovingSphere {
         rec.p = ray.at(rec.t);
         let outward_normal = (rec.p - self.center(ray.time())) / self.radius;
         rec.set_face_normal(ray, outward_normal);
+        get_sphere_uv(outward_normal, &mut rec.u, &mut rec.v);
+        (rec.dpdu, rec.dpdv) = sphere_tangents(outward_normal, self.radius);
         rec.material = self.material.clone();
+        rec.sampled_light = false;
         true
     }
 
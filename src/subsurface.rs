@@ -0,0 +1,247 @@
+use crate::hit::*;
+use crate::microfacet::fresnel_dielectric;
+use crate::rt_weekend::*;
+use crate::{aabb::AABB, material::*, texture::*, Ray, Vec3};
+use std::sync::Arc;
+
+/// A translucent object like skin, wax or marble,
+/// where light goes through the surface and scatters many times inside before it comes out.
+///
+/// Like `ConstantMedium`, it wraps a closed `boundary` and replaces its material.
+/// The surface is smooth glass, and under it the light does a random walk
+/// through a homogeneous medium, which is followed to the end within a single scattering.
+pub struct Subsurface {
+    boundary: Arc<dyn Hittable>,
+    material: Arc<dyn Material>,
+}
+impl Subsurface {
+    /// # Arguments
+    /// * `albedo` - the color the object has when seen from afar, after all the scattering inside
+    /// * `mean_free_path` - the average distance the light travels inside between two collisions,
+    ///   for each channel. The longer it is, the more translucent the object looks.
+    /// * `ior` - the index of refraction of the surface
+    pub fn new(boundary: Arc<dyn Hittable>, albedo: Vec3, mean_free_path: Vec3, ior: f64) -> Self {
+        Self::new_texture(
+            boundary,
+            Arc::new(SolidColor::new(albedo)),
+            mean_free_path,
+            ior,
+        )
+    }
+    /// The albedo is looked up where the light enters the object.
+    pub fn new_texture(
+        boundary: Arc<dyn Hittable>,
+        albedo: Arc<dyn Texture>,
+        mean_free_path: Vec3,
+        ior: f64,
+    ) -> Self {
+        let material = Arc::new(RandomWalk {
+            boundary: boundary.clone(),
+            albedo,
+            sigma_t: Vec3::new(
+                1. / mean_free_path.x(),
+                1. / mean_free_path.y(),
+                1. / mean_free_path.z(),
+            ),
+            ior,
+        });
+        Self { boundary, material }
+    }
+}
+impl Hittable for Subsurface {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        if !self.boundary.hit(ray, t_min, t_max, rec) {
+            return false;
+        }
+        rec.material = self.material.clone();
+        true
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.boundary.bounding_box(time0, time1, output_box)
+    }
+}
+/// The surface of a `Subsurface` object, which walks the light through the inside.
+struct RandomWalk {
+    boundary: Arc<dyn Hittable>,
+    albedo: Arc<dyn Texture>,
+    /// the extinction coefficient for each channel
+    sigma_t: Vec3,
+    ior: f64,
+}
+impl RandomWalk {
+    /// The walk gives up after this many collisions.
+    const MAX_STEPS: usize = 1024;
+
+    /// The single scattering albedo that makes a thick slab look like `albedo` after all the scattering,
+    /// with the fit of Chiang et al., "Practical and Controllable Subsurface Scattering
+    /// for Production Path Tracing" (2016).
+    fn single_scattering_albedo(albedo: f64) -> f64 {
+        let a = albedo.clamp(0., 0.999);
+        let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+        1. - s * s
+    }
+    /// Follow the light from `p` in the direction `direction` inside the object until it gets out,
+    /// returning the ray that leaves and the throughput of the walk.
+    ///
+    /// All distances of a walk are sampled with the mean free path of one channel picked at random,
+    /// and the walk is weighted by the average of the pdfs every channel has for it
+    /// (one-sample multiple importance sampling), so that channels
+    /// with very different mean free paths all converge.
+    fn walk(&self, r_in: &Ray, p: Vec3, direction: Vec3, albedo: Vec3) -> Option<(Ray, Vec3)> {
+        let sigma_s = Vec3::elemul(
+            Vec3::new(
+                Self::single_scattering_albedo(albedo.x()),
+                Self::single_scattering_albedo(albedo.y()),
+                Self::single_scattering_albedo(albedo.z()),
+            ),
+            self.sigma_t,
+        );
+        let transmittance = |distance: f64| {
+            Vec3::new(
+                (-self.sigma_t.x() * distance).exp(),
+                (-self.sigma_t.y() * distance).exp(),
+                (-self.sigma_t.z() * distance).exp(),
+            )
+        };
+        let channel = ((random_double() * 3.) as usize).min(2);
+        let sigma = [self.sigma_t.x(), self.sigma_t.y(), self.sigma_t.z()][channel];
+        let component = |v: Vec3| [v.x(), v.y(), v.z()][channel];
+
+        // The pdfs of the walk so far for each channel, and its contribution,
+        // both rescaled at every step so that the largest pdf is 1.
+        let mut pdf = Vec3::ones();
+        let mut contribution = Vec3::ones();
+        let mut ray = Ray::new(p, direction, r_in.time());
+        for step in 0..Self::MAX_STEPS {
+            let distance = -(1. - random_double()).ln() / sigma;
+            let mut rec = HitRecord::default();
+            if !self.boundary.hit(&ray, 0.0001, f64::INFINITY, &mut rec) {
+                // The walk has leaked out through a crack of the boundary.
+                return None;
+            }
+            let leaves_medium = distance >= rec.t;
+            let (step_pdf, step_contribution) = if leaves_medium {
+                let tr = transmittance(rec.t);
+                (tr, tr)
+            } else {
+                let tr = transmittance(distance);
+                (Vec3::elemul(self.sigma_t, tr), Vec3::elemul(sigma_s, tr))
+            };
+            let scale = component(step_pdf);
+            pdf = Vec3::elemul(pdf, step_pdf) / scale;
+            contribution = Vec3::elemul(contribution, step_contribution) / scale;
+            let largest = pdf.x().max(pdf.y()).max(pdf.z());
+            pdf = pdf / largest;
+            contribution = contribution / largest;
+
+            if leaves_medium {
+                // Reach the surface, where the light either leaves or is reflected back inside.
+                let unit_direction = Vec3::unit(ray.direction());
+                let cos_theta = -(unit_direction * rec.normal);
+                let reflectance = fresnel_dielectric(cos_theta, 1. / self.ior);
+                if random_double() >= reflectance {
+                    let out = Vec3::refract(&unit_direction, &rec.normal, self.ior);
+                    let average = (pdf.x() + pdf.y() + pdf.z()) / 3.;
+                    return Some((Ray::new(rec.p, out, r_in.time()), contribution / average));
+                }
+                ray = Ray::new(
+                    rec.p,
+                    Vec3::reflect(&unit_direction, &rec.normal),
+                    r_in.time(),
+                );
+            } else {
+                // Scatter in the medium, isotropically.
+                ray = Ray::new(ray.at(distance), Vec3::random_unit_vector(), r_in.time());
+            }
+            // Russian roulette once the walk has gone on for a while.
+            if step > 16 {
+                let average = (pdf.x() + pdf.y() + pdf.z()) / 3.;
+                let throughput = contribution / average;
+                let survival = throughput
+                    .x()
+                    .max(throughput.y())
+                    .max(throughput.z())
+                    .min(1.);
+                if random_double() >= survival {
+                    return None;
+                }
+                contribution = contribution / survival;
+            }
+        }
+        None
+    }
+}
+impl Material for RandomWalk {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let unit_direction = Vec3::unit(r_in.direction());
+        if !rec.front_face {
+            // A ray from inside the object, which no walk started: just let it out.
+            *scattered = Ray::new(
+                rec.p,
+                Vec3::refract(&unit_direction, &rec.normal, self.ior),
+                r_in.time(),
+            );
+            *attenuation = Vec3::ones();
+            return true;
+        }
+        let cos_theta = -(unit_direction * rec.normal);
+        if random_double() < fresnel_dielectric(cos_theta, self.ior) {
+            *scattered = Ray::new(
+                rec.p,
+                Vec3::reflect(&unit_direction, &rec.normal),
+                r_in.time(),
+            );
+            *attenuation = Vec3::ones();
+            return true;
+        }
+        let inside = Vec3::refract(&unit_direction, &rec.normal, 1. / self.ior);
+        let albedo = self.albedo.sample(&rec.texture_point());
+        match self.walk(r_in, rec.p, inside, albedo) {
+            Some((ray, throughput)) => {
+                *scattered = ray;
+                *attenuation = throughput;
+                true
+            }
+            None => false,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    /// A white object neither gains nor loses much light, whatever its mean free path.
+    #[test]
+    fn test_white_walk_conserves_energy() {
+        let unused: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::zero()));
+        let sphere = Arc::new(Sphere::new(Vec3::zero(), 1., unused));
+        let object = Subsurface::new(sphere, Vec3::ones(), Vec3::new(0.5, 0.1, 0.02), 1.4);
+        let ray = Ray::new(Vec3::new(0.3, 0.2, 5.), Vec3::new(0., 0., -1.), 0.);
+        let mut rec = HitRecord::default();
+        assert!(object.hit(&ray, 0.001, f64::INFINITY, &mut rec));
+        const N: usize = 5000;
+        let mut sum = Vec3::zero();
+        for _ in 0..N {
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::zero();
+            if rec
+                .material
+                .scatter(&ray, &rec, &mut attenuation, &mut scattered)
+            {
+                // Whatever comes out leaves the sphere.
+                assert!(scattered.origin().length() > 0.999);
+                sum += attenuation;
+            }
+        }
+        let albedo = sum / N as f64;
+        for channel in [albedo.x(), albedo.y(), albedo.z()] {
+            assert!(channel > 0.8 && channel < 1.15, "albedo {:?}", albedo);
+        }
+    }
+}
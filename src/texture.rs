@@ -1,10 +1,50 @@
-use crate::color::clamp;
 use crate::perlin::*;
+use crate::rt_weekend::PI;
 use crate::vec3::Vec3;
 use image::*;
 use std::sync::Arc;
 pub trait Texture: Sync + Send {
     fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3;
+    /// The value averaged over the `footprint`, the width in texture coordinates
+    /// of the area a ray covers around (u, v), where 0 means a single point.
+    /// Textures that cannot filter look up the point.
+    fn value_filtered(&self, u: f64, v: f64, p: &Vec3, _footprint: f64) -> Vec3 {
+        self.value(u, v, p)
+    }
+    /// The opacity at (u, v), from 0 for transparent to 1 for opaque.
+    fn alpha(&self, _u: f64, _v: f64, _p: &Vec3) -> f64 {
+        1.
+    }
+    /// The value at a point of a surface, knowing everything about it.
+    /// Textures that depend on the normal, like triplanar projections, override this.
+    fn sample(&self, point: &TexturePoint) -> Vec3 {
+        self.value_filtered(point.u, point.v, &point.p, point.footprint)
+    }
+}
+/// Where a texture is looked up: the U,V and position of a point,
+/// the outward normal of the surface there and the footprint to filter over.
+#[derive(Clone, Copy, Debug)]
+pub struct TexturePoint {
+    pub u: f64,
+    pub v: f64,
+    pub p: Vec3,
+    /// zero where the texture is looked up off a surface
+    pub normal: Vec3,
+    pub footprint: f64,
+    /// the time of the ray, for textures that change over time
+    pub time: f64,
+}
+impl TexturePoint {
+    pub fn new(u: f64, v: f64, p: Vec3) -> Self {
+        Self {
+            u,
+            v,
+            p,
+            normal: Vec3::zero(),
+            footprint: 0.,
+            time: 0.,
+        }
+    }
 }
 pub struct SolidColor {
     color_value: Vec3,
@@ -23,102 +63,600 @@ impl Texture for SolidColor {
         self.color_value
     }
 }
-/// We can create a checker texture by noting that
-/// the sign of sine and cosine just alternates in a regular way,
-/// and if we multiply trig functions in all three dimensions,
-/// the sign of that product forms a 3D checker pattern.
+/// Where a `CheckerTexture` lays out its cells.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckerMode {
+    /// Cubes in space, so objects look carved out of a checkered block.
+    Solid,
+    /// Squares in the U,V coordinates of the surface, which follow it around.
+    Uv,
+}
+/// Alternating cells of two textures, `even` and `odd`,
+/// which are `1 / scale` wide along each axis.
+///
+/// A cell is odd when the sum of the floors of its coordinates is.
+/// The default scale gives cells as wide as the half period of `sin(10x)`,
+/// like the product of sines this texture used to be.
 pub struct CheckerTexture {
     odd: Arc<dyn Texture>,
     even: Arc<dyn Texture>,
+    mode: CheckerMode,
+    scale: f64,
+    antialiased: bool,
 }
 impl CheckerTexture {
-    #[allow(dead_code)]
     pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
-        Self { odd, even }
-    }
-    pub fn new_rgb(even: Vec3, odd: Vec3) -> Self {
         Self {
-            odd: Arc::new(SolidColor::new(odd)),
-            even: Arc::new(SolidColor::new(even)),
+            odd,
+            even,
+            mode: CheckerMode::Solid,
+            scale: 10. / PI,
+            antialiased: false,
         }
     }
+    pub fn new_rgb(even: Vec3, odd: Vec3) -> Self {
+        Self::new(
+            Arc::new(SolidColor::new(even)),
+            Arc::new(SolidColor::new(odd)),
+        )
+    }
+    pub fn with_mode(mut self, mode: CheckerMode) -> Self {
+        self.mode = mode;
+        self
+    }
+    /// Set the number of cells per unit, of length in solid mode and of U,V in U,V mode.
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        self.scale = scale;
+        self
+    }
+    /// Average the cells over the footprint of the ray in U,V mode,
+    /// which keeps checkers in the distance from turning into moire.
+    pub fn with_antialiasing(mut self) -> Self {
+        self.antialiased = true;
+        self
+    }
+    /// How much of a box `width` cells wide around `x` is covered by odd cells along one axis.
+    fn odd_fraction(x: f64, width: f64) -> f64 {
+        // the number of odd cells from 0 to x
+        let odd_cells = |x: f64| (x / 2.).floor() + (x - 2. * (x / 2.).floor() - 1.).max(0.);
+        (odd_cells(x + 0.5 * width) - odd_cells(x - 0.5 * width)) / width
+    }
 }
 impl Texture for CheckerTexture {
     fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
-        let sines = (p.x() * 10.).sin() * (p.y() * 10.).sin() * (p.z() * 10.).sin();
-        if sines < 0. {
-            self.odd.value(u, v, p)
+        self.sample(&TexturePoint::new(u, v, *p))
+    }
+    fn value_filtered(&self, u: f64, v: f64, p: &Vec3, footprint: f64) -> Vec3 {
+        self.sample(&TexturePoint {
+            footprint,
+            ..TexturePoint::new(u, v, *p)
+        })
+    }
+    /// The cells are looked up at the whole point, so that they can be textures of any kind.
+    fn sample(&self, point: &TexturePoint) -> Vec3 {
+        let (u, v) = (point.u, point.v);
+        let width = point.footprint * self.scale;
+        if self.antialiased && self.mode == CheckerMode::Uv && width >= 1e-6 {
+            // Whether a cell is odd along U and along V are independent over the box,
+            // and it is odd overall when it is odd along exactly one of them.
+            let odd_u = Self::odd_fraction(u * self.scale, width);
+            let odd_v = Self::odd_fraction(v * self.scale, width);
+            let odd = odd_u * (1. - odd_v) + odd_v * (1. - odd_u);
+            return self.even.sample(point) * (1. - odd) + self.odd.sample(point) * odd;
+        }
+        let cells = match self.mode {
+            CheckerMode::Solid => {
+                let q = point.p * self.scale;
+                q.x().floor() + q.y().floor() + q.z().floor()
+            }
+            CheckerMode::Uv => (u * self.scale).floor() + (v * self.scale).floor(),
+        };
+        if cells.rem_euclid(2.) == 1. {
+            self.odd.sample(point)
         } else {
-            self.even.value(u, v, p)
+            self.even.sample(point)
         }
     }
 }
 pub struct NoiseTexture {
     noise: Perlin,
     scale: f64,
+    /// how fast the noise changes with the time of the rays, 0 for not at all
+    speed: f64,
 }
 impl NoiseTexture {
     pub fn new(scale: f64) -> Self {
         Self {
             noise: Perlin::new(),
             scale,
+            speed: 0.,
         }
     }
+    pub fn with_noise(mut self, noise: Perlin) -> Self {
+        self.noise = noise;
+        self
+    }
+    /// Let the marble change over time, `speed` lattice cells per unit of time.
+    pub fn with_evolution(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
 }
 impl Texture for NoiseTexture {
-    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
-        // Noise texture with marbled texture
-        let n = 0.5 * (1. + (self.scale * p.z() + 10. * self.noise.turb(p, 7)).sin());
-        // let n = self.noise.turb(&(*p * self.scale), 7);
-        // let n = 0.5 * (1.0 + self.noise.noise(&(*p * self.scale)));
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.sample(&TexturePoint::new(u, v, *p))
+    }
+    fn sample(&self, point: &TexturePoint) -> Vec3 {
+        // Noise texture with marbled texture; see `procedural` for configurable ones.
+        let p = &point.p;
+        let turb = if self.speed == 0. {
+            self.noise.turb(p, 7)
+        } else {
+            self.noise.turb4(p, point.time * self.speed, 7)
+        };
+        let n = 0.5 * (1. + (self.scale * p.z() + 10. * turb).sin());
         Vec3::new(n, n, n)
     }
 }
-pub struct ImageTexture {
-    data: ImageBuffer<Rgb<u8>, Vec<u8>>,
+/// Perlin turbulence, for densities of smoke and clouds.
+pub struct TurbulenceTexture {
+    noise: Perlin,
+    scale: f64,
+    /// how fast the noise changes with the time of the rays, 0 for not at all
+    speed: f64,
+}
+impl TurbulenceTexture {
+    pub fn new(scale: f64) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            speed: 0.,
+        }
+    }
+    pub fn with_noise(mut self, noise: Perlin) -> Self {
+        self.noise = noise;
+        self
+    }
+    /// Let the turbulence change over time, `speed` lattice cells per unit of time,
+    /// so that smoke billows under motion blur and across frames.
+    pub fn with_evolution(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+}
+impl Texture for TurbulenceTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.sample(&TexturePoint::new(u, v, *p))
+    }
+    fn sample(&self, point: &TexturePoint) -> Vec3 {
+        let p = point.p * self.scale;
+        let n = if self.speed == 0. {
+            self.noise.turb(&p, 7)
+        } else {
+            self.noise.turb4(&p, point.time * self.speed, 7)
+        };
+        Vec3::new(n, n, n)
+    }
+}
+/// How texture coordinates outside of [0,1] are brought back into the image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wrap {
+    /// The image tiles the plane.
+    Repeat,
+    /// The image tiles the plane, flipped every other time so that the tiles meet seamlessly.
+    Mirror,
+    /// The pixels at the edges stretch on forever.
+    Clamp,
+}
+/// How an `ImageTexture` is looked up between the centers of its pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    /// The pixel the point falls in, which looks blocky up close.
+    Nearest,
+    /// Linear interpolation between the four nearest pixels.
+    Bilinear,
+    /// Catmull-Rom interpolation between the sixteen nearest pixels, which stays sharper up close.
+    Bicubic,
+    /// Bilinear lookups in the two levels of the mipmap whose pixels are about as large
+    /// as the footprint of the ray, blended linearly, so that far away textures do not alias.
+    Trilinear,
+}
+/// One level of a mipmap, in linear RGB.
+struct MipLevel {
     width: usize,
     height: usize,
+    texels: Vec<Vec3>,
 }
-impl Default for ImageTexture {
-    fn default() -> Self {
+impl MipLevel {
+    /// The level with half the resolution, each pixel the average of those it covers.
+    /// Odd sizes round up, so the last row and column are kept, each in pixels of their own.
+    fn downsample(&self) -> Self {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut texels = Vec::with_capacity(width * height);
+        for j in 0..height {
+            for i in 0..width {
+                let mut sum = Vec3::zero();
+                for (di, dj) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let x = (2 * i + di).min(self.width - 1);
+                    let y = (2 * j + dj).min(self.height - 1);
+                    sum += self.texels[x + y * self.width];
+                }
+                texels.push(sum / 4.);
+            }
+        }
         Self {
-            data: ImageBuffer::new(0, 0),
-            width: 0,
-            height: 0,
+            width,
+            height,
+            texels,
         }
     }
 }
+/// An image mapped onto the surface by its texture coordinates,
+/// with (0,0) at the lower left corner of the image and (1,1) at the upper right.
+///
+/// Color images are decoded to linear RGB, and other data like roughness is kept as it is stored.
+/// The alpha channel of images that have one is kept too, as `alpha`.
+pub struct ImageTexture {
+    /// the image itself, then every level of its mipmap down to a single pixel
+    levels: Vec<MipLevel>,
+    /// the same for the alpha channel, in every channel, if there is one
+    alpha_levels: Option<Vec<MipLevel>>,
+    filter: Filter,
+    wrap_u: Wrap,
+    wrap_v: Wrap,
+    /// the texture coordinates are multiplied by `scale`, then `offset` is added
+    scale: (f64, f64),
+    offset: (f64, f64),
+}
+impl Default for ImageTexture {
+    fn default() -> Self {
+        Self::from_texels(0, 0, Vec::new())
+    }
+}
 impl ImageTexture {
+    /// A color image, which is decoded from sRGB unless it is stored in floating point,
+    /// like HDR and EXR images, which are linear already.
     pub fn new(filename: &str) -> Self {
-        let data = open(filename).unwrap().into_rgb8();
-        let width = data.width() as usize;
-        let height = data.height() as usize;
+        Self::load(filename, true)
+    }
+    /// An image of data that is not color, like roughness, which is used as it is stored.
+    pub fn new_non_color(filename: &str) -> Self {
+        Self::load(filename, false)
+    }
+    fn load(filename: &str, color: bool) -> Self {
+        let image =
+            open(filename).unwrap_or_else(|error| panic!("Cannot read {}: {}", filename, error));
+        let float = matches!(image.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+        let has_alpha = image.color().has_alpha();
+        let image = image.into_rgba32f();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let decode = |value: f32| {
+            if color && !float {
+                srgb_to_linear(value as f64)
+            } else {
+                value as f64
+            }
+        };
+        let texels = image
+            .pixels()
+            .map(|pixel| Vec3::new(decode(pixel[0]), decode(pixel[1]), decode(pixel[2])))
+            .collect();
+        let mut texture = Self::from_texels(width, height, texels);
+        if has_alpha {
+            let alpha = image
+                .pixels()
+                .map(|pixel| Vec3::ones() * pixel[3] as f64)
+                .collect();
+            texture.alpha_levels = Some(mipmap(width, height, alpha));
+        }
+        texture
+    }
+    /// A texture of `width` by `height` pixels, given row by row from the top.
+    fn from_texels(width: usize, height: usize, texels: Vec<Vec3>) -> Self {
         Self {
-            data,
+            levels: mipmap(width, height, texels),
+            alpha_levels: None,
+            filter: Filter::Trilinear,
+            wrap_u: Wrap::Clamp,
+            wrap_v: Wrap::Clamp,
+            scale: (1., 1.),
+            offset: (0., 0.),
+        }
+    }
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+    pub fn with_wrap(mut self, wrap_u: Wrap, wrap_v: Wrap) -> Self {
+        self.wrap_u = wrap_u;
+        self.wrap_v = wrap_v;
+        self
+    }
+    /// Repeat the image `scale` times along u and v, moved by `offset`,
+    /// which is usually done with `Wrap::Repeat`.
+    pub fn with_uv_transform(mut self, scale: (f64, f64), offset: (f64, f64)) -> Self {
+        self.scale = scale;
+        self.offset = offset;
+        self
+    }
+    /// Look up the mipmap `levels` with the filter of the texture.
+    fn filtered(&self, levels: &[MipLevel], u: f64, v: f64, footprint: f64) -> Vec3 {
+        if levels.is_empty() {
+            return Vec3::zero();
+        }
+        let u = u * self.scale.0 + self.offset.0;
+        let v = v * self.scale.1 + self.offset.1;
+        if self.filter != Filter::Trilinear {
+            return self.lookup(&levels[0], self.filter, u, v);
+        }
+        // The level whose pixels are as wide as the footprint.
+        let footprint = footprint * self.scale.0.abs().max(self.scale.1.abs());
+        let size = levels[0].width.max(levels[0].height) as f64;
+        let level = (footprint * size)
+            .log2()
+            .clamp(0., (levels.len() - 1) as f64);
+        let below = level.floor() as usize;
+        let fine = self.lookup(&levels[below], Filter::Bilinear, u, v);
+        if below + 1 == levels.len() {
+            return fine;
+        }
+        let coarse = self.lookup(&levels[below + 1], Filter::Bilinear, u, v);
+        fine * (1. - level.fract()) + coarse * level.fract()
+    }
+    /// The pixel at column `i` and row `j` of a level, wrapped into the image.
+    fn texel(&self, level: &MipLevel, i: i64, j: i64) -> Vec3 {
+        let i = wrap(self.wrap_u, i, level.width);
+        let j = wrap(self.wrap_v, j, level.height);
+        level.texels[i + j * level.width]
+    }
+    /// Look up the level at the texture coordinates (u, v) with `filter`.
+    fn lookup(&self, level: &MipLevel, filter: Filter, u: f64, v: f64) -> Vec3 {
+        let x = u * level.width as f64;
+        let y = (1. - v) * level.height as f64; // Flip V to image coordinates
+        if filter == Filter::Nearest {
+            return self.texel(level, x.floor() as i64, y.floor() as i64);
+        }
+        // Measure from the center of the pixel up and to the left.
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (i, j) = (x.floor() as i64, y.floor() as i64);
+        let (fx, fy) = (x - x.floor(), y - y.floor());
+        if filter == Filter::Bicubic {
+            let (wx, wy) = (catmull_rom(fx), catmull_rom(fy));
+            let mut sum = Vec3::zero();
+            for (dj, wy) in wy.iter().enumerate() {
+                for (di, wx) in wx.iter().enumerate() {
+                    sum += self.texel(level, i + di as i64 - 1, j + dj as i64 - 1) * (wx * wy);
+                }
+            }
+            // The negative lobes can overshoot below 0 next to sharp edges.
+            return Vec3::new(sum.x().max(0.), sum.y().max(0.), sum.z().max(0.));
+        }
+        self.texel(level, i, j) * ((1. - fx) * (1. - fy))
+            + self.texel(level, i + 1, j) * (fx * (1. - fy))
+            + self.texel(level, i, j + 1) * ((1. - fx) * fy)
+            + self.texel(level, i + 1, j + 1) * (fx * fy)
+    }
+}
+/// The image of `width` by `height` pixels, then every level of its mipmap down to a single pixel.
+fn mipmap(width: usize, height: usize, texels: Vec<Vec3>) -> Vec<MipLevel> {
+    let mut levels = Vec::new();
+    if width > 0 && height > 0 {
+        levels.push(MipLevel {
             width,
             height,
+            texels,
+        });
+        while let Some(last) = levels
+            .last()
+            .filter(|last| last.width > 1 || last.height > 1)
+        {
+            levels.push(last.downsample());
         }
     }
+    levels
+}
+/// Decode a channel of an sRGB color to linear.
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+/// The index of a pixel in a row or column of `n` pixels.
+fn wrap(mode: Wrap, i: i64, n: usize) -> usize {
+    let n = n as i64;
+    let i = match mode {
+        Wrap::Repeat => i.rem_euclid(n),
+        Wrap::Mirror => {
+            let i = i.rem_euclid(2 * n);
+            if i < n {
+                i
+            } else {
+                2 * n - 1 - i
+            }
+        }
+        Wrap::Clamp => i.clamp(0, n - 1),
+    };
+    i as usize
+}
+/// The weights of the four pixels around a point at `t` past the second of them.
+fn catmull_rom(t: f64) -> [f64; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        0.5 * (-t3 + 2. * t2 - t),
+        0.5 * (3. * t3 - 5. * t2 + 2.),
+        0.5 * (-3. * t3 + 4. * t2 + t),
+        0.5 * (t3 - t2),
+    ]
 }
 impl Texture for ImageTexture {
-    fn value(&self, mut u: f64, mut v: f64, _p: &Vec3) -> Vec3 {
-        // Clamp input texture coordinates to [0,1] x [1,0]
-        u = clamp(u, 0., 1.);
-        v = 1.0 - clamp(v, 0., 1.); // Flip V to image coordinates
-        let mut i = (u * self.width as f64).floor() as usize;
-        let mut j = (v * self.height as f64).floor() as usize;
-        // Clamp integer mapping, since actual coordinates should be less than 1.0
-        if i >= self.width {
-            i = self.width - 1;
-        }
-        if j >= self.height {
-            j = self.height - 1;
-        }
-        let pixel = self.data.get_pixel(i as u32, j as u32).to_rgb();
-        Vec3::new(
-            pixel[0] as f64 / 255.,
-            pixel[1] as f64 / 255.,
-            pixel[2] as f64 / 255.,
-        )
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.value_filtered(u, v, p, 0.)
+    }
+    fn value_filtered(&self, u: f64, v: f64, _p: &Vec3, footprint: f64) -> Vec3 {
+        self.filtered(&self.levels, u, v, footprint)
+    }
+    fn alpha(&self, u: f64, v: f64, _p: &Vec3) -> f64 {
+        match &self.alpha_levels {
+            Some(levels) => self.filtered(levels, u, v, 0.).x().clamp(0., 1.),
+            None => 1.,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    /// The default solid checker has the cells of the product of sines it replaced.
+    #[test]
+    fn test_checker() {
+        let checker = CheckerTexture::new_rgb(Vec3::zero(), Vec3::ones());
+        for p in [
+            Vec3::new(0.1, 0.2, 0.05),
+            Vec3::new(-0.1, 0.2, 0.05),
+            Vec3::new(0.4, -0.7, 1.3),
+            Vec3::new(-2.2, 0.9, -0.33),
+        ] {
+            let sines = (p.x() * 10.).sin() * (p.y() * 10.).sin() * (p.z() * 10.).sin();
+            let odd = if sines < 0. { 1. } else { 0. };
+            assert_eq!(checker.value(0., 0., &p).x(), odd);
+        }
+        let uv = CheckerTexture::new_rgb(Vec3::zero(), Vec3::ones())
+            .with_mode(CheckerMode::Uv)
+            .with_scale(4.)
+            .with_antialiasing();
+        let p = Vec3::zero();
+        assert_eq!(uv.value(0.1, 0.1, &p).x(), 0.);
+        assert_eq!(uv.value(0.3, 0.1, &p).x(), 1.);
+        // A footprint over whole cells averages them, a tiny one picks the cell.
+        assert!((uv.value_filtered(0.3, 0.1, &p, 0.5).x() - 0.5).abs() < 1e-12);
+        assert!((uv.value_filtered(0.3, 0.1, &p, 0.01).x() - 1.).abs() < 1e-12);
+        // Straddling an edge along U only gives a mix of the two cells.
+        assert!((uv.value_filtered(0.25, 0.1, &p, 0.1).x() - 0.5).abs() < 1e-12);
+    }
+    /// Evolving noise changes smoothly with the time of the rays, and the rest ignores it.
+    #[test]
+    fn test_evolution() {
+        let textures: [(Arc<dyn Texture>, bool); 4] = [
+            (
+                Arc::new(NoiseTexture::new(4.).with_noise(Perlin::with_seed(2))),
+                false,
+            ),
+            (
+                Arc::new(TurbulenceTexture::new(3.).with_noise(Perlin::with_seed(2))),
+                false,
+            ),
+            (
+                Arc::new(
+                    NoiseTexture::new(4.)
+                        .with_noise(Perlin::with_seed(2))
+                        .with_evolution(1.),
+                ),
+                true,
+            ),
+            (
+                Arc::new(
+                    TurbulenceTexture::new(3.)
+                        .with_noise(Perlin::with_seed(2))
+                        .with_evolution(1.),
+                ),
+                true,
+            ),
+        ];
+        let p = Vec3::new(0.3, 0.6, 0.2);
+        for (texture, evolving) in textures {
+            let at = |time: f64| {
+                texture
+                    .sample(&TexturePoint {
+                        time,
+                        ..TexturePoint::new(0., 0., p)
+                    })
+                    .x()
+            };
+            assert_eq!(at(0.), texture.value(0., 0., &p).x());
+            assert!((at(0.5) - at(0.5001)).abs() < 1e-2);
+            let changes = (0..10).any(|i| (at(0.5) - at(1.5 + i as f64)).abs() > 0.01);
+            assert_eq!(changes, evolving);
+        }
+    }
+    /// Two pixels side by side, black and white.
+    fn black_white() -> ImageTexture {
+        ImageTexture::from_texels(2, 1, vec![Vec3::zero(), Vec3::ones()])
+    }
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap(Wrap::Repeat, -1, 4), 3);
+        assert_eq!(wrap(Wrap::Repeat, 9, 4), 1);
+        assert_eq!(wrap(Wrap::Mirror, -1, 4), 0);
+        assert_eq!(wrap(Wrap::Mirror, 5, 4), 2);
+        assert_eq!(wrap(Wrap::Clamp, -3, 4), 0);
+        assert_eq!(wrap(Wrap::Clamp, 7, 4), 3);
+    }
+    #[test]
+    fn test_filters() {
+        let p = Vec3::zero();
+        let nearest = black_white().with_filter(Filter::Nearest);
+        assert_eq!(nearest.value(0.4, 0.5, &p).x(), 0.);
+        assert_eq!(nearest.value(0.6, 0.5, &p).x(), 1.);
+        // Halfway between the pixel centers, and at the centers themselves.
+        let bilinear = black_white().with_filter(Filter::Bilinear);
+        assert!((bilinear.value(0.5, 0.5, &p).x() - 0.5).abs() < 1e-9);
+        assert_eq!(bilinear.value(0.25, 0.5, &p).x(), 0.);
+        assert_eq!(bilinear.value(0.75, 0.5, &p).x(), 1.);
+        let bicubic = black_white().with_filter(Filter::Bicubic);
+        assert!((bicubic.value(0.5, 0.5, &p).x() - 0.5).abs() < 1e-9);
+        // Repeating blends the white pixel back into the black one across the edge.
+        let repeat = black_white()
+            .with_filter(Filter::Bilinear)
+            .with_wrap(Wrap::Repeat, Wrap::Clamp);
+        assert!((repeat.value(0., 0.5, &p).x() - 0.5).abs() < 1e-9);
+        let tiled = repeat.with_uv_transform((2., 1.), (0.5, 0.));
+        assert_eq!(tiled.value(0.375, 0.5, &p).x(), 0.);
+    }
+    #[test]
+    fn test_srgb_and_alpha() {
+        assert_eq!(srgb_to_linear(0.), 0.);
+        assert!((srgb_to_linear(1.) - 1.).abs() < 1e-12);
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+        let p = Vec3::zero();
+        let mut texture = black_white().with_filter(Filter::Nearest);
+        assert_eq!(texture.alpha(0.25, 0.5, &p), 1.);
+        texture.alpha_levels = Some(mipmap(2, 1, vec![Vec3::ones(), Vec3::zero()]));
+        assert_eq!(texture.alpha(0.25, 0.5, &p), 1.);
+        assert_eq!(texture.alpha(0.75, 0.5, &p), 0.);
+    }
+    /// A footprint as wide as the texture looks up its average.
+    #[test]
+    fn test_mipmap() {
+        let texture = black_white();
+        assert_eq!(texture.levels.len(), 2);
+        let p = Vec3::zero();
+        assert_eq!(texture.value_filtered(0.75, 0.5, &p, 0.).x(), 1.);
+        assert!((texture.value_filtered(0.75, 0.5, &p, 1.).x() - 0.5).abs() < 1e-9);
+        // In between, the two levels are blended by the logarithm of the footprint.
+        let expected = 1. - 0.5 * 1.5f64.log2();
+        assert!((texture.value_filtered(0.75, 0.5, &p, 0.75).x() - expected).abs() < 1e-9);
+    }
+    /// Odd sizes keep their last column and row all the way down to a single pixel.
+    #[test]
+    fn test_mipmap_odd() {
+        let white_edges = (0..9)
+            .map(|k| {
+                if k % 3 == 2 || k >= 6 {
+                    Vec3::ones()
+                } else {
+                    Vec3::zero()
+                }
+            })
+            .collect();
+        let texture = ImageTexture::from_texels(3, 3, white_edges);
+        let sizes: Vec<_> = texture.levels.iter().map(|l| (l.width, l.height)).collect();
+        assert_eq!(sizes, [(3, 3), (2, 2), (1, 1)]);
+        let level = &texture.levels[1];
+        assert_eq!(level.texels[0].x(), 0.);
+        assert_eq!(level.texels[1].x(), 1.);
+        assert_eq!(level.texels[3].x(), 1.);
+        assert!(texture.levels[2].texels[0].x() > 0.5);
     }
 }
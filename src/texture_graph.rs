@@ -0,0 +1,548 @@
+use crate::perlin::Perlin;
+use crate::procedural::*;
+use crate::texture::*;
+use crate::vec3::Vec3;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+
+/// What an `InputTexture` passes on of the point it is looked up at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    /// (u, v, 0)
+    Uv,
+    /// The position in world space.
+    Position,
+    /// The outward normal, with components in [-1,1].
+    Normal,
+}
+/// The coordinates of the lookup itself, to feed ramps and math nodes.
+pub struct InputTexture {
+    input: Input,
+}
+impl InputTexture {
+    pub fn new(input: Input) -> Self {
+        Self { input }
+    }
+}
+impl Texture for InputTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.sample(&TexturePoint::new(u, v, *p))
+    }
+    fn sample(&self, point: &TexturePoint) -> Vec3 {
+        match self.input {
+            Input::Uv => Vec3::new(point.u, point.v, 0.),
+            Input::Position => point.p,
+            Input::Normal => point.normal,
+        }
+    }
+}
+/// How a `MathTexture` combines its two textures, channel by channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MathOp {
+    Add,
+    Subtract,
+    Multiply,
+    Minimum,
+    Maximum,
+}
+pub struct MathTexture {
+    op: MathOp,
+    a: Arc<dyn Texture>,
+    b: Arc<dyn Texture>,
+}
+impl MathTexture {
+    pub fn new(op: MathOp, a: Arc<dyn Texture>, b: Arc<dyn Texture>) -> Self {
+        Self { op, a, b }
+    }
+    /// `texture` with every channel multiplied by `factor`.
+    pub fn scale(texture: Arc<dyn Texture>, factor: f64) -> Self {
+        Self::new(
+            MathOp::Multiply,
+            texture,
+            Arc::new(SolidColor::new(Vec3::ones() * factor)),
+        )
+    }
+}
+impl Texture for MathTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.sample(&TexturePoint::new(u, v, *p))
+    }
+    fn sample(&self, point: &TexturePoint) -> Vec3 {
+        let a = self.a.sample(point);
+        let b = self.b.sample(point);
+        let channel = |a: f64, b: f64| match self.op {
+            MathOp::Add => a + b,
+            MathOp::Subtract => a - b,
+            MathOp::Multiply => a * b,
+            MathOp::Minimum => a.min(b),
+            MathOp::Maximum => a.max(b),
+        };
+        Vec3::new(
+            channel(a.x(), b.x()),
+            channel(a.y(), b.y()),
+            channel(a.z(), b.z()),
+        )
+    }
+}
+/// `a` where the mask is 0 and `b` where it is 1, blended in between.
+/// A gray mask blends the whole color, a colored one each channel on its own.
+pub struct MixTexture {
+    a: Arc<dyn Texture>,
+    b: Arc<dyn Texture>,
+    mask: Arc<dyn Texture>,
+}
+impl MixTexture {
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>, mask: Arc<dyn Texture>) -> Self {
+        Self { a, b, mask }
+    }
+}
+impl Texture for MixTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.sample(&TexturePoint::new(u, v, *p))
+    }
+    fn sample(&self, point: &TexturePoint) -> Vec3 {
+        let mask = self.mask.sample(point);
+        Vec3::elemul(self.a.sample(point), Vec3::ones() - mask)
+            + Vec3::elemul(self.b.sample(point), mask)
+    }
+}
+/// A scalar texture, its red channel, mapped to a gradient of colors.
+pub struct RampTexture {
+    input: Arc<dyn Texture>,
+    ramp: ColorRamp,
+}
+impl RampTexture {
+    pub fn new(input: Arc<dyn Texture>, ramp: ColorRamp) -> Self {
+        Self { input, ramp }
+    }
+}
+impl Texture for RampTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.sample(&TexturePoint::new(u, v, *p))
+    }
+    fn sample(&self, point: &TexturePoint) -> Vec3 {
+        self.ramp.at(self.input.sample(point).x())
+    }
+}
+/// A texture with its hue turned by `hue_shift` degrees
+/// and its saturation and value multiplied by the given factors.
+pub struct HsvTexture {
+    input: Arc<dyn Texture>,
+    hue_shift: f64,
+    saturation: f64,
+    value: f64,
+}
+impl HsvTexture {
+    pub fn new(input: Arc<dyn Texture>, hue_shift: f64, saturation: f64, value: f64) -> Self {
+        Self {
+            input,
+            hue_shift,
+            saturation,
+            value,
+        }
+    }
+}
+impl Texture for HsvTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.sample(&TexturePoint::new(u, v, *p))
+    }
+    fn sample(&self, point: &TexturePoint) -> Vec3 {
+        let (h, s, v) = rgb_to_hsv(self.input.sample(point));
+        hsv_to_rgb(
+            (h + self.hue_shift).rem_euclid(360.),
+            (s * self.saturation).clamp(0., 1.),
+            (v * self.value).max(0.),
+        )
+    }
+}
+/// A texture projected along the three axes and blended by how much the surface faces each,
+/// which covers objects without U,V coordinates or with stretched ones, like rocks and terrain.
+///
+/// The position times `scale` gives the U,V of the three projections.
+/// A higher `sharpness` narrows the seams where they blend.
+/// Off a surface the texture is looked up as usual.
+pub struct TriplanarTexture {
+    texture: Arc<dyn Texture>,
+    scale: f64,
+    sharpness: f64,
+}
+impl TriplanarTexture {
+    pub fn new(texture: Arc<dyn Texture>, scale: f64, sharpness: f64) -> Self {
+        Self {
+            texture,
+            scale,
+            sharpness,
+        }
+    }
+}
+impl Texture for TriplanarTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.sample(&TexturePoint::new(u, v, *p))
+    }
+    fn sample(&self, point: &TexturePoint) -> Vec3 {
+        let n = point.normal;
+        let weights = Vec3::new(
+            n.x().abs().powf(self.sharpness),
+            n.y().abs().powf(self.sharpness),
+            n.z().abs().powf(self.sharpness),
+        );
+        let total = weights.x() + weights.y() + weights.z();
+        if total <= 0. {
+            return self.texture.sample(point);
+        }
+        let p = point.p * self.scale;
+        let projected = |u: f64, v: f64| {
+            self.texture.sample(&TexturePoint {
+                u,
+                v,
+                footprint: point.footprint * self.scale,
+                ..*point
+            })
+        };
+        let mut color = Vec3::zero();
+        if weights.x() > 0. {
+            color += projected(p.z(), p.y()) * weights.x();
+        }
+        if weights.y() > 0. {
+            color += projected(p.x(), p.z()) * weights.y();
+        }
+        if weights.z() > 0. {
+            color += projected(p.x(), p.y()) * weights.z();
+        }
+        color / total
+    }
+}
+/// Hue in degrees, saturation and value of a linear RGB color.
+fn rgb_to_hsv(color: Vec3) -> (f64, f64, f64) {
+    let (r, g, b) = (color.x(), color.y(), color.z());
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let hue = if delta <= 0. {
+        0.
+    } else if max == r {
+        60. * ((g - b) / delta).rem_euclid(6.)
+    } else if max == g {
+        60. * ((b - r) / delta + 2.)
+    } else {
+        60. * ((r - g) / delta + 4.)
+    };
+    let saturation = if max > 0. { delta / max } else { 0. };
+    (hue, saturation, max)
+}
+fn hsv_to_rgb(hue: f64, saturation: f64, value: f64) -> Vec3 {
+    let chroma = value * saturation;
+    let h = hue / 60.;
+    let x = chroma * (1. - (h.rem_euclid(2.) - 1.).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.),
+        1 => (x, chroma, 0.),
+        2 => (0., chroma, x),
+        3 => (0., x, chroma),
+        4 => (x, 0., chroma),
+        _ => (chroma, 0., x),
+    };
+    let m = value - chroma;
+    Vec3::new(r + m, g + m, b + m)
+}
+
+/// Load the texture graph in `filename`; see `parse_texture_graph` for the format.
+pub fn load_texture_graph(filename: &str) -> Arc<dyn Texture> {
+    let text = fs::read_to_string(filename)
+        .unwrap_or_else(|error| panic!("Cannot read {}: {}", filename, error));
+    parse_texture_graph(&text)
+        .unwrap_or_else(|error| panic!("Cannot parse {}: {}", filename, error))
+}
+/// Parse the text of a texture graph, a node per line written as `name = kind arguments...`,
+/// whose arguments are numbers, words or the names of the nodes defined above it.
+/// Everything after a `#` is a comment. The texture of the graph is the node named `output`.
+///
+/// The kinds of nodes are
+/// - `color R G B`, `uv`, `position`, `normal`
+/// - `image FILE [repeat|mirror|clamp]`, and `data FILE [...]` for images that are not colors
+/// - `noise SCALE`, `turbulence SCALE`, `fbm SCALE`, `ridged SCALE`, `worley SCALE f1|f2-f1`
+/// - `add A B`, `subtract A B`, `multiply A B`, `min A B`, `max A B`, `scale A FACTOR`
+/// - `mix A B MASK`, `checker EVEN ODD`, `warp A SCALE STRENGTH`
+/// - `ramp INPUT POSITION R G B [POSITION R G B ...]`
+/// - `hsv INPUT HUE SATURATION VALUE`
+/// - `triplanar INPUT SCALE SHARPNESS`
+///
+/// The nodes made of noise, `noise` to `worley` and `warp`, take a whole number as a last
+/// optional argument, the seed that makes them the same on every run.
+pub fn parse_texture_graph(text: &str) -> Result<Arc<dyn Texture>, String> {
+    let mut nodes: HashMap<String, Arc<dyn Texture>> = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (name, definition) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected \"name = node\"", number + 1))?;
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("line {}: invalid name \"{}\"", number + 1, name));
+        }
+        if nodes.contains_key(name) {
+            return Err(format!(
+                "line {}: \"{}\" is defined twice",
+                number + 1,
+                name
+            ));
+        }
+        let mut args = Arguments {
+            tokens: definition.split_whitespace(),
+            nodes: &nodes,
+        };
+        let node = args
+            .node()
+            .and_then(|node| args.end().map(|_| node))
+            .map_err(|error| format!("line {}: {}", number + 1, error))?;
+        nodes.insert(name.to_string(), node);
+    }
+    nodes
+        .remove("output")
+        .ok_or_else(|| "no node named \"output\"".to_string())
+}
+/// The rest of the words of a node definition.
+struct Arguments<'a> {
+    tokens: std::str::SplitWhitespace<'a>,
+    nodes: &'a HashMap<String, Arc<dyn Texture>>,
+}
+impl<'a> Arguments<'a> {
+    fn word(&mut self) -> Result<&'a str, String> {
+        self.tokens
+            .next()
+            .ok_or_else(|| "missing argument".to_string())
+    }
+    fn number(&mut self) -> Result<f64, String> {
+        let token = self.word()?;
+        token
+            .parse()
+            .map_err(|_| format!("invalid number \"{}\"", token))
+    }
+    fn color(&mut self) -> Result<Vec3, String> {
+        Ok(Vec3::new(self.number()?, self.number()?, self.number()?))
+    }
+    fn texture(&mut self) -> Result<Arc<dyn Texture>, String> {
+        let token = self.word()?;
+        self.nodes
+            .get(token)
+            .cloned()
+            .ok_or_else(|| format!("unknown node \"{}\"", token))
+    }
+    fn end(&mut self) -> Result<(), String> {
+        match self.tokens.next() {
+            Some(token) => Err(format!("unexpected \"{}\"", token)),
+            None => Ok(()),
+        }
+    }
+    /// The optional seed at the end of a node.
+    fn seed(&mut self) -> Result<Option<u64>, String> {
+        match self.tokens.next() {
+            Some(token) => token
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid seed \"{}\"", token)),
+            None => Ok(None),
+        }
+    }
+    /// Noise from the optional seed, or different on every run without one.
+    fn noise(&mut self) -> Result<Perlin, String> {
+        Ok(self.seed()?.map_or_else(Perlin::new, Perlin::with_seed))
+    }
+    fn image(&mut self, color: bool) -> Result<Arc<dyn Texture>, String> {
+        let filename = self.word()?.to_string();
+        let wrap = match self.tokens.clone().next() {
+            Some("repeat") => Wrap::Repeat,
+            Some("mirror") => Wrap::Mirror,
+            Some("clamp") | None => Wrap::Clamp,
+            Some(other) => return Err(format!("unknown wrap mode \"{}\"", other)),
+        };
+        self.tokens.next();
+        let image = if color {
+            ImageTexture::new(&filename)
+        } else {
+            ImageTexture::new_non_color(&filename)
+        };
+        Ok(Arc::new(image.with_wrap(wrap, wrap)))
+    }
+    fn node(&mut self) -> Result<Arc<dyn Texture>, String> {
+        let kind = self.word()?;
+        Ok(match kind {
+            "color" => Arc::new(SolidColor::new(self.color()?)),
+            "uv" => Arc::new(InputTexture::new(Input::Uv)),
+            "position" => Arc::new(InputTexture::new(Input::Position)),
+            "normal" => Arc::new(InputTexture::new(Input::Normal)),
+            "image" => self.image(true)?,
+            "data" => self.image(false)?,
+            "noise" => Arc::new(NoiseTexture::new(self.number()?).with_noise(self.noise()?)),
+            "turbulence" => {
+                Arc::new(TurbulenceTexture::new(self.number()?).with_noise(self.noise()?))
+            }
+            "fbm" => Arc::new(FbmTexture::new(self.number()?).with_noise(self.noise()?)),
+            "ridged" => Arc::new(RidgedTexture::new(self.number()?).with_noise(self.noise()?)),
+            "worley" => {
+                let scale = self.number()?;
+                let feature = match self.word()? {
+                    "f1" => Cellular::F1,
+                    "f2-f1" => Cellular::F2MinusF1,
+                    other => return Err(format!("unknown cellular feature \"{}\"", other)),
+                };
+                let worley = WorleyTexture::new(scale, feature);
+                match self.seed()? {
+                    Some(seed) => Arc::new(worley.with_seed(seed)),
+                    None => Arc::new(worley),
+                }
+            }
+            "add" | "subtract" | "multiply" | "min" | "max" => {
+                let op = match kind {
+                    "add" => MathOp::Add,
+                    "subtract" => MathOp::Subtract,
+                    "multiply" => MathOp::Multiply,
+                    "min" => MathOp::Minimum,
+                    _ => MathOp::Maximum,
+                };
+                Arc::new(MathTexture::new(op, self.texture()?, self.texture()?))
+            }
+            "scale" => Arc::new(MathTexture::scale(self.texture()?, self.number()?)),
+            "mix" => Arc::new(MixTexture::new(
+                self.texture()?,
+                self.texture()?,
+                self.texture()?,
+            )),
+            "checker" => Arc::new(CheckerTexture::new(self.texture()?, self.texture()?)),
+            "warp" => Arc::new(
+                DomainWarp::new(self.texture()?, self.number()?, self.number()?)
+                    .with_noise(self.noise()?),
+            ),
+            "ramp" => {
+                let input = self.texture()?;
+                let mut stops = vec![(self.number()?, self.color()?)];
+                while self.tokens.clone().next().is_some() {
+                    stops.push((self.number()?, self.color()?));
+                }
+                Arc::new(RampTexture::new(input, ColorRamp::new(stops)))
+            }
+            "hsv" => Arc::new(HsvTexture::new(
+                self.texture()?,
+                self.number()?,
+                self.number()?,
+                self.number()?,
+            )),
+            "triplanar" => Arc::new(TriplanarTexture::new(
+                self.texture()?,
+                self.number()?,
+                self.number()?,
+            )),
+            other => return Err(format!("unknown node \"{}\"", other)),
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_nodes() {
+        let gray = |g: f64| -> Arc<dyn Texture> { Arc::new(SolidColor::new(Vec3::ones() * g)) };
+        let red: Arc<dyn Texture> = Arc::new(SolidColor::new(Vec3::new(1., 0., 0.)));
+        let point = TexturePoint::new(0.25, 0.75, Vec3::zero());
+        let mix = MixTexture::new(gray(0.), gray(1.), gray(0.25));
+        assert!((mix.sample(&point) - Vec3::ones() * 0.25).length() < 1e-12);
+        let difference = MathTexture::new(MathOp::Subtract, gray(1.), red.clone());
+        assert_eq!(difference.sample(&point), Vec3::new(0., 1., 1.));
+        // Turning red by a third of the way around the wheel gives green.
+        let green = HsvTexture::new(red, 120., 1., 1.).sample(&point);
+        assert!((green - Vec3::new(0., 1., 0.)).length() < 1e-12);
+        let ramp = RampTexture::new(
+            Arc::new(InputTexture::new(Input::Uv)),
+            ColorRamp::new(vec![(0., Vec3::zero()), (1., Vec3::ones())]),
+        );
+        assert!((ramp.sample(&point) - Vec3::ones() * 0.25).length() < 1e-12);
+    }
+    /// A triplanar projection follows the axis the surface faces.
+    #[test]
+    fn test_triplanar() {
+        let uv = TriplanarTexture::new(Arc::new(InputTexture::new(Input::Uv)), 2., 4.);
+        let p = Vec3::new(0.1, 0.2, 0.3);
+        let up = TexturePoint {
+            normal: Vec3::new(0., 1., 0.),
+            ..TexturePoint::new(0., 0., p)
+        };
+        assert!((uv.sample(&up) - Vec3::new(0.2, 0.6, 0.)).length() < 1e-12);
+        let side = TexturePoint {
+            normal: Vec3::new(-1., 0., 0.),
+            ..up
+        };
+        assert!((uv.sample(&side) - Vec3::new(0.6, 0.4, 0.)).length() < 1e-12);
+    }
+    #[test]
+    fn test_parse() {
+        let graph = parse_texture_graph(
+            "# a gradient along U, darkened where the surface faces up
+            u = uv
+            gradient = ramp u 0 0 0 0  1 1 1 1
+            up = normal
+            half = color 0.5 0.5 0.5
+            output = mix gradient half up  # blend
+            ",
+        )
+        .unwrap();
+        let point = TexturePoint {
+            normal: Vec3::new(0., 1., 0.),
+            ..TexturePoint::new(0.8, 0., Vec3::zero())
+        };
+        assert!((graph.sample(&point) - Vec3::new(0.8, 0.5, 0.8)).length() < 1e-12);
+        assert!(parse_texture_graph("a = color 1 1 1").is_err());
+        assert!(parse_texture_graph("output = scale missing 2").is_err());
+        assert!(parse_texture_graph("output = color 1 1").is_err());
+        assert!(parse_texture_graph("output = uv 3").is_err());
+        assert!(parse_texture_graph("output = sparkle").is_err());
+        assert!(parse_texture_graph("output = fbm 2 -1").is_err());
+        assert!(parse_texture_graph("output = worley 2 f1 7 8").is_err());
+    }
+    /// Noise with a seed is the same every time it is parsed, and differs without one.
+    #[test]
+    fn test_seeds() {
+        let p = TexturePoint::new(0., 0., Vec3::new(0.37, 1.21, -0.53));
+        for node in [
+            "fbm 2",
+            "ridged 2",
+            "turbulence 2",
+            "noise 2",
+            "worley 2 f1",
+        ] {
+            let parse = |seed: &str| {
+                parse_texture_graph(&format!("output = {} {}", node, seed))
+                    .unwrap()
+                    .sample(&p)
+            };
+            assert_eq!(parse("42"), parse("42"), "{}", node);
+            assert_ne!(parse("42"), parse("43"), "{}", node);
+        }
+    }
+    /// Nodes under checkers and warps still see the normal of the surface.
+    #[test]
+    fn test_nested_triplanar() {
+        let graph = parse_texture_graph(
+            "u = uv
+            projected = triplanar u 1 8
+            still = warp projected 1 0
+            black = color 0 0 0
+            output = checker still black
+            ",
+        )
+        .unwrap();
+        let up = TexturePoint {
+            normal: Vec3::new(0., 1., 0.),
+            ..TexturePoint::new(0.9, 0.9, Vec3::new(0.1, 0.2, 0.3))
+        };
+        assert!((graph.sample(&up) - Vec3::new(0.1, 0.3, 0.)).length() < 1e-12);
+        let side = TexturePoint {
+            normal: Vec3::new(-1., 0., 0.),
+            ..up
+        };
+        assert!((graph.sample(&side) - Vec3::new(0.3, 0.2, 0.)).length() < 1e-12);
+    }
+}
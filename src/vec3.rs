@@ -55,6 +55,14 @@ impl Vec3 {
             a.x * b.y - a.y * b.x,
         )
     }
+    /// The product of a 3×3 `matrix`, in row major order, and `v`.
+    pub fn transform(matrix: &[f64; 9], v: &Vec3) -> Self {
+        Self::new(
+            matrix[0] * v.x + matrix[1] * v.y + matrix[2] * v.z,
+            matrix[3] * v.x + matrix[4] * v.y + matrix[5] * v.z,
+            matrix[6] * v.x + matrix[7] * v.y + matrix[8] * v.z,
+        )
+    }
     pub fn unit(self) -> Self {
         let length = self.length();
         if length == 0.0 {
@@ -354,7 +362,7 @@ mod tests {
     fn test_length() {
         assert_eq!(
             Vec3::new(3.0, 4.0, 5.0).length(),
-            ((3.0 * 3.0 + 4.0 * 4.0 + 5.0 * 5.0) as f64).sqrt()
+            (3.0 * 3.0 + 4.0 * 4.0 + 5.0 * 5.0_f64).sqrt()
         );
     }
     #[test]
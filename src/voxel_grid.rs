@@ -0,0 +1,478 @@
+use crate::aabb::AABB;
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+use std::collections::HashMap;
+use std::fs;
+
+/// The voxels of a leaf block are 8 to a side, as in OpenVDB and NanoVDB.
+const LEAF_LOG2DIM: i32 = 3;
+const LEAF_SIZE: usize = 1 << (3 * LEAF_LOG2DIM);
+
+/// A sparse grid of float voxels, like the density caches of fluid simulations,
+/// which can be the density of a `HeterogeneousMedium`.
+///
+/// The voxels are stored in blocks of 8×8×8, and only the blocks that have been set take any memory.
+/// Voxel (i, j, k) of index space is centered at the world space point
+/// `matrix * (i, j, k) + translation`, and the grid is interpolated trilinearly between the centers.
+/// Voxels that were never set are 0.
+///
+/// # The rtgrid format
+///
+/// A line of text, then little endian binary data right after its newline.
+/// The line is either `rtgrid dense NX NY NZ VOXEL_SIZE X Y Z`,
+/// followed by NX·NY·NZ `f32` values with x varying the fastest, then y, then z,
+/// or `rtgrid sparse COUNT VOXEL_SIZE X Y Z`,
+/// followed by COUNT records of three `i32` indices and a `f32` value.
+/// Either way, voxel (0, 0, 0) is centered at (X, Y, Z), and the voxels are cubes of side VOXEL_SIZE.
+pub struct VoxelGrid {
+    /// the blocks by the index of their first voxel divided by 8,
+    /// each with its voxels in x major order, like the leaf nodes of NanoVDB
+    leaves: HashMap<[i32; 3], Box<[f32; LEAF_SIZE]>>,
+    /// the index space bounds of the voxels that have been set
+    index_min: [i32; 3],
+    index_max: [i32; 3],
+    /// the map from index space to world space, with its matrices in row major order
+    matrix: [f64; 9],
+    inverse: [f64; 9],
+    translation: Vec3,
+}
+impl Default for VoxelGrid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl VoxelGrid {
+    /// An empty grid, with index space the same as world space.
+    pub fn new() -> Self {
+        let identity = [1., 0., 0., 0., 1., 0., 0., 0., 1.];
+        Self {
+            leaves: HashMap::new(),
+            index_min: [i32::MAX; 3],
+            index_max: [i32::MIN; 3],
+            matrix: identity,
+            inverse: identity,
+            translation: Vec3::zero(),
+        }
+    }
+    /// Read an rtgrid file, or a NanoVDB file if the name ends with `.nvdb`.
+    pub fn load(filename: &str) -> Self {
+        let bytes = fs::read(filename)
+            .unwrap_or_else(|error| panic!("Cannot read {}: {}", filename, error));
+        let grid = if filename.ends_with(".nvdb") {
+            Self::parse_nanovdb(&bytes)
+        } else {
+            Self::parse_rtgrid(&bytes)
+        };
+        grid.unwrap_or_else(|error| panic!("Cannot parse {}: {}", filename, error))
+    }
+    /// Place the grid in world space: voxel (0, 0, 0) is centered at `origin`,
+    /// and the voxels are cubes of side `voxel_size`.
+    pub fn with_transform(mut self, origin: Vec3, voxel_size: f64) -> Self {
+        let s = voxel_size;
+        self.matrix = [s, 0., 0., 0., s, 0., 0., 0., s];
+        self.inverse = [1. / s, 0., 0., 0., 1. / s, 0., 0., 0., 1. / s];
+        self.translation = origin;
+        self
+    }
+    pub fn get(&self, ijk: [i32; 3]) -> f32 {
+        match self.leaves.get(&leaf_key(ijk)) {
+            Some(leaf) => leaf[leaf_offset(ijk)],
+            None => 0.,
+        }
+    }
+    pub fn set(&mut self, ijk: [i32; 3], value: f32) {
+        for (axis, &c) in ijk.iter().enumerate() {
+            self.index_min[axis] = self.index_min[axis].min(c);
+            self.index_max[axis] = self.index_max[axis].max(c);
+        }
+        self.leaves
+            .entry(leaf_key(ijk))
+            .or_insert_with(|| Box::new([0.; LEAF_SIZE]))[leaf_offset(ijk)] = value;
+    }
+    /// The largest value of the grid, which is the maximum density a medium needs.
+    pub fn max_value(&self) -> f64 {
+        self.leaves
+            .values()
+            .flat_map(|leaf| leaf.iter())
+            .fold(0., |max, &value| max.max(value as f64))
+    }
+    /// Scale the values so that the largest is 1, as the density of a `HeterogeneousMedium` is.
+    pub fn normalized(mut self) -> Self {
+        let max = self.max_value() as f32;
+        if max > 0. {
+            for leaf in self.leaves.values_mut() {
+                leaf.iter_mut().for_each(|value| *value /= max);
+            }
+        }
+        self
+    }
+    /// The world space box outside of which the grid is 0.
+    pub fn bounding_box(&self) -> AABB {
+        let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = -min;
+        // The interpolation reaches a voxel further than the centers.
+        for corner in 0..8 {
+            let pick = |axis: usize| {
+                if corner >> axis & 1 == 0 {
+                    self.index_min[axis] as f64 - 1.
+                } else {
+                    self.index_max[axis] as f64 + 1.
+                }
+            };
+            let p = self.to_world(&Vec3::new(pick(0), pick(1), pick(2)));
+            min = Vec3::new(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z()));
+            max = Vec3::new(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z()));
+        }
+        AABB::new(min, max)
+    }
+    fn to_world(&self, index: &Vec3) -> Vec3 {
+        Vec3::transform(&self.matrix, index) + self.translation
+    }
+    fn to_index(&self, p: &Vec3) -> Vec3 {
+        Vec3::transform(&self.inverse, &(*p - self.translation))
+    }
+    /// The value of the grid at the world space point `p`, interpolated trilinearly.
+    pub fn sample(&self, p: &Vec3) -> f64 {
+        let index = self.to_index(p);
+        let base = [index.x().floor(), index.y().floor(), index.z().floor()];
+        let (fx, fy, fz) = (
+            index.x() - base[0],
+            index.y() - base[1],
+            index.z() - base[2],
+        );
+        let base = [base[0] as i32, base[1] as i32, base[2] as i32];
+        // Far from the voxels that have been set, which may be very far.
+        for (axis, &c) in base.iter().enumerate() {
+            if c < self.index_min[axis].saturating_sub(1) || c > self.index_max[axis] {
+                return 0.;
+            }
+        }
+        let mut value = 0.;
+        for (di, wx) in [(0, 1. - fx), (1, fx)] {
+            for (dj, wy) in [(0, 1. - fy), (1, fy)] {
+                for (dk, wz) in [(0, 1. - fz), (1, fz)] {
+                    let voxel = self.get([base[0] + di, base[1] + dj, base[2] + dk]);
+                    value += wx * wy * wz * voxel as f64;
+                }
+            }
+        }
+        value
+    }
+    /// Parse the bytes of an rtgrid file.
+    pub fn parse_rtgrid(bytes: &[u8]) -> Result<Self, String> {
+        let newline = bytes
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .ok_or_else(|| "missing header".to_string())?;
+        let header = std::str::from_utf8(&bytes[..newline]).map_err(|error| error.to_string())?;
+        let data = &bytes[newline + 1..];
+        let tokens: Vec<&str> = header.split_whitespace().collect();
+        let number = |index: usize| {
+            let token = tokens
+                .get(index)
+                .ok_or_else(|| "header too short".to_string())?;
+            token
+                .parse::<f64>()
+                .map_err(|_| format!("invalid number \"{}\"", token))
+        };
+        if tokens.first() != Some(&"rtgrid") {
+            return Err("not an rtgrid file".to_string());
+        }
+        let mut grid = Self::new();
+        let transform_at = match tokens.get(1) {
+            Some(&"dense") => {
+                let (nx, ny, nz) = (
+                    number(2)? as usize,
+                    number(3)? as usize,
+                    number(4)? as usize,
+                );
+                for z in 0..nz {
+                    for y in 0..ny {
+                        for x in 0..nx {
+                            let value = f32_at(data, 4 * (x + nx * (y + ny * z)))?;
+                            if value != 0. {
+                                grid.set([x as i32, y as i32, z as i32], value);
+                            }
+                        }
+                    }
+                }
+                5
+            }
+            Some(&"sparse") => {
+                let count = number(2)? as usize;
+                for record in 0..count {
+                    let offset = 16 * record;
+                    let ijk = [
+                        i32_at(data, offset)?,
+                        i32_at(data, offset + 4)?,
+                        i32_at(data, offset + 8)?,
+                    ];
+                    grid.set(ijk, f32_at(data, offset + 12)?);
+                }
+                3
+            }
+            _ => return Err("unknown layout, expected dense or sparse".to_string()),
+        };
+        let origin = Vec3::new(
+            number(transform_at + 1)?,
+            number(transform_at + 2)?,
+            number(transform_at + 3)?,
+        );
+        Ok(grid.with_transform(origin, number(transform_at)?))
+    }
+    /// Parse the bytes of a NanoVDB file, taking its first float grid.
+    ///
+    /// The grid has to be stored without compression, in the memory layout of NanoVDB 32.
+    /// Its voxels are read from the leaf nodes, and the constant tiles of the internal nodes
+    /// are filled in as voxels too. Tiles of the root node, which are 4096 voxels to a side,
+    /// and the inactive voxels (the background) are left out.
+    pub fn parse_nanovdb(bytes: &[u8]) -> Result<Self, String> {
+        let magic = u64_at(bytes, 0)?;
+        if magic != NANOVDB_MAGIC && magic != NANOVDB_MAGIC_FILE {
+            return Err("not a NanoVDB file".to_string());
+        }
+        let grid_count = u16_at(bytes, 12)?;
+        if u16_at(bytes, 14)? != 0 {
+            return Err("compressed grids are not supported".to_string());
+        }
+        let mut offset = FILE_HEADER_SIZE;
+        for _ in 0..grid_count {
+            let grid_size = u64_at(bytes, offset)? as usize;
+            let grid_type = u32_at(bytes, offset + 32)?;
+            let name_size = u32_at(bytes, offset + 136)? as usize;
+            let grid_start = offset + FILE_METADATA_SIZE + name_size;
+            if grid_type == GRID_TYPE_FLOAT {
+                let end = grid_start
+                    .checked_add(grid_size)
+                    .filter(|&end| end <= bytes.len())
+                    .ok_or_else(|| "truncated grid".to_string())?;
+                return Self::parse_nanovdb_grid(&bytes[grid_start..end]);
+            }
+            offset = grid_start + grid_size;
+        }
+        Err("no float grid".to_string())
+    }
+    /// Read a grid from the buffer of a NanoVDB grid, which starts with its `GridData`.
+    fn parse_nanovdb_grid(buffer: &[u8]) -> Result<Self, String> {
+        let mut grid = Self::new();
+        for row in 0..9 {
+            grid.matrix[row] = f64_at(buffer, MAP_MATRIX + 8 * row)?;
+            grid.inverse[row] = f64_at(buffer, MAP_INVERSE + 8 * row)?;
+        }
+        grid.translation = Vec3::new(
+            f64_at(buffer, MAP_TRANSLATION)?,
+            f64_at(buffer, MAP_TRANSLATION + 8)?,
+            f64_at(buffer, MAP_TRANSLATION + 16)?,
+        );
+        // The tree data gives where each level of nodes starts, relative to itself, and how many there are.
+        let tree = GRID_DATA_SIZE;
+        let node_start = |level: usize| -> Result<usize, String> {
+            Ok(tree + u64_at(buffer, tree + 8 * level)? as usize)
+        };
+        let node_count = |level: usize| u32_at(buffer, tree + 32 + 4 * level);
+
+        let leaves = node_start(0)?;
+        for leaf in 0..node_count(0)? as usize {
+            let node = leaves + leaf * LEAF_NODE_SIZE;
+            let origin = read_coord(buffer, node)?.map(|c| c & !7);
+            for n in 0..LEAF_SIZE {
+                if !mask_bit(buffer, node + 16, n)? {
+                    continue;
+                }
+                let ijk = [
+                    origin[0] + (n >> 6) as i32,
+                    origin[1] + (n >> 3 & 7) as i32,
+                    origin[2] + (n & 7) as i32,
+                ];
+                grid.set(ijk, f32_at(buffer, node + 96 + 4 * n)?);
+            }
+        }
+        // Tiles of the lower (16³ children of 8³) and upper (32³ children of 128³) internal nodes.
+        for (level, log2dim, child_log2dim, table, node_size) in [
+            (1, 4, 3, LOWER_TABLE, LOWER_NODE_SIZE),
+            (2, 5, 7, UPPER_TABLE, UPPER_NODE_SIZE),
+        ] {
+            let size = 1usize << (3 * log2dim);
+            let mask_bytes = size / 8;
+            let start = node_start(level)?;
+            for index in 0..node_count(level)? as usize {
+                let node = start + index * node_size;
+                let origin =
+                    read_coord(buffer, node)?.map(|c| c & !((1 << (log2dim + child_log2dim)) - 1));
+                for n in 0..size {
+                    let active = mask_bit(buffer, node + 32, n)?;
+                    let child = mask_bit(buffer, node + 32 + mask_bytes, n)?;
+                    if !active || child {
+                        continue;
+                    }
+                    let value = f32_at(buffer, node + table + 8 * n)?;
+                    let dim = 1usize << log2dim;
+                    let tile = [n / (dim * dim), n / dim % dim, n % dim].map(|local| local as i32);
+                    let extent = 1 << child_log2dim;
+                    for i in 0..extent {
+                        for j in 0..extent {
+                            for k in 0..extent {
+                                grid.set(
+                                    [
+                                        origin[0] + tile[0] * extent + i,
+                                        origin[1] + tile[1] * extent + j,
+                                        origin[2] + tile[2] * extent + k,
+                                    ],
+                                    value,
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(grid)
+    }
+}
+impl Texture for VoxelGrid {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        let value = self.sample(p);
+        Vec3::new(value, value, value)
+    }
+}
+fn leaf_key(ijk: [i32; 3]) -> [i32; 3] {
+    ijk.map(|c| c >> LEAF_LOG2DIM)
+}
+fn leaf_offset(ijk: [i32; 3]) -> usize {
+    let local = ijk.map(|c| (c & ((1 << LEAF_LOG2DIM) - 1)) as usize);
+    (local[0] << (2 * LEAF_LOG2DIM)) | (local[1] << LEAF_LOG2DIM) | local[2]
+}
+
+// The layout of NanoVDB files and grids.
+/// "NanoVDB0", which starts grids and older files
+const NANOVDB_MAGIC: u64 = 0x304244566f6e614e;
+/// "NanoVDB1", which starts newer files
+const NANOVDB_MAGIC_FILE: u64 = 0x314244566f6e614e;
+const GRID_TYPE_FLOAT: u32 = 1;
+const FILE_HEADER_SIZE: usize = 16;
+const FILE_METADATA_SIZE: usize = 176;
+const GRID_DATA_SIZE: usize = 672;
+/// the offsets in `GridData` of the double precision matrices and translation of its map
+const MAP_MATRIX: usize = 384;
+const MAP_INVERSE: usize = 456;
+const MAP_TRANSLATION: usize = 528;
+const LEAF_NODE_SIZE: usize = 96 + 4 * LEAF_SIZE;
+/// where the table of children and tiles starts in an internal node, and the size of the node
+const LOWER_TABLE: usize = 1088;
+const LOWER_NODE_SIZE: usize = LOWER_TABLE + 8 * 4096;
+const UPPER_TABLE: usize = 8256;
+const UPPER_NODE_SIZE: usize = UPPER_TABLE + 8 * 32768;
+
+fn bytes_at<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N], String> {
+    offset
+        .checked_add(N)
+        .and_then(|end| bytes.get(offset..end))
+        .map(|slice| slice.try_into().unwrap())
+        .ok_or_else(|| "unexpected end of file".to_string())
+}
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16, String> {
+    bytes_at(bytes, offset).map(u16::from_le_bytes)
+}
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    bytes_at(bytes, offset).map(u32::from_le_bytes)
+}
+fn i32_at(bytes: &[u8], offset: usize) -> Result<i32, String> {
+    bytes_at(bytes, offset).map(i32::from_le_bytes)
+}
+fn u64_at(bytes: &[u8], offset: usize) -> Result<u64, String> {
+    bytes_at(bytes, offset).map(u64::from_le_bytes)
+}
+fn f32_at(bytes: &[u8], offset: usize) -> Result<f32, String> {
+    bytes_at(bytes, offset).map(f32::from_le_bytes)
+}
+fn f64_at(bytes: &[u8], offset: usize) -> Result<f64, String> {
+    bytes_at(bytes, offset).map(f64::from_le_bytes)
+}
+fn read_coord(bytes: &[u8], offset: usize) -> Result<[i32; 3], String> {
+    Ok([
+        i32_at(bytes, offset)?,
+        i32_at(bytes, offset + 4)?,
+        i32_at(bytes, offset + 8)?,
+    ])
+}
+/// Bit `n` of a NanoVDB mask, which is stored in 64 bit words.
+fn mask_bit(bytes: &[u8], offset: usize, n: usize) -> Result<bool, String> {
+    Ok(u64_at(bytes, offset + 8 * (n >> 6))? >> (n & 63) & 1 == 1)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_trilinear() {
+        let mut grid = VoxelGrid::new().with_transform(Vec3::new(1., 0., 0.), 0.5);
+        grid.set([0, 0, 0], 1.);
+        grid.set([1, 0, 0], 3.);
+        grid.set([-9, 0, 0], 5.);
+        assert_eq!(grid.leaves.len(), 2);
+        assert!((grid.sample(&Vec3::new(1.25, 0., 0.)) - 2.).abs() < 1e-9);
+        assert!((grid.sample(&Vec3::new(1., 0.25, 0.)) - 0.5).abs() < 1e-9);
+        assert!((grid.sample(&Vec3::new(1. - 4.5, 0., 0.)) - 5.).abs() < 1e-9);
+        assert_eq!(grid.sample(&Vec3::new(10., 0., 0.)), 0.);
+        assert_eq!(grid.max_value(), 5.);
+    }
+    #[test]
+    fn test_rtgrid() {
+        let mut dense = b"rtgrid dense 2 1 2 0.1 1 2 3\n".to_vec();
+        for value in [0f32, 1., 2., 3.] {
+            dense.extend_from_slice(&value.to_le_bytes());
+        }
+        let grid = VoxelGrid::parse_rtgrid(&dense).unwrap();
+        assert_eq!(grid.get([1, 0, 1]), 3.);
+        assert!((grid.sample(&Vec3::new(1.1, 2., 3.)) - 1.).abs() < 1e-6);
+
+        let mut sparse = b"rtgrid sparse 1 1 0 0 0\n".to_vec();
+        for value in [-3i32, 4, 5] {
+            sparse.extend_from_slice(&value.to_le_bytes());
+        }
+        sparse.extend_from_slice(&0.5f32.to_le_bytes());
+        let grid = VoxelGrid::parse_rtgrid(&sparse).unwrap();
+        assert_eq!(grid.get([-3, 4, 5]), 0.5);
+        assert!(VoxelGrid::parse_rtgrid(&sparse[..sparse.len() - 1]).is_err());
+    }
+    /// A NanoVDB file with a single leaf, laid out by hand.
+    #[test]
+    fn test_nanovdb() {
+        let mut grid = vec![0u8; GRID_DATA_SIZE + 64 + LEAF_NODE_SIZE];
+        grid[..8].copy_from_slice(&NANOVDB_MAGIC.to_le_bytes());
+        let put_f64 = |grid: &mut Vec<u8>, offset: usize, value: f64| {
+            grid[offset..offset + 8].copy_from_slice(&value.to_le_bytes())
+        };
+        // Voxels of side 2, with (0, 0, 0) at (10, 0, 0).
+        for (row, value) in [(0, 2.), (4, 2.), (8, 2.)] {
+            put_f64(&mut grid, MAP_MATRIX + 8 * row, value);
+            put_f64(&mut grid, MAP_INVERSE + 8 * row, 0.5);
+        }
+        put_f64(&mut grid, MAP_TRANSLATION, 10.);
+        // The leaves start right after the tree data, and there is one.
+        let tree = GRID_DATA_SIZE;
+        grid[tree..tree + 8].copy_from_slice(&64u64.to_le_bytes());
+        grid[tree + 32..tree + 36].copy_from_slice(&1u32.to_le_bytes());
+        let leaf = tree + 64;
+        for (axis, c) in [8i32, 16, -8].iter().enumerate() {
+            grid[leaf + 4 * axis..leaf + 4 * axis + 4].copy_from_slice(&c.to_le_bytes());
+        }
+        // Voxel (9, 18, -5) is active, with the value 0.75.
+        let n = (1 << 6) | (2 << 3) | 3;
+        grid[leaf + 16 + n / 8] |= 1 << (n % 8);
+        grid[leaf + 96 + 4 * n..leaf + 100 + 4 * n].copy_from_slice(&0.75f32.to_le_bytes());
+
+        let mut file = vec![0u8; FILE_HEADER_SIZE + FILE_METADATA_SIZE];
+        file[..8].copy_from_slice(&NANOVDB_MAGIC_FILE.to_le_bytes());
+        file[12..14].copy_from_slice(&1u16.to_le_bytes());
+        let meta = FILE_HEADER_SIZE;
+        file[meta..meta + 8].copy_from_slice(&(grid.len() as u64).to_le_bytes());
+        file[meta + 32..meta + 36].copy_from_slice(&GRID_TYPE_FLOAT.to_le_bytes());
+        file.extend_from_slice(&grid);
+
+        let parsed = VoxelGrid::parse_nanovdb(&file).unwrap();
+        assert_eq!(parsed.get([9, 18, -5]), 0.75);
+        assert_eq!(parsed.leaves.len(), 1);
+        assert!((parsed.sample(&Vec3::new(28., 36., -10.)) - 0.75).abs() < 1e-9);
+        assert!(VoxelGrid::parse_nanovdb(&file[..file.len() - 4]).is_err());
+    }
+}